@@ -0,0 +1,210 @@
+//! Panic safety at the FFI boundary
+//!
+//! A panic must not unwind into the host, which would abort the app. Every
+//! exported function runs its body through `catch`, which turns a panic into
+//! the function's failure value: `PANIC_ERROR` for integer results, NULL for
+//! pointers. Panics are reported as a `panic` event (and an error log line).
+//!
+//! The report is not sent from the panic hook itself: the hook runs before
+//! unwinding, while the panicking code may still hold the log, event or
+//! diagnostics locks the report needs, and taking one again would deadlock.
+//! Inside `catch`, the hook leaves the report for `catch` to send once the
+//! body has unwound; on other threads (e.g. the async runtime's) it hands the
+//! report to a short-lived thread, which gets the locks once the panicking
+//! thread has let go of them.
+//!
+//! A panic while a global mutex is held poisons it. Each lock only guards
+//! state that is updated in one go, so the `*_or_recover` methods below take
+//! over a poisoned lock instead of failing every later call.
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::events::{self, Event};
+
+/// Returned by exported functions with an integer result when they panic
+pub const PANIC_ERROR: i32 = -99;
+
+static HOOK_ONCE: Once = Once::new();
+
+thread_local! {
+    /// `catch` calls running on this thread
+    static CATCH_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Panics on this thread waiting for `catch` to report them
+    static PENDING: RefCell<Vec<PanicReport>> = const { RefCell::new(Vec::new()) };
+    /// Set while this thread sends a report, so a panic doing so is not reported again
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Locking that recovers from poisoning
+pub trait LockExt<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Read/write locking that recovers from poisoning
+pub trait RwLockExt<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What an exported function returns if its body panicked
+pub trait PanicValue {
+    fn panic_value() -> Self;
+}
+
+impl PanicValue for () {
+    fn panic_value() -> Self {}
+}
+
+impl PanicValue for i32 {
+    fn panic_value() -> Self {
+        PANIC_ERROR
+    }
+}
+
+impl PanicValue for i64 {
+    fn panic_value() -> Self {
+        PANIC_ERROR.into()
+    }
+}
+
+impl<T> PanicValue for *mut T {
+    fn panic_value() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+impl<T> PanicValue for *const T {
+    fn panic_value() -> Self {
+        std::ptr::null()
+    }
+}
+
+/// Run the body of an exported function, turning a panic into its failure value
+pub fn catch<R: PanicValue>(body: impl FnOnce() -> R) -> R {
+    HOOK_ONCE.call_once(install_hook);
+    CATCH_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    CATCH_DEPTH.with(|depth| depth.set(depth.get() - 1));
+
+    // The body has unwound, so the locks it held are free again. This also
+    // covers panics the body caught itself.
+    for report in PENDING.with(|pending| std::mem::take(&mut *pending.borrow_mut())) {
+        report.send();
+    }
+    result.unwrap_or_else(|_| R::panic_value())
+}
+
+/// A panic's message and location
+struct PanicReport {
+    message: String,
+    location: Option<String>,
+}
+
+impl PanicReport {
+    /// Log the panic and emit it as an event
+    fn send(&self) {
+        REPORTING.with(|reporting| reporting.set(true));
+        // Whatever goes wrong while reporting must not unwind into the host
+        let _ = panic::catch_unwind(|| {
+            // Panic messages are free text and may name a destination
+            log_error!(
+                "Panic at {}: {}",
+                self.location.as_deref().unwrap_or("unknown location"),
+                safelog::sensitive(&self.message)
+            );
+            events::emit(&Event::Panic { message: &self.message, location: self.location.as_deref() });
+        });
+        REPORTING.with(|reporting| reporting.set(false));
+    }
+}
+
+/// Report panics, wherever they happen, once it is safe to take locks
+fn install_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if REPORTING.with(Cell::get) {
+            return;
+        }
+
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("(non-string panic payload)");
+        let report = PanicReport {
+            message: message.to_string(),
+            location: info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+        };
+
+        if CATCH_DEPTH.with(Cell::get) > 0 {
+            PENDING.with(|pending| pending.borrow_mut().push(report));
+        } else {
+            // If no thread can be started, the panic goes unreported
+            let _ = std::thread::Builder::new()
+                .name("arti-panic-report".to_string())
+                .spawn(move || report.send());
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// `file:line:` of a panic, as reports give it; the message itself is scrubbed
+    fn location(line: u32) -> String {
+        format!("{}:{}:", file!(), line)
+    }
+
+    #[test]
+    fn panic_holding_a_reporting_lock_is_reported_after_unwinding() {
+        let panic_line = line!() + 3;
+        let result = catch(|| -> i32 {
+            let _callback = crate::LOG_CALLBACK.lock_or_recover();
+            panic!("panic inside catch");
+        });
+
+        assert_eq!(result, PANIC_ERROR);
+        assert!(crate::diagnostics::recent_json().contains(&location(panic_line)));
+    }
+
+    #[test]
+    fn panic_outside_catch_is_reported_from_another_thread() {
+        catch(|| ());
+        let panic_line = line!() + 3;
+        let panicked = std::thread::spawn(|| {
+            let _callback = crate::LOG_CALLBACK.lock_or_recover();
+            panic!("panic outside catch");
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !crate::diagnostics::recent_json().contains(&location(panic_line)) {
+            assert!(Instant::now() < deadline, "panic was not reported");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
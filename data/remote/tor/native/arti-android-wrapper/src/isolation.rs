@@ -0,0 +1,31 @@
+//! Stream isolation for the native connection APIs
+//!
+//! Callers pick isolation with a plain integer so it can cross the FFI:
+//! `0` uses arti's default isolation, a positive value names a group whose
+//! streams may share circuits with each other but with nothing else, and a
+//! negative value gives the stream circuits of its own.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use arti_client::{IsolationToken, StreamPrefs};
+
+use crate::guard::LockExt;
+
+/// Isolation tokens of the named groups, created on first use
+static GROUPS: Mutex<BTreeMap<i64, IsolationToken>> = Mutex::new(BTreeMap::new());
+
+/// Stream preferences for an isolation selector
+pub fn stream_prefs(isolation: i64) -> StreamPrefs {
+    let mut prefs = StreamPrefs::new();
+    if isolation > 0 {
+        let token = *GROUPS
+            .lock_or_recover()
+            .entry(isolation)
+            .or_insert_with(IsolationToken::new);
+        prefs.set_isolation(token);
+    } else if isolation < 0 {
+        prefs.set_isolation(IsolationToken::new());
+    }
+    prefs
+}
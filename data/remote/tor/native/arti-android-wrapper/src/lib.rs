@@ -1,16 +1,20 @@
 use jni::JNIEnv;
 use jni::objects::{JClass, JString, JObject, GlobalRef};
-use jni::sys::{jint, jstring};
+use jni::sys::{jboolean, jint, jlong, jstring};
 use jni::JavaVM;
 
 use arti_client::TorClient;
 use arti_client::config::TorClientConfigBuilder;
 use tor_rtcompat::PreferredRuntime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-use std::sync::{Arc, Mutex, Once};
+use std::sync::{Arc, Mutex, Once, OnceLock};
 use std::path::PathBuf;
 use anyhow::Result;
 
+use crate::guard::LockExt;
+
 // ============================================================================
 // Global State
 // ============================================================================
@@ -22,11 +26,20 @@ static ARTI_CLIENT: Mutex<Option<Arc<TorClient<PreferredRuntime>>>> = Mutex::new
 static TOKIO_RUNTIME: Mutex<Option<tokio::runtime::Runtime>> = Mutex::new(None);
 
 /// Global JavaVM reference (cached on first JNI call)
-static JAVA_VM: Mutex<Option<JavaVM>> = Mutex::new(None);
+static JAVA_VM: OnceLock<JavaVM> = OnceLock::new();
 
 /// Global log callback reference
 static LOG_CALLBACK: Mutex<Option<GlobalRef>> = Mutex::new(None);
 
+/// Global leveled log callback reference
+static LOG_CALLBACK_V2: Mutex<Option<GlobalRef>> = Mutex::new(None);
+
+/// Global event callback reference (receives JSON-encoded events)
+static EVENT_CALLBACK: Mutex<Option<GlobalRef>> = Mutex::new(None);
+
+/// State directory of the initialized client (holds the auth cookie)
+static STATE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
 /// Handle to SOCKS server task (for graceful shutdown)
 static SOCKS_TASK: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
 
@@ -37,22 +50,112 @@ static INIT_ONCE: Once = Once::new();
 // Logging Integration
 // ============================================================================
 
-/// Send log message to Java callback
-fn send_log_to_java(message: String) {
-    let vm_opt = JAVA_VM.lock().unwrap();
-    let callback_opt = LOG_CALLBACK.lock().unwrap();
+/// Log severities, as passed to the leveled log callback
+const LOG_LEVEL_TRACE: jint = 0;
+const LOG_LEVEL_DEBUG: jint = 1;
+const LOG_LEVEL_INFO: jint = 2;
+const LOG_LEVEL_WARN: jint = 3;
+const LOG_LEVEL_ERROR: jint = 4;
+
+/// Send a log record to logcat and the registered Java log callbacks
+///
+/// `onLogLine` gets a single line: errors are prefixed with "ERROR: " and
+/// records from arti itself with their level and target.
+fn send_log(level: jint, target: &str, message: &str) {
+    diagnostics::record_log(log_level_name(level), target, message);
+
+    let line = flat_log_line(level, target, message);
+    android_logger::log(level, &format!("Arti: {}", line));
+
+    // Cloned out of the locks so a callback may call back into the wrapper
+    let callback = LOG_CALLBACK.lock_or_recover().clone();
+    if let Some(callback) = callback {
+        with_java_env(|env| {
+            let jline = env.new_string(&line)?;
+            env.call_method(
+                callback.as_obj(),
+                "onLogLine",
+                "(Ljava/lang/String;)V",
+                &[(&jline).into()]
+            )?;
+            Ok(())
+        });
+    }
 
-    if let (Some(vm), Some(callback)) = (vm_opt.as_ref(), callback_opt.as_ref()) {
-        if let Ok(mut env) = vm.attach_current_thread() {
-            if let Ok(jmessage) = env.new_string(&message) {
-                let _ = env.call_method(
-                    callback.as_obj(),
-                    "onLogLine",
-                    "(Ljava/lang/String;)V",
-                    &[(&jmessage).into()]
-                );
-            }
-        }
+    let callback_v2 = LOG_CALLBACK_V2.lock_or_recover().clone();
+    if let Some(callback) = callback_v2 {
+        with_java_env(|env| {
+            let jtarget = env.new_string(target)?;
+            let jmessage = env.new_string(message)?;
+            env.call_method(
+                callback.as_obj(),
+                "onLog",
+                "(IJLjava/lang/String;Ljava/lang/String;)V",
+                &[level.into(), unix_time_ms().into(), (&jtarget).into(), (&jmessage).into()]
+            )?;
+            Ok(())
+        });
+    }
+}
+
+/// Run `f` with a JNI env for the current thread
+///
+/// The thread is attached as a daemon and stays attached, so Tor runtime
+/// threads do not attach and detach for every log line; a local frame frees
+/// the references `f` creates. If `f` fails, an exception the Java side threw
+/// is cleared so it cannot break later JNI calls on this thread.
+fn with_java_env(f: impl FnOnce(&mut JNIEnv) -> jni::errors::Result<()>) {
+    let Some(vm) = JAVA_VM.get() else {
+        return;
+    };
+    let Ok(mut env) = vm.attach_current_thread_as_daemon() else {
+        return;
+    };
+    if env.with_local_frame(8, f).is_err() {
+        let _ = env.exception_clear();
+    }
+}
+
+fn flat_log_line(level: jint, target: &str, message: &str) -> String {
+    let prefix = if level == LOG_LEVEL_ERROR { "ERROR: " } else { "" };
+    if target.starts_with(module_path!()) {
+        format!("{}{}", prefix, message)
+    } else {
+        format!("{}[{}] {}: {}", prefix, log_level_name(level), target, message)
+    }
+}
+
+fn log_level_name(level: jint) -> &'static str {
+    match level {
+        LOG_LEVEL_TRACE => "TRACE",
+        LOG_LEVEL_DEBUG => "DEBUG",
+        LOG_LEVEL_INFO => "INFO",
+        LOG_LEVEL_WARN => "WARN",
+        _ => "ERROR",
+    }
+}
+
+fn unix_time_ms() -> jlong {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as jlong)
+        .unwrap_or(0)
+}
+
+/// Send JSON event to Java callback
+fn send_event(json: &str) {
+    let callback = EVENT_CALLBACK.lock_or_recover().clone();
+    if let Some(callback) = callback {
+        with_java_env(|env| {
+            let jjson = env.new_string(json)?;
+            env.call_method(
+                callback.as_obj(),
+                "onEvent",
+                "(Ljava/lang/String;)V",
+                &[(&jjson).into()]
+            )?;
+            Ok(())
+        });
     }
 }
 
@@ -60,19 +163,45 @@ fn send_log_to_java(message: String) {
 macro_rules! log_info {
     ($($arg:tt)*) => {{
         let msg = format!($($arg)*);
-        android_logger::log(&format!("Arti: {}", msg));
-        send_log_to_java(msg);
+        $crate::send_log($crate::LOG_LEVEL_INFO, module_path!(), &msg);
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        let msg = format!($($arg)*);
+        $crate::send_log($crate::LOG_LEVEL_WARN, module_path!(), &msg);
     }};
 }
 
 macro_rules! log_error {
     ($($arg:tt)*) => {{
-        let msg = format!("ERROR: {}", format!($($arg)*));
-        android_logger::log(&format!("Arti: {}", msg));
-        send_log_to_java(msg);
+        let msg = format!($($arg)*);
+        $crate::send_log($crate::LOG_LEVEL_ERROR, module_path!(), &msg);
     }};
 }
 
+mod addrmap;
+mod auth;
+mod circuits;
+mod clientauth;
+mod diagnostics;
+mod ephemeral;
+mod events;
+mod fdstream;
+mod guard;
+mod identity;
+mod isolation;
+mod metrics;
+mod onion;
+mod policy;
+mod pow;
+mod redact;
+mod resolve;
+mod streamevents;
+mod tracelog;
+mod traffic;
+
 // ============================================================================
 // JNI Functions
 // ============================================================================
@@ -83,16 +212,18 @@ pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetVersion(
     env: JNIEnv,
     _class: JClass,
 ) -> jstring {
-    // Cache JavaVM on first call
-    if JAVA_VM.lock().unwrap().is_none() {
-        if let Ok(vm) = env.get_java_vm() {
-            *JAVA_VM.lock().unwrap() = Some(vm);
+    guard::catch(|| {
+        // Cache JavaVM on first call
+        if JAVA_VM.get().is_none() {
+            if let Ok(vm) = env.get_java_vm() {
+                let _ = JAVA_VM.set(vm);
+            }
         }
-    }
 
-    let version = format!("Arti {} (custom build with rustls)", env!("CARGO_PKG_VERSION"));
-    let output = env.new_string(version).expect("Couldn't create java string!");
-    output.into_raw()
+        let version = format!("Arti {} (custom build with rustls)", env!("CARGO_PKG_VERSION"));
+        let output = env.new_string(version).expect("Couldn't create java string!");
+        output.into_raw()
+    })
 }
 
 /// Set log callback for Arti logs
@@ -102,110 +233,305 @@ pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogCallback(
     _class: JClass,
     callback: JObject,
 ) {
-    // Cache JavaVM if not already cached
-    if JAVA_VM.lock().unwrap().is_none() {
-        if let Ok(vm) = env.get_java_vm() {
-            *JAVA_VM.lock().unwrap() = Some(vm);
+    guard::catch(|| {
+        // Cache JavaVM if not already cached
+        if JAVA_VM.get().is_none() {
+            if let Ok(vm) = env.get_java_vm() {
+                let _ = JAVA_VM.set(vm);
+            }
         }
-    }
 
-    // Store global reference to callback
-    if let Ok(global_ref) = env.new_global_ref(callback) {
-        *LOG_CALLBACK.lock().unwrap() = Some(global_ref);
-        log_info!("Log callback registered");
-    }
+        // Store global reference to callback
+        if let Ok(global_ref) = env.new_global_ref(callback) {
+            *LOG_CALLBACK.lock_or_recover() = Some(global_ref);
+            log_info!("Log callback registered");
+        }
+    })
 }
 
-/// Initialize Arti runtime
+/// Set leveled log callback, receiving the same records as the log callback
+///
+/// `callback.onLog(level, timestampMs, target, message)` gets the level
+/// (0 = trace ... 4 = error), unix time in milliseconds, module target and
+/// message as separate arguments. Passing null unsets it.
 #[no_mangle]
-pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeInitialize(
-    mut env: JNIEnv,
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogCallbackV2(
+    env: JNIEnv,
     _class: JClass,
-    data_dir: JString,
-) -> jint {
-    // Cache JavaVM if not already cached
-    if JAVA_VM.lock().unwrap().is_none() {
-        if let Ok(vm) = env.get_java_vm() {
-            *JAVA_VM.lock().unwrap() = Some(vm);
+    callback: JObject,
+) {
+    guard::catch(|| {
+        // Cache JavaVM if not already cached
+        if JAVA_VM.get().is_none() {
+            if let Ok(vm) = env.get_java_vm() {
+                let _ = JAVA_VM.set(vm);
+            }
         }
-    }
 
-    let data_dir_str: String = match env.get_string(&data_dir) {
-        Ok(s) => s.into(),
-        Err(e) => {
-            log_error!("Failed to convert data_dir: {:?}", e);
-            return -1;
+        if callback.is_null() {
+            *LOG_CALLBACK_V2.lock_or_recover() = None;
+            return;
         }
-    };
 
-    log_info!("AMEx: state changed to Initialized");
-    log_info!("Initializing Arti with data directory: {}", data_dir_str);
+        // Store global reference to callback
+        if let Ok(global_ref) = env.new_global_ref(callback) {
+            *LOG_CALLBACK_V2.lock_or_recover() = Some(global_ref);
+            log_info!("Leveled log callback registered");
+        }
+    })
+}
+
+/// Set event callback for structured (JSON) events (null to unset)
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetEventCallback(
+    env: JNIEnv,
+    _class: JClass,
+    callback: JObject,
+) {
+    guard::catch(|| {
+        // Cache JavaVM if not already cached
+        if JAVA_VM.get().is_none() {
+            if let Ok(vm) = env.get_java_vm() {
+                let _ = JAVA_VM.set(vm);
+            }
+        }
+
+        if callback.is_null() {
+            *EVENT_CALLBACK.lock_or_recover() = None;
+            return;
+        }
+
+        // Store global reference to callback
+        if let Ok(global_ref) = env.new_global_ref(callback) {
+            *EVENT_CALLBACK.lock_or_recover() = Some(global_ref);
+            log_info!("Event callback registered");
+        }
+    })
+}
+
+/// Set which of arti's internal log events are logged, e.g. `warn` or `info,tor_guardmgr=debug`
+///
+/// Returns 0 on success, -1 if the directives do not parse.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogFilter(
+    mut env: JNIEnv,
+    _class: JClass,
+    directives: JString,
+) -> jint {
+    guard::catch(|| {
+        let directives: String = match env.get_string(&directives) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert directives: {:?}", e);
+                return -1;
+            }
+        };
 
-    // Initialize Tokio runtime (once)
-    INIT_ONCE.call_once(|| {
-        match tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-        {
-            Ok(rt) => {
-                log_info!("Tokio runtime created successfully");
-                *TOKIO_RUNTIME.lock().unwrap() = Some(rt);
+        match tracelog::set_filter(&directives) {
+            Ok(()) => {
+                log_info!("Log filter set to {}", directives);
+                0
             }
             Err(e) => {
-                log_error!("Failed to create Tokio runtime: {:?}", e);
+                log_error!("Invalid log filter {:?}: {}", directives, e);
+                -1
             }
         }
-    });
+    })
+}
 
-    // Check if runtime exists
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
+/// Turn safe logging on (the default) or off
+///
+/// With safe logging on, destination hosts, onion addresses and peer sockets
+/// are logged as "[scrubbed]". Returns 0 on success, -1 if it could not be changed.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetSafeLogging(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) -> jint {
+    guard::catch(|| {
+        match redact::set_safe_logging(enabled != 0) {
+            Ok(()) => {
+                log_info!("Safe logging {}", if enabled != 0 { "enabled" } else { "disabled - destinations will be logged" });
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to change safe logging: {}", e);
+                -1
+            }
         }
-    };
+    })
+}
+
+/// Recent log lines and events, oldest first, as a JSON array
+///
+/// Keeps the last 1000 records, whether or not a callback was set.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetRecentLogs(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        match env.new_string(diagnostics::recent_json()) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
 
-    // Create config with explicit Android paths
-    let data_path = PathBuf::from(data_dir_str);
-    let cache_dir = data_path.join("cache");
-    let state_dir = data_path.join("state");
+/// Also write log lines to `<data dir>/logs/arti.log`, or stop doing so
+///
+/// The file is rotated to `arti.log.1`, `arti.log.2`, ... once it reaches
+/// `maxBytes` or, if `maxAgeSeconds` > 0, gets that old; up to `maxFiles`
+/// rotated files are kept and those older than `maxAgeSeconds` deleted.
+/// `maxBytes` <= 0 turns the file off. Returns 0 on success, -1 on invalid limits.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogFile(
+    _env: JNIEnv,
+    _class: JClass,
+    max_bytes: jlong,
+    max_files: jint,
+    max_age_seconds: jlong,
+) -> jint {
+    guard::catch(|| {
+        if max_bytes <= 0 {
+            diagnostics::set_file_limits(None);
+            log_info!("Log file disabled");
+            return 0;
+        }
+        let Ok(max_files) = u32::try_from(max_files) else {
+            log_error!("Invalid max_files: {}", max_files);
+            return -1;
+        };
+
+        diagnostics::set_file_limits(Some(diagnostics::FileLimits {
+            max_bytes: max_bytes as u64,
+            max_files,
+            max_age: u64::try_from(max_age_seconds).ok().filter(|&s| s > 0).map(std::time::Duration::from_secs),
+        }));
+        log_info!("Log file enabled ({} bytes, {} rotated files)", max_bytes, max_files);
+        0
+    })
+}
 
-    // Create directories if they don't exist
-    std::fs::create_dir_all(&cache_dir).ok();
-    std::fs::create_dir_all(&state_dir).ok();
+/// Initialize Arti runtime
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeInitialize(
+    mut env: JNIEnv,
+    _class: JClass,
+    data_dir: JString,
+) -> jint {
+    guard::catch(|| {
+        // Cache JavaVM if not already cached
+        if JAVA_VM.get().is_none() {
+            if let Ok(vm) = env.get_java_vm() {
+                let _ = JAVA_VM.set(vm);
+            }
+        }
 
-    let result: Result<()> = runtime.block_on(async {
-        log_info!("Creating Arti client...");
-        log_info!("Cache dir: {:?}", cache_dir);
-        log_info!("State dir: {:?}", state_dir);
+        let data_dir_str: String = match env.get_string(&data_dir) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert data_dir: {:?}", e);
+                return -1;
+            }
+        };
+
+        log_info!("AMEx: state changed to Initialized");
+        log_info!("Initializing Arti with data directory: {}", data_dir_str);
+
+        // Initialize Tokio runtime (once)
+        INIT_ONCE.call_once(|| {
+            match tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => {
+                    log_info!("Tokio runtime created successfully");
+                    *TOKIO_RUNTIME.lock_or_recover() = Some(rt);
+                }
+                Err(e) => {
+                    log_error!("Failed to create Tokio runtime: {:?}", e);
+                }
+            }
 
-        // Create config with Android-specific directories
-        let config = TorClientConfigBuilder::from_directories(state_dir, cache_dir)
-            .build()?;
+            // Forward arti's diagnostics to logcat and the log callback, watch
+            // for proof-of-work solver runs and count circuits
+            if tracing_subscriber::registry()
+                .with(metrics::layer())
+                .with(pow::layer())
+                .with(tracelog::layer())
+                .try_init()
+                .is_err()
+            {
+                log_error!("A tracing subscriber is already installed - arti logs, proof-of-work events and circuit metrics disabled");
+            }
+        });
 
-        // Create client with Android-specific config
-        let client = TorClient::create_bootstrapped(config).await?;
+        // Check if runtime exists
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
+
+        // Create config with explicit Android paths
+        let data_path = PathBuf::from(data_dir_str);
+        let cache_dir = data_path.join("cache");
+        let state_dir = data_path.join("state");
+
+        // Create directories if they don't exist
+        std::fs::create_dir_all(&cache_dir).ok();
+        std::fs::create_dir_all(&state_dir).ok();
+        *STATE_DIR.lock_or_recover() = Some(state_dir.clone());
+        ephemeral::set_directories(data_path.join("ephemeral"), cache_dir.clone());
+        diagnostics::set_log_dir(data_path.join("logs"));
+
+        let result: Result<()> = runtime.block_on(async {
+            log_info!("Creating Arti client...");
+            log_info!("Cache dir: {:?}", cache_dir);
+            log_info!("State dir: {:?}", state_dir);
+
+            // Create config with Android-specific directories
+            let mut builder = TorClientConfigBuilder::from_directories(&state_dir, &cache_dir);
+            onion::apply_pow_max_effort(&mut builder);
+            let config = builder.build()?;
+
+            // Onion service identities and client keys live in the keystore arti itself uses
+            match tor_keymgr::ArtiNativeKeystore::from_path_and_mistrust(state_dir.join("keystore"), config.fs_mistrust()) {
+                Ok(keystore) => identity::set_keystore(keystore, state_dir.clone()),
+                Err(e) => {
+                    log_error!("Failed to open keystore: {:?}", e);
+                }
+            }
 
-        log_info!("Arti client created successfully");
+            // Create client with Android-specific config
+            let client = TorClient::create_bootstrapped(config).await?;
 
-        // Store client globally
-        *ARTI_CLIENT.lock().unwrap() = Some(Arc::new(client));
+            log_info!("Arti client created successfully");
 
-        Ok(())
-    });
+            // Store client globally
+            *ARTI_CLIENT.lock_or_recover() = Some(Arc::new(client));
+            metrics::mark_started();
 
-    match result {
-        Ok(_) => {
-            log_info!("Arti initialized successfully");
-            0
-        }
-        Err(e) => {
-            log_error!("Failed to initialize Arti: {:?}", e);
-            -3
+            Ok(())
+        });
+
+        match result {
+            Ok(_) => {
+                log_info!("Arti initialized successfully");
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to initialize Arti: {:?}", e);
+                -3
+            }
         }
-    }
+    })
 }
 
 /// Start SOCKS proxy on specified port
@@ -215,96 +541,99 @@ pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeStartSocksProxy(
     _class: JClass,
     port: jint,
 ) -> jint {
-    log_info!("AMEx: state changed to Starting");
-    log_info!("Starting SOCKS proxy on port {}", port);
-
-    // Stop any existing SOCKS server first
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting previous SOCKS server task");
-        handle.abort();
-    }
-
-    let client_guard = ARTI_CLIENT.lock().unwrap();
-    let client = match client_guard.as_ref() {
-        Some(c) => Arc::clone(c),
-        None => {
-            log_error!("Arti client not initialized - call initialize() first");
-            return -1;
-        }
-    };
-    drop(client_guard);
-
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Starting");
+        log_info!("Starting SOCKS proxy on port {}", port);
+
+        // Stop any existing SOCKS server first
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting previous SOCKS server task");
+            handle.abort();
         }
-    };
 
-    // Try to bind IMMEDIATELY to detect port conflicts before returning
-    let addr = format!("127.0.0.1:{}", port);
+        let client_guard = ARTI_CLIENT.lock_or_recover();
+        let client = match client_guard.as_ref() {
+            Some(c) => Arc::clone(c),
+            None => {
+                log_error!("Arti client not initialized - call initialize() first");
+                return -1;
+            }
+        };
+        drop(client_guard);
+
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
 
-    // Use block_on to synchronously attempt binding
-    let bind_result = runtime.block_on(async {
-        tokio::net::TcpListener::bind(&addr).await
-    });
+        // Try to bind IMMEDIATELY to detect port conflicts before returning
+        let addr = format!("127.0.0.1:{}", port);
 
-    let listener = match bind_result {
-        Ok(l) => {
-            log_info!("SOCKS proxy bound to {}", addr);
-            l
-        }
-        Err(e) => {
-            log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
-            return -3;
-        }
-    };
+        // Use block_on to synchronously attempt binding
+        let bind_result = runtime.block_on(async {
+            tokio::net::TcpListener::bind(&addr).await
+        });
 
-    // Now spawn the background task with the already-bound listener
-    let handle = runtime.spawn(async move {
-        log_info!("SOCKS proxy listening on {}", addr);
-        log_info!("Sufficiently bootstrapped; system SOCKS now functional");
-
-        // Signal bootstrap completion to bitchat-android (expected by ArtiTorManager)
-        // This sets bootstrapPercent to 100% and stops inactivity restarts
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        log_info!("We have found that guard [scrubbed] is usable.");
-
-        // Accept connections
-        loop {
-            match listener.accept().await {
-                Ok((stream, peer_addr)) => {
-                    log_info!("SOCKS connection from: {}", peer_addr);
-                    let client_clone = Arc::clone(&client);
-
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_socks_connection(stream, client_clone).await {
-                            log_error!("SOCKS connection error: {:?}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    log_error!("Failed to accept SOCKS connection: {:?}", e);
-                    break; // Exit loop on error
+        let listener = match bind_result {
+            Ok(l) => {
+                log_info!("SOCKS proxy bound to {}", addr);
+                l
+            }
+            Err(e) => {
+                log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
+                return -3;
+            }
+        };
+
+        // Now spawn the background task with the already-bound listener
+        let handle = runtime.spawn(async move {
+            log_info!("SOCKS proxy listening on {}", addr);
+            log_info!("Sufficiently bootstrapped; system SOCKS now functional");
+
+            // Signal bootstrap completion to bitchat-android (expected by ArtiTorManager)
+            // This sets bootstrapPercent to 100% and stops inactivity restarts
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            log_info!("We have found that guard [scrubbed] is usable.");
+
+            // Accept connections
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer_addr)) => {
+                        log_info!("SOCKS connection from: {}", safelog::sensitive(peer_addr));
+                        let client_clone = Arc::clone(&client);
+
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_socks_connection(stream, peer_addr, client_clone).await {
+                                log_error!("SOCKS connection error: {:?}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log_error!("Failed to accept SOCKS connection: {:?}", e);
+                        break; // Exit loop on error
+                    }
                 }
             }
-        }
 
-        log_info!("SOCKS proxy task exiting");
-    });
+            log_info!("SOCKS proxy task exiting");
+        });
 
-    // Store handle for cleanup
-    *SOCKS_TASK.lock().unwrap() = Some(handle);
+        // Store handle for cleanup
+        *SOCKS_TASK.lock_or_recover() = Some(handle);
 
-    log_info!("SOCKS proxy started on port {}", port);
-    0
+        log_info!("SOCKS proxy started on port {}", port);
+        0
+    })
 }
 
 /// Handle a single SOCKS connection
 async fn handle_socks_connection(
     mut stream: tokio::net::TcpStream,
+    peer_addr: std::net::SocketAddr,
     client: Arc<TorClient<PreferredRuntime>>,
 ) -> Result<()> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -318,8 +647,26 @@ async fn handle_socks_connection(
         return Err(anyhow::anyhow!("Invalid SOCKS handshake"));
     }
 
-    // Send "no auth required" response
-    stream.write_all(&[0x05, 0x00]).await?;
+    if auth::is_required() {
+        // Only accept username/password auth (RFC 1929) carrying the cookie
+        let nmethods = buf[1] as usize;
+        let methods = &buf[2..n.min(2 + nmethods)];
+        if !methods.contains(&0x02) {
+            stream.write_all(&[0x05, 0xFF]).await?;
+            return Err(anyhow::anyhow!("SOCKS client did not offer username/password auth"));
+        }
+        stream.write_all(&[0x05, 0x02]).await?;
+
+        let n = stream.read(&mut buf).await?;
+        if !auth::socks_password(&buf[..n]).is_some_and(auth::verify) {
+            stream.write_all(&[0x01, 0x01]).await?;
+            return Err(anyhow::anyhow!("SOCKS authentication failed"));
+        }
+        stream.write_all(&[0x01, 0x00]).await?;
+    } else {
+        // Send "no auth required" response
+        stream.write_all(&[0x05, 0x00]).await?;
+    }
 
     // Read request
     let n = stream.read(&mut buf).await?;
@@ -380,27 +727,84 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS5 CONNECT to {}:{}", target_host, target_port);
+    // Safe-socks: an IP literal means the caller resolved the name outside Tor.
+    // Checked before address mapping, since only the caller's own request can
+    // reveal such a leak; a mapping to an IP literal is the host's choice.
+    if policy::is_ip_literal(&target_host) {
+        let mode = policy::safe_socks_mode();
+        if mode != policy::SafeSocksMode::Off {
+            let rejected = mode == policy::SafeSocksMode::Reject;
+            events::emit(&events::Event::SafeSocksViolation {
+                peer: &safelog::sensitive(peer_addr).to_string(),
+                destination: &redact::destination(&target_host, target_port).to_string(),
+                rejected,
+            });
+            if rejected {
+                // Send SOCKS5 error: connection not allowed by ruleset
+                stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("Safe-socks rejected IP-literal destination from {}", safelog::sensitive(peer_addr)));
+            }
+            log_warn!(
+                "Safe-socks: {} requested IP-literal destination {}",
+                safelog::sensitive(peer_addr),
+                redact::destination(&target_host, target_port)
+            );
+        }
+    }
+
+    // Address mapping: transparently rewrite mapped destinations (e.g. onion mirrors)
+    let (target_host, target_port) = addrmap::apply(&target_host, target_port);
+
+    // Destination policy: refuse anything the loaded rule set does not allow
+    if !policy::is_allowed(&target_host, target_port) {
+        events::emit(&events::Event::DestinationRejected {
+            peer: &safelog::sensitive(peer_addr).to_string(),
+            destination: &redact::destination(&target_host, target_port).to_string(),
+        });
+        // Send SOCKS5 error: connection not allowed by ruleset
+        stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(anyhow::anyhow!("Destination {} rejected by policy", redact::destination(&target_host, target_port)));
+    }
+
+    log_info!("SOCKS5 CONNECT to {}", redact::destination(&target_host, target_port));
 
     // Establish Tor connection
+    let mut lifecycle = streamevents::opened(&target_host, target_port);
     let tor_stream = match client.connect((target_host.as_str(), target_port)).await {
         Ok(s) => s,
         Err(e) => {
             log_error!("Failed to connect through Tor: {:?}", e);
-            // Send SOCKS5 error: general failure
-            stream.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+            lifecycle.failed(&e);
+            // Onion service failures get Tor's extended reply codes (e.g. 0xF4 missing client auth)
+            let reply = clientauth::socks_reply_code(&e);
+            stream.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
             return Err(e.into());
         }
     };
 
-    log_info!("Tor connection established to {}:{}", target_host, target_port);
+    log_info!("Tor connection established to {}", redact::destination(&target_host, target_port));
 
     // Send SOCKS5 success response
     stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
 
-    // Bidirectional data forwarding
-    let (mut client_read, mut client_write) = stream.split();
-    let (mut tor_read, mut tor_write) = tor_stream.split();
+    // Register the stream for traffic accounting and circuit inspection
+    let traffic_stream = traffic::open_stream(lifecycle.id(), &target_host, target_port);
+    let _circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+
+    // Bidirectional data forwarding, counting bytes in both directions
+    let (client_read, mut client_write) = stream.split();
+    let (tor_read, mut tor_write) = tor_stream.split();
+    let mut client_read = traffic::CountingReader::new(
+        client_read,
+        traffic_stream.counters(),
+        traffic::Direction::Up,
+    );
+    let mut tor_read = traffic::CountingReader::new(
+        tor_read,
+        traffic_stream.counters(),
+        traffic::Direction::Down,
+    );
 
     let client_to_tor = async {
         tokio::io::copy(&mut client_read, &mut tor_write).await
@@ -424,7 +828,13 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS connection closed for {}:{}", target_host, target_port);
+    log_info!(
+        "SOCKS connection closed for {} ({} bytes up, {} bytes down, {} ms)",
+        redact::destination(&target_host, target_port),
+        traffic_stream.bytes_up(),
+        traffic_stream.bytes_down(),
+        traffic_stream.duration_ms()
+    );
 
     Ok(())
 }
@@ -435,59 +845,1171 @@ pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeStop(
     _env: JNIEnv,
     _class: JClass,
 ) -> jint {
-    log_info!("AMEx: state changed to Stopping");
-    log_info!("Stopping Arti...");
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Stopping");
+        log_info!("Stopping Arti...");
+
+        // Abort SOCKS proxy task (releases the port)
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting SOCKS server task");
+            handle.abort();
+        }
 
-    // Abort SOCKS proxy task (releases the port)
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting SOCKS server task");
-        handle.abort();
-    }
+        // Take down hosted onion services, dropping ephemeral keys
+        ephemeral::destroy_all();
+        onion::stop_all();
 
-    // Give the abort a moment to complete and release the port
-    if let Some(rt) = TOKIO_RUNTIME.lock().unwrap().as_ref() {
-        rt.block_on(async {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        });
-    }
+        // Give the abort a moment to complete and release the port
+        if let Some(rt) = TOKIO_RUNTIME.lock_or_recover().as_ref() {
+            rt.block_on(async {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            });
+        }
 
-    // NOTE: We do NOT clear ARTI_CLIENT here!
-    // The TorClient can be reused for multiple SOCKS proxy start/stop cycles.
-    // Only clear it if you want to force full reinitialization.
+        // NOTE: We do NOT clear ARTI_CLIENT here!
+        // The TorClient can be reused for multiple SOCKS proxy start/stop cycles.
+        // Only clear it if you want to force full reinitialization.
 
-    // Uncomment this line only if you want to force reinitialization on every start:
-    // *ARTI_CLIENT.lock().unwrap() = None;
+        // Uncomment this line only if you want to force reinitialization on every start:
+        // *ARTI_CLIENT.lock_or_recover() = None;
 
-    log_info!("AMEx: state changed to Stopped");
-    log_info!("Arti stopped successfully");
+        log_info!("AMEx: state changed to Stopped");
+        log_info!("Arti stopped successfully");
 
-    0
+        0
+    })
 }
 
 // ============================================================================
-// Android Logger (simple implementation)
+// Loopback Authentication
 // ============================================================================
 
-mod android_logger {
-    use std::ffi::CString;
+/// Enable or disable cookie authentication for the SOCKS proxy
+///
+/// When enabled, a fresh cookie is written to `<state dir>/socks_auth_cookie`
+/// and clients must send it as the SOCKS5 password.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetCookieAuth(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) -> jint {
+    guard::catch(|| {
+        let state_dir = match STATE_DIR.lock_or_recover().clone() {
+            Some(dir) => dir,
+            None => {
+                log_error!("Arti not initialized - call initialize() first");
+                return -1;
+            }
+        };
 
-    #[allow(non_camel_case_types)]
-    type c_int = i32;
+        if enabled == 0 {
+            auth::disable(&state_dir);
+            log_info!("SOCKS cookie authentication disabled");
+            return 0;
+        }
 
-    #[allow(non_camel_case_types)]
-    type c_char = i8;
+        match auth::enable(&state_dir) {
+            Ok(path) => {
+                log_info!("SOCKS cookie authentication enabled, cookie at {:?}", path);
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to write SOCKS auth cookie: {:?}", e);
+                -2
+            }
+        }
+    })
+}
 
-    extern "C" {
-        fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
-    }
+// ============================================================================
+// Destination Policy
+// ============================================================================
+
+/// Set how IP-literal SOCKS destinations are handled
+///
+/// 0 = accept, 1 = accept and report (warn only), 2 = reject and report.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetSafeSocksMode(
+    _env: JNIEnv,
+    _class: JClass,
+    mode: jint,
+) -> jint {
+    guard::catch(|| {
+        match policy::SafeSocksMode::from_raw(mode) {
+            Some(m) => {
+                policy::set_safe_socks_mode(m);
+                log_info!("Safe-socks mode set to {}", mode);
+                0
+            }
+            None => {
+                log_error!("Invalid safe-socks mode: {}", mode);
+                -1
+            }
+        }
+    })
+}
+
+/// Load (or replace) the SOCKS destination rule set from JSON
+///
+/// null or an empty string removes the rule set and allows all destinations.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetDestinationPolicy(
+    mut env: JNIEnv,
+    _class: JClass,
+    rules_json: JString,
+) -> jint {
+    guard::catch(|| {
+        let json: String = if rules_json.is_null() {
+            String::new()
+        } else {
+            match env.get_string(&rules_json) {
+                Ok(s) => s.into(),
+                Err(e) => {
+                    log_error!("Failed to convert rules_json: {:?}", e);
+                    return -1;
+                }
+            }
+        };
 
-    const ANDROID_LOG_INFO: c_int = 4;
+        if json.trim().is_empty() {
+            policy::set_rule_set(None);
+            log_info!("Destination policy cleared");
+            return 0;
+        }
 
-    pub fn log(message: &str) {
-        unsafe {
-            let tag = CString::new("ArtiNative").unwrap();
-            let text = CString::new(message).unwrap();
-            __android_log_write(ANDROID_LOG_INFO, tag.as_ptr() as *const c_char, text.as_ptr() as *const c_char);
+        match policy::RuleSet::from_json(&json) {
+            Ok(rule_set) => {
+                log_info!("Destination policy loaded ({} rules)", rule_set.len());
+                policy::set_rule_set(Some(rule_set));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid destination policy: {}", e);
+                -1
+            }
         }
-    }
+    })
+}
+
+// ============================================================================
+// Address Mapping
+// ============================================================================
+
+/// Replace the address mapping table from a JSON array of `{"from", "to"}`
+///
+/// null or an empty string clears the table.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetAddressMap(
+    mut env: JNIEnv,
+    _class: JClass,
+    map_json: JString,
+) -> jint {
+    guard::catch(|| {
+        let json: String = if map_json.is_null() {
+            String::new()
+        } else {
+            match env.get_string(&map_json) {
+                Ok(s) => s.into(),
+                Err(e) => {
+                    log_error!("Failed to convert map_json: {:?}", e);
+                    return -1;
+                }
+            }
+        };
+        let json = if json.trim().is_empty() { "[]" } else { json.as_str() };
+
+        match addrmap::load_json(json) {
+            Ok(count) => {
+                log_info!("Address map loaded ({} mappings)", count);
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address map: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Add a single mapping, or retarget an existing one
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeAddAddressMapping(
+    mut env: JNIEnv,
+    _class: JClass,
+    from: JString,
+    to: JString,
+) -> jint {
+    guard::catch(|| {
+        let (from, to): (String, String) = match (env.get_string(&from), env.get_string(&to)) {
+            (Ok(from), Ok(to)) => (from.into(), to.into()),
+            _ => {
+                log_error!("Failed to convert address mapping arguments");
+                return -1;
+            }
+        };
+
+        match addrmap::insert(&from, &to) {
+            Ok(()) => {
+                log_info!("Address mapping added: {} -> {}", safelog::sensitive(from), safelog::sensitive(to));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Remove a single mapping (0 = removed, 1 = not present)
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeRemoveAddressMapping(
+    mut env: JNIEnv,
+    _class: JClass,
+    from: JString,
+) -> jint {
+    guard::catch(|| {
+        let from: String = match env.get_string(&from) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert from: {:?}", e);
+                return -1;
+            }
+        };
+
+        match addrmap::remove(&from) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Get the address mapping table with per-mapping usage counts as JSON
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetAddressMap(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        match env.new_string(addrmap::snapshot_json()) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// File Descriptor Streams
+// ============================================================================
+
+/// Open a Tor stream and return it as one end of a Unix socketpair
+///
+/// Blocks until the stream is established. The returned descriptor belongs
+/// to the caller (e.g. via `ParcelFileDescriptor.adoptFd`); closing it closes
+/// the Tor stream.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeConnectFd(
+    mut env: JNIEnv,
+    _class: JClass,
+    host: JString,
+    port: jint,
+    isolation: jlong,
+) -> jint {
+    guard::catch(|| {
+        let host: String = match env.get_string(&host) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert host: {:?}", e);
+                return -1;
+            }
+        };
+        let Ok(port) = u16::try_from(port) else {
+            log_error!("Invalid port: {}", port);
+            return -1;
+        };
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        match fdstream::connect_fd(&runtime, client, &host, port, isolation) {
+            Ok(fd) => {
+                log_info!("Tor fd stream open to {}", redact::destination(&host, port));
+                fd
+            }
+            Err(fdstream::FdError::Socketpair(e)) => {
+                log_error!("Failed to create socketpair: {:?}", e);
+                -3
+            }
+            Err(fdstream::FdError::RuntimeThread) => {
+                log_error!("Tor fd streams cannot be opened from a Tor runtime thread");
+                -8
+            }
+            Err(fdstream::FdError::Rejected) => {
+                log_error!("Destination {} rejected by policy", redact::destination(&host, port));
+                -4
+            }
+            Err(fdstream::FdError::Tor(e)) => {
+                log_error!("Failed to connect through Tor: {:?}", e);
+                -5
+            }
+        }
+    })
+}
+
+/// Get the initialized client and a handle to the runtime it runs on
+fn client_and_runtime() -> Option<(Arc<TorClient<PreferredRuntime>>, tokio::runtime::Handle)> {
+    let client = ARTI_CLIENT.lock_or_recover().as_ref().map(Arc::clone);
+    let runtime = TOKIO_RUNTIME.lock_or_recover().as_ref().map(|rt| rt.handle().clone());
+    match (client, runtime) {
+        (Some(client), Some(runtime)) => Some((client, runtime)),
+        _ => {
+            log_error!("Arti client not initialized - call initialize() first");
+            None
+        }
+    }
+}
+
+// ============================================================================
+// DNS Resolution
+// ============================================================================
+
+/// Deliver a lookup result to `callback.onResolved(long, int, String)`
+///
+/// Status 0 comes with the result JSON, -5 with `{"error": ...}`.
+fn complete_lookup(callback: GlobalRef, id: i64, result: Result<String, String>) {
+    let (status, json) = match result {
+        Ok(json) => (0, json),
+        Err(e) => (-5, serde_json::json!({ "error": e }).to_string()),
+    };
+
+    with_java_env(|env| {
+        let jjson = env.new_string(&json)?;
+        env.call_method(
+            callback.as_obj(),
+            "onResolved",
+            "(JILjava/lang/String;)V",
+            &[id.into(), status.into(), (&jjson).into()]
+        )?;
+        Ok(())
+    });
+}
+
+/// Resolve a hostname to all of its addresses through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolve(
+    mut env: JNIEnv,
+    _class: JClass,
+    hostname: JString,
+    isolation: jlong,
+    callback: JObject,
+) -> jlong {
+    guard::catch(|| {
+        let hostname: String = match env.get_string(&hostname) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert hostname: {:?}", e);
+                return -1;
+            }
+        };
+        let Ok(callback) = env.new_global_ref(callback) else {
+            log_error!("Failed to reference resolve callback");
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        resolve::resolve(&runtime, client, hostname, isolation, move |id, result| {
+            complete_lookup(callback, id, result)
+        })
+    })
+}
+
+/// Resolve an IP address back to hostnames through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolvePtr(
+    mut env: JNIEnv,
+    _class: JClass,
+    address: JString,
+    isolation: jlong,
+    callback: JObject,
+) -> jlong {
+    guard::catch(|| {
+        let address: String = match env.get_string(&address) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert address: {:?}", e);
+                return -1;
+            }
+        };
+        let Ok(address) = address.parse::<std::net::IpAddr>() else {
+            log_error!("Invalid IP address: {}", safelog::sensitive(address));
+            return -1;
+        };
+        let Ok(callback) = env.new_global_ref(callback) else {
+            log_error!("Failed to reference resolve callback");
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        resolve::resolve_ptr(&runtime, client, address, isolation, move |id, result| {
+            complete_lookup(callback, id, result)
+        })
+    })
+}
+
+/// Cancel a running lookup; `onResolved` is not called for it
+///
+/// Returns 0 on success, -2 if the lookup already finished or never existed.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolveCancel(
+    _env: JNIEnv,
+    _class: JClass,
+    lookup_id: jlong,
+) -> jint {
+    guard::catch(|| {
+        if resolve::cancel(lookup_id) { 0 } else { -2 }
+    })
+}
+
+// ============================================================================
+// Onion Services
+// ============================================================================
+
+/// Publish the onion service `nickname`, forwarding `virtualPort` to `localTarget`
+///
+/// `localTarget` is `host:port` or `unix:/path/to/socket`. Returns the .onion
+/// address, or null on failure.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStart(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    virtual_port: jint,
+    local_target: JString,
+) -> jstring {
+    guard::catch(|| {
+        let (nickname, local_target): (String, String) = match (env.get_string(&nickname), env.get_string(&local_target)) {
+            (Ok(n), Ok(t)) => (n.into(), t.into()),
+            _ => {
+                log_error!("Failed to get onion service strings");
+                return std::ptr::null_mut();
+            }
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        if ephemeral::is_ephemeral(&nickname) {
+            log_error!("Onion service nickname {} is reserved for ephemeral services", nickname);
+            return std::ptr::null_mut();
+        }
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        let address = match onion::start(&runtime, client, &nickname, virtual_port, &local_target) {
+            Ok(address) => address,
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid onion service configuration: {}", e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::AlreadyRunning) => {
+                log_error!("Onion service {} is already running", nickname);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch onion service {}: {:?}", nickname, e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::NoAddress) => {
+                log_error!("Onion service {} has no identity key", nickname);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Onion services cannot be started from a Tor runtime thread");
+                return std::ptr::null_mut();
+            }
+        };
+
+        log_info!("Onion service {} published at {}", nickname, redact::destination(&address, virtual_port));
+        to_jstring(&env, address)
+    })
+}
+
+/// Stop a hosted onion service
+///
+/// Returns 0 on success, -2 if no service with that nickname is running, -3
+/// for an ephemeral service (use `nativeOnionEphemeralDestroy`).
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStop(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+) -> jint {
+    guard::catch(|| {
+        let nickname: String = match env.get_string(&nickname) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert nickname: {:?}", e);
+                return -1;
+            }
+        };
+
+        if ephemeral::is_ephemeral(&nickname) {
+            log_error!("Onion service {} is ephemeral - destroy it by its address instead", nickname);
+            return -3;
+        }
+
+        if onion::stop(&nickname) {
+            log_info!("Onion service {} stopped", nickname);
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+/// State of every hosted onion service as a JSON array
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStatus(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        to_jstring(&env, onion::snapshot_json())
+    })
+}
+
+/// Configure the DoS defenses of an onion service from JSON
+///
+/// Returns 0 if in effect, 1 if they apply from the service's next start, -1 on invalid input.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceSetDosParams(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    config_json: JString,
+) -> jint {
+    guard::catch(|| {
+        let (nickname, config_json): (String, String) = match (env.get_string(&nickname), env.get_string(&config_json)) {
+            (Ok(n), Ok(c)) => (n.into(), c.into()),
+            _ => {
+                log_error!("Failed to get DoS defense strings");
+                return -1;
+            }
+        };
+
+        match onion::set_dos_params(&nickname, &config_json) {
+            Ok(true) => {
+                log_info!("DoS defenses updated for onion service {}", nickname);
+                0
+            }
+            Ok(false) => {
+                log_info!("DoS defenses for onion service {} apply from its next start", nickname);
+                1
+            }
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                -1
+            }
+            Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid DoS defense configuration: {}", e);
+                -1
+            }
+            Err(_) => -1,
+        }
+    })
+}
+
+/// Cap the proof-of-work effort hosted onion services credit to a client
+///
+/// Read when a client is created, so call it before nativeInitialize. A
+/// negative `max_effort` goes back to the consensus value. Returns 0.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceSetPowMaxEffort(
+    _env: JNIEnv,
+    _class: JClass,
+    max_effort: jint,
+) -> jint {
+    guard::catch(|| {
+        if max_effort >= 0 {
+            onion::set_pow_max_effort(Some(max_effort));
+            log_info!("Onion service PoW effort capped at {}", max_effort);
+        } else {
+            onion::set_pow_max_effort(None);
+            log_info!("Onion service PoW effort cap follows the consensus");
+        }
+        0
+    })
+}
+
+// ============================================================================
+// Ephemeral Onion Services
+// ============================================================================
+
+/// Publish a throwaway onion service whose keys never touch the disk
+///
+/// `ttl_seconds` > 0 tears it down after that long. Returns the .onion
+/// address, or null on failure.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionEphemeralCreate(
+    mut env: JNIEnv,
+    _class: JClass,
+    virtual_port: jint,
+    local_target: JString,
+    ttl_seconds: jlong,
+) -> jstring {
+    guard::catch(|| {
+        let local_target: String = match env.get_string(&local_target) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert local target: {:?}", e);
+                return std::ptr::null_mut();
+            }
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        let ttl = u64::try_from(ttl_seconds)
+            .ok()
+            .filter(|&secs| secs > 0)
+            .map(std::time::Duration::from_secs);
+
+        let Some((_, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        match ephemeral::create(&runtime, virtual_port, &local_target, ttl) {
+            Ok(address) => {
+                log_info!("Ephemeral onion service published at {}", redact::destination(&address, virtual_port));
+                to_jstring(&env, address)
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid ephemeral onion service configuration: {}", e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Ephemeral onion services cannot be created from a Tor runtime thread");
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch ephemeral onion service: {:?}", e);
+                std::ptr::null_mut()
+            }
+            Err(_) => {
+                log_error!("Failed to launch ephemeral onion service");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Tear down an ephemeral onion service; returns 0, or -2 if it does not exist
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionEphemeralDestroy(
+    mut env: JNIEnv,
+    _class: JClass,
+    onion_address: JString,
+) -> jint {
+    guard::catch(|| {
+        let onion_address: String = match env.get_string(&onion_address) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert onion address: {:?}", e);
+                return -1;
+            }
+        };
+
+        if ephemeral::destroy(&onion_address) {
+            log_info!("Ephemeral onion service {} destroyed", safelog::sensitive(onion_address));
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Identity
+// ============================================================================
+
+/// Map an identity error to a status code, logging it
+fn identity_error_code(error: identity::IdentityError, nickname: &str) -> jint {
+    match error {
+        identity::IdentityError::InvalidNickname(e) => {
+            log_error!("Invalid onion service nickname {}: {}", nickname, e);
+            -1
+        }
+        identity::IdentityError::InvalidKey(e) => {
+            log_error!("Invalid onion service identity key: {}", e);
+            -1
+        }
+        identity::IdentityError::NotInitialized => {
+            log_error!("Arti client not initialized - call initialize() first");
+            -2
+        }
+        identity::IdentityError::Exists => {
+            log_error!("Onion service {} already has a different identity", nickname);
+            -3
+        }
+        identity::IdentityError::Running => {
+            log_error!("Onion service {} is running - stop it first", nickname);
+            -4
+        }
+        identity::IdentityError::NotFound => {
+            log_error!("Onion service {} has no identity key", nickname);
+            -5
+        }
+        identity::IdentityError::Decrypt => {
+            log_error!("Failed to decrypt onion service identity (wrong passphrase?)");
+            -6
+        }
+        identity::IdentityError::Keystore(e) => {
+            log_error!("Keystore error: {}", e);
+            -7
+        }
+    }
+}
+
+/// Hand a Rust string to Java, or null
+fn to_jstring(env: &JNIEnv, s: String) -> jstring {
+    match env.new_string(s) {
+        Ok(s) => s.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Import a hex-encoded ed25519 seed (32 bytes) or expanded secret key (64 bytes)
+///
+/// Returns the .onion address, or null on failure.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityImport(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    secret_hex: JString,
+    overwrite: jboolean,
+) -> jstring {
+    guard::catch(|| {
+        let (nickname, secret_hex): (String, String) = match (env.get_string(&nickname), env.get_string(&secret_hex)) {
+            (Ok(n), Ok(s)) => (n.into(), s.into()),
+            _ => {
+                log_error!("Failed to get identity strings");
+                return std::ptr::null_mut();
+            }
+        };
+        let Ok(secret) = hex::decode(secret_hex.trim()).map(zeroize::Zeroizing::new) else {
+            log_error!("Identity key is not valid hex");
+            return std::ptr::null_mut();
+        };
+
+        match identity::import(&nickname, &secret, overwrite != 0) {
+            Ok(address) => {
+                log_info!("Identity imported for onion service {}", nickname);
+                to_jstring(&env, address)
+            }
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Import an identity exported with nativeOnionIdentityExport
+///
+/// Returns the .onion address, or null on failure.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityImportEncrypted(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    exported: JString,
+    passphrase: JString,
+    overwrite: jboolean,
+) -> jstring {
+    guard::catch(|| {
+        let (nickname, exported, passphrase): (String, String, String) =
+            match (env.get_string(&nickname), env.get_string(&exported), env.get_string(&passphrase)) {
+                (Ok(n), Ok(e), Ok(p)) => (n.into(), e.into(), p.into()),
+                _ => {
+                    log_error!("Failed to get identity strings");
+                    return std::ptr::null_mut();
+                }
+            };
+
+        match identity::import_encrypted(&nickname, &exported, passphrase.as_bytes(), overwrite != 0) {
+            Ok(address) => {
+                log_info!("Identity imported for onion service {}", nickname);
+                to_jstring(&env, address)
+            }
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Export the identity of `nickname` encrypted with `passphrase`, as hex
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityExport(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    passphrase: JString,
+) -> jstring {
+    guard::catch(|| {
+        let (nickname, passphrase): (String, String) = match (env.get_string(&nickname), env.get_string(&passphrase)) {
+            (Ok(n), Ok(p)) => (n.into(), p.into()),
+            _ => {
+                log_error!("Failed to get identity strings");
+                return std::ptr::null_mut();
+            }
+        };
+
+        match identity::export_encrypted(&nickname, passphrase.as_bytes()) {
+            Ok(exported) => to_jstring(&env, exported),
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Compute the v3 .onion address for a hex-encoded 32-byte ed25519 public key
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionAddressFromPublicKey(
+    mut env: JNIEnv,
+    _class: JClass,
+    public_key_hex: JString,
+) -> jstring {
+    guard::catch(|| {
+        let public_key = env
+            .get_string(&public_key_hex)
+            .ok()
+            .and_then(|s| hex::decode(String::from(s).trim()).ok());
+        let Some(public_key) = public_key else {
+            log_error!("Public key is not valid hex");
+            return std::ptr::null_mut();
+        };
+
+        match identity::onion_address_for_public_key(&public_key) {
+            Ok(address) => to_jstring(&env, address),
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Client Authorization
+// ============================================================================
+
+/// Authorize a client, by its `descriptor:x25519:...` public key, to discover `nickname`
+///
+/// Returns 0 on success or a negative identity error code.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceAuthorizeClient(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    client_nickname: JString,
+    public_key: JString,
+) -> jint {
+    guard::catch(|| {
+        let (nickname, client_nickname, public_key): (String, String, String) =
+            match (env.get_string(&nickname), env.get_string(&client_nickname), env.get_string(&public_key)) {
+                (Ok(n), Ok(c), Ok(k)) => (n.into(), c.into(), k.into()),
+                _ => {
+                    log_error!("Failed to get client authorization strings");
+                    return -1;
+                }
+            };
+
+        match clientauth::authorize_client(&nickname, &client_nickname, &public_key) {
+            Ok(()) => {
+                log_info!("Client {} authorized for onion service {}", client_nickname, nickname);
+                0
+            }
+            Err(e) => identity_error_code(e, &nickname),
+        }
+    })
+}
+
+/// Revoke a client; returns 0 on success, -5 if it was not authorized
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceRevokeClient(
+    mut env: JNIEnv,
+    _class: JClass,
+    nickname: JString,
+    client_nickname: JString,
+) -> jint {
+    guard::catch(|| {
+        let (nickname, client_nickname): (String, String) = match (env.get_string(&nickname), env.get_string(&client_nickname)) {
+            (Ok(n), Ok(c)) => (n.into(), c.into()),
+            _ => {
+                log_error!("Failed to get client authorization strings");
+                return -1;
+            }
+        };
+
+        match clientauth::revoke_client(&nickname, &client_nickname) {
+            Ok(true) => {
+                log_info!("Client {} revoked for onion service {}", client_nickname, nickname);
+                0
+            }
+            Ok(false) => -5,
+            Err(e) => identity_error_code(e, &nickname),
+        }
+    })
+}
+
+/// Store the client key for a restricted onion service
+///
+/// Returns the matching public key for the service operator, or null on failure.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthSet(
+    mut env: JNIEnv,
+    _class: JClass,
+    onion_address: JString,
+    secret_key: JString,
+) -> jstring {
+    guard::catch(|| {
+        let (onion_address, secret_key): (String, String) = match (env.get_string(&onion_address), env.get_string(&secret_key)) {
+            (Ok(a), Ok(k)) => (a.into(), k.into()),
+            _ => {
+                log_error!("Failed to get client key strings");
+                return std::ptr::null_mut();
+            }
+        };
+
+        match clientauth::set_client_key(&onion_address, &secret_key) {
+            Ok(public_key) => match env.new_string(public_key) {
+                Ok(s) => s.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Generate and store a new client key; returns its public key, or null on failure
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthGenerate(
+    mut env: JNIEnv,
+    _class: JClass,
+    onion_address: JString,
+) -> jstring {
+    guard::catch(|| {
+        let onion_address: String = match env.get_string(&onion_address) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert onion address: {:?}", e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        match clientauth::generate_client_key(&onion_address) {
+            Ok(public_key) => match env.new_string(public_key) {
+                Ok(s) => s.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Forget the client key for an onion service; returns 0 on success, -5 if none was stored
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthRemove(
+    mut env: JNIEnv,
+    _class: JClass,
+    onion_address: JString,
+) -> jint {
+    guard::catch(|| {
+        let onion_address: String = match env.get_string(&onion_address) {
+            Ok(s) => s.into(),
+            Err(e) => {
+                log_error!("Failed to convert onion address: {:?}", e);
+                return -1;
+            }
+        };
+
+        match clientauth::remove_client_key(&onion_address) {
+            Ok(true) => 0,
+            Ok(false) => -5,
+            Err(e) => identity_error_code(e, ""),
+        }
+    })
+}
+
+// ============================================================================
+// Traffic Accounting
+// ============================================================================
+
+/// Get a JSON snapshot of per-stream and aggregate traffic counters
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetTrafficSnapshot(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        match env.new_string(traffic::snapshot_json()) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Allow or forbid recording stream destinations in traffic snapshots
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetTrafficRecordDestinations(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    guard::catch(|| {
+        traffic::set_record_destinations(enabled != 0);
+    })
+}
+
+/// Turn per-stream lifecycle events on or off (off by default)
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetStreamEvents(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    guard::catch(|| {
+        streamevents::set_enabled(enabled != 0);
+    })
+}
+
+// ============================================================================
+// Circuits
+// ============================================================================
+
+/// Get the circuits behind active SOCKS and fd streams as a JSON array
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetCircuits(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match env.new_string(circuits::list_json(client.as_deref())) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Get the circuit behind a stream, by its id in the traffic snapshot
+///
+/// Returns null if the stream is not active.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetStreamCircuit(
+    env: JNIEnv,
+    _class: JClass,
+    stream_id: jlong,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        let Some(json) = circuits::stream_json(client.as_deref(), stream_id as u64) else {
+            return std::ptr::null_mut();
+        };
+        match env.new_string(json) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Get a JSON snapshot of the Tor subsystem's metrics
+///
+/// Bootstrap state, consensus validity window, usable relays, circuit and
+/// stream counters, bytes transferred and uptime.
+#[no_mangle]
+pub extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetMetrics(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match env.new_string(metrics::snapshot_json(client.as_deref())) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// Android Logger (simple implementation)
+// ============================================================================
+
+mod android_logger {
+    #[allow(non_camel_case_types)]
+    type c_int = i32;
+
+    #[cfg(target_os = "android")]
+    #[allow(non_camel_case_types)]
+    type c_char = i8;
+
+    #[cfg(target_os = "android")]
+    extern "C" {
+        fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
+    }
+
+    /// ANDROID_LOG_VERBOSE; DEBUG, INFO, WARN and ERROR follow in order
+    #[cfg(target_os = "android")]
+    const ANDROID_LOG_VERBOSE: c_int = 2;
+
+    /// Write to logcat at the priority matching a `LOG_LEVEL_*`
+    #[cfg(target_os = "android")]
+    pub fn log(level: c_int, message: &str) {
+        use std::ffi::CString;
+
+        unsafe {
+            // An interior NUL would make the line unrepresentable
+            let text = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+            __android_log_write(ANDROID_LOG_VERBOSE + level, c"ArtiNative".as_ptr() as *const c_char, text.as_ptr() as *const c_char);
+        }
+    }
+
+    /// liblog only exists on Android; host builds (unit tests) log nowhere
+    #[cfg(not(target_os = "android"))]
+    pub fn log(_level: c_int, _message: &str) {}
 }
@@ -0,0 +1,31 @@
+//! Safe logging
+//!
+//! Destination hosts, onion addresses and peer sockets in log lines are
+//! wrapped in `safelog::Sensitive`, so they print as `[scrubbed]` like arti's
+//! own sensitive values. Safe logging is on by default; turning it off (for
+//! debugging) reveals them, in the wrapper's lines and arti's alike.
+
+use std::sync::Mutex;
+
+use safelog::Sensitive;
+
+use crate::guard::LockExt;
+
+/// Held for as long as safe logging is off
+static UNSAFE_LOGGING: Mutex<Option<safelog::Guard>> = Mutex::new(None);
+
+/// Turn safe logging on or off
+pub fn set_safe_logging(enabled: bool) -> Result<(), safelog::Error> {
+    let mut guard = UNSAFE_LOGGING.lock_or_recover();
+    if enabled {
+        *guard = None;
+    } else if guard.is_none() {
+        *guard = Some(safelog::disable_safe_logging()?);
+    }
+    Ok(())
+}
+
+/// A `host:port` destination, for log lines
+pub fn destination(host: &str, port: u16) -> Sensitive<String> {
+    safelog::sensitive(format!("{}:{}", host, port))
+}
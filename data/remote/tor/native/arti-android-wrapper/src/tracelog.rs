@@ -0,0 +1,120 @@
+//! Forwarding of arti's own diagnostics to the host log callbacks
+//!
+//! arti logs through `tracing`. This layer hands its events, with their level,
+//! target and `message key=value ...` text, to the same log callbacks as the
+//! wrapper's own messages. Which events get through is decided by a filter
+//! in `tracing_subscriber::filter::Targets` syntax (e.g.
+//! `info,tor_guardmgr=debug`) that can be swapped at any time.
+
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::Interest;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::filter::{Filtered, Targets};
+use tracing_subscriber::layer::{Context, Filter, Layer};
+
+use crate::guard::RwLockExt;
+
+/// Current filter; `None` lets everything at INFO and above through
+static FILTER: RwLock<Option<Targets>> = RwLock::new(None);
+
+/// Layer forwarding arti's events to the log callback
+pub struct ForwardLayer;
+
+/// Filter reading `FILTER`; `set_filter` rebuilds tracing's callsite cache,
+/// so changes apply immediately
+pub struct DynamicFilter;
+
+/// `ForwardLayer` behind the runtime-settable filter
+pub fn layer<S>() -> Filtered<ForwardLayer, DynamicFilter, S>
+where
+    S: Subscriber,
+{
+    ForwardLayer.with_filter(DynamicFilter)
+}
+
+/// Replace the filter, e.g. `warn` or `info,tor_dirmgr=debug,tor_guardmgr=trace`
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let targets = Targets::from_str(directives).map_err(|e| e.to_string())?;
+    *FILTER.write_or_recover() = Some(targets);
+    // Callsites cache whether they are enabled; make them ask again
+    tracing::callsite::rebuild_interest_cache();
+    Ok(())
+}
+
+impl DynamicFilter {
+    fn is_enabled(metadata: &Metadata<'_>) -> bool {
+        match FILTER.read_or_recover().as_ref() {
+            Some(targets) => targets.would_enable(metadata.target(), metadata.level()),
+            None => *metadata.level() <= Level::INFO,
+        }
+    }
+}
+
+impl<S> Filter<S> for DynamicFilter {
+    fn enabled(&self, metadata: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        Self::is_enabled(metadata)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // Disabled callsites are skipped without taking the lock
+        if Self::is_enabled(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        match FILTER.read_or_recover().as_ref() {
+            Some(targets) => <Targets as Filter<S>>::max_level_hint(targets),
+            None => Some(LevelFilter::INFO),
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ForwardLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+
+        let level = match *metadata.level() {
+            Level::TRACE => crate::LOG_LEVEL_TRACE,
+            Level::DEBUG => crate::LOG_LEVEL_DEBUG,
+            Level::INFO => crate::LOG_LEVEL_INFO,
+            Level::WARN => crate::LOG_LEVEL_WARN,
+            Level::ERROR => crate::LOG_LEVEL_ERROR,
+        };
+        crate::send_log(level, metadata.target(), &format!("{}{}", visitor.message, visitor.fields));
+    }
+}
+
+/// Collects the message and any other fields of an event
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
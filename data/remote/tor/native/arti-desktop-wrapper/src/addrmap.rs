@@ -0,0 +1,250 @@
+//! Address mapping table (MapAddress-style rewrites)
+//!
+//! Lets the host redirect connections to a clearnet name (SOCKS CONNECT and
+//! fd streams) to another target, typically an onion mirror:
+//! `relay.example.com:443` can be sent to `xyz.onion:443` without the
+//! application knowing. A mapping without a port applies to every port and
+//! keeps the requested one. IPv6 literals are written `[::1]:80` with a port
+//! and may be bare without one.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::guard::RwLockExt;
+use crate::redact;
+
+/// Source of a mapping: host plus optional port
+type MapKey = (String, Option<u16>);
+
+/// Rewrite target and its usage counter
+struct Mapping {
+    to_host: String,
+    to_port: Option<u16>,
+    uses: AtomicU64,
+}
+
+/// Active mappings, keyed by normalized source
+static ADDRESS_MAP: RwLock<BTreeMap<MapKey, Arc<Mapping>>> = RwLock::new(BTreeMap::new());
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingEntry {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize)]
+struct MappingSnapshot {
+    from: String,
+    to: String,
+    uses: u64,
+}
+
+/// Replace the whole table from a JSON array of `{"from": ..., "to": ...}`
+///
+/// Usage counts are kept for entries whose source and target are unchanged.
+pub fn load_json(json: &str) -> Result<usize, String> {
+    let entries: Vec<MappingEntry> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    let mut parsed = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        parsed.push((parse_endpoint(&entry.from)?, parse_endpoint(&entry.to)?));
+    }
+
+    let mut map = ADDRESS_MAP.write_or_recover();
+    let mut new_map = BTreeMap::new();
+    for (from, (to_host, to_port)) in parsed {
+        let previous_uses = map
+            .get(&from)
+            .filter(|m| m.to_host == to_host && m.to_port == to_port)
+            .map(|m| m.uses.load(Ordering::Relaxed))
+            .unwrap_or(0);
+        new_map.insert(from, Arc::new(Mapping {
+            to_host,
+            to_port,
+            uses: AtomicU64::new(previous_uses),
+        }));
+    }
+    *map = new_map;
+
+    Ok(map.len())
+}
+
+/// Add a mapping, or change the target of an existing one
+pub fn insert(from: &str, to: &str) -> Result<(), String> {
+    let from = parse_endpoint(from)?;
+    let (to_host, to_port) = parse_endpoint(to)?;
+
+    ADDRESS_MAP.write_or_recover().insert(from, Arc::new(Mapping {
+        to_host,
+        to_port,
+        uses: AtomicU64::new(0),
+    }));
+    Ok(())
+}
+
+/// Remove a mapping; returns whether it existed
+pub fn remove(from: &str) -> Result<bool, String> {
+    let from = parse_endpoint(from)?;
+    Ok(ADDRESS_MAP.write_or_recover().remove(&from).is_some())
+}
+
+/// Rewrite `host:port` if a mapping applies, counting the use
+///
+/// An exact `host:port` mapping wins over a host-only one.
+pub fn rewrite(host: &str, port: u16) -> Option<(String, u16)> {
+    let host = normalize_host(host);
+    let map = ADDRESS_MAP.read_or_recover();
+    let mapping = map
+        .get(&(host.clone(), Some(port)))
+        .or_else(|| map.get(&(host, None)))?;
+
+    mapping.uses.fetch_add(1, Ordering::Relaxed);
+    Some((mapping.to_host.clone(), mapping.to_port.unwrap_or(port)))
+}
+
+/// `host:port` with any mapping applied, as every connect path does before
+/// the destination policy check
+pub fn apply(host: &str, port: u16) -> (String, u16) {
+    match rewrite(host, port) {
+        Some((to_host, to_port)) => {
+            log_info!(
+                "Address mapping: {} -> {}",
+                redact::destination(host, port),
+                redact::destination(&to_host, to_port)
+            );
+            (to_host, to_port)
+        }
+        None => (host.to_string(), port),
+    }
+}
+
+/// Serialize the table with per-mapping usage counts as JSON
+pub fn snapshot_json() -> String {
+    let snapshot: Vec<MappingSnapshot> = ADDRESS_MAP
+        .read_or_recover()
+        .iter()
+        .map(|((from_host, from_port), m)| MappingSnapshot {
+            from: format_endpoint(from_host, *from_port),
+            to: format_endpoint(&m.to_host, m.to_port),
+            uses: m.uses.load(Ordering::Relaxed),
+        })
+        .collect();
+
+    serde_json::to_string(&snapshot).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Parse `host`, `host:port`, a bare IPv6 literal or `[v6]:port`
+fn parse_endpoint(endpoint: &str) -> Result<MapKey, String> {
+    let endpoint = endpoint.trim();
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| format!("Invalid port in address mapping: {}", endpoint))
+    };
+
+    let (host, port) = if let Some(rest) = endpoint.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Unterminated IPv6 literal in address mapping: {}", endpoint))?;
+        if host.parse::<Ipv6Addr>().is_err() {
+            return Err(format!("Invalid IPv6 literal in address mapping: {}", endpoint));
+        }
+        let port = match rest {
+            "" => None,
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Some(parse_port(port)?),
+                None => return Err(format!("Invalid port in address mapping: {}", endpoint)),
+            },
+        };
+        (host, port)
+    } else if endpoint.parse::<Ipv6Addr>().is_ok() {
+        (endpoint, None)
+    } else {
+        match endpoint.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(parse_port(port)?)),
+            Some(_) => return Err(format!("Invalid address mapping (IPv6 needs brackets): {}", endpoint)),
+            None => (endpoint, None),
+        }
+    };
+
+    let host = normalize_host(host);
+    if host.is_empty() {
+        return Err(format!("Invalid host in address mapping: {}", endpoint));
+    }
+    Ok((host, port))
+}
+
+fn format_endpoint(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if host.contains(':') => format!("[{}]:{}", host, port),
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Lowercase and drop a trailing root dot; IP literals get their canonical form
+///
+/// SOCKS IPv6 destinations arrive fully expanded, so `::1` must match
+/// `0000:0000:0000:0000:0000:0000:0000:0001`.
+fn normalize_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => host.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(host: &str, port: Option<u16>) -> MapKey {
+        (host.to_string(), port)
+    }
+
+    #[test]
+    fn parses_hosts_with_and_without_port() {
+        assert_eq!(parse_endpoint("relay.example.com:443"), Ok(key("relay.example.com", Some(443))));
+        assert_eq!(parse_endpoint(" Relay.Example.COM. "), Ok(key("relay.example.com", None)));
+        assert_eq!(parse_endpoint("127.0.0.1:80"), Ok(key("127.0.0.1", Some(80))));
+    }
+
+    #[test]
+    fn parses_ipv6_literals() {
+        assert_eq!(parse_endpoint("[::1]:443"), Ok(key("::1", Some(443))));
+        assert_eq!(parse_endpoint("[::1]"), Ok(key("::1", None)));
+        assert_eq!(parse_endpoint("::1"), Ok(key("::1", None)));
+        assert_eq!(parse_endpoint("0000:0000:0000:0000:0000:0000:0000:0001"), Ok(key("::1", None)));
+        assert_eq!(format_endpoint("::1", Some(443)), "[::1]:443");
+    }
+
+    #[test]
+    fn rejects_invalid_ports_and_hosts() {
+        for endpoint in [
+            "example.com:",
+            "example.com:https",
+            "example.com:-1",
+            "example.com:65536",
+            "[::1]:",
+            "[::1]443",
+            "[::1]:99999",
+            "[::1",
+            "[example.com]:80",
+            "fe80::1:zz",
+            ":80",
+            "",
+        ] {
+            assert!(parse_endpoint(endpoint).is_err(), "{} was accepted", endpoint);
+        }
+    }
+
+    #[test]
+    fn normalizes_hosts_like_socks_destinations() {
+        assert_eq!(normalize_host("0000:0000:0000:0000:0000:0000:0000:0001"), "::1");
+        assert_eq!(normalize_host("2001:DB8::1"), "2001:db8::1");
+        assert_eq!(normalize_host("Example.ONION."), "example.onion");
+    }
+}
@@ -0,0 +1,158 @@
+//! Circuit inspection
+//!
+//! Shows which path the app's traffic takes, hop by hop, for a
+//! Tor-Browser-style circuit display. arti has no public list of its
+//! circuits, so they are found through the streams using them: every SOCKS
+//! and fd stream is tracked under its traffic stream id, and the circuits
+//! listed are the live ones behind those streams. Circuits without a stream
+//! of ours (directory fetches, preemptive circuits) do not show up.
+//!
+//! Nicknames and country codes come from the consensus; relays missing from
+//! it (e.g. bridges) get their country from arti's built-in GeoIP database.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use arti_client::{DataStream, TorClient};
+use serde::Serialize;
+use tor_geoip::{GeoipDb, HasCountryCode};
+use tor_linkspec::ChanTarget;
+use tor_netdir::{NetDir, Timeliness};
+use tor_proto::client::circuit::Path;
+use tor_proto::client::stream::{ClientDataStreamCtrl, ClientStreamCtrl};
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+
+/// Control handles of the tracked streams, keyed by traffic stream id
+static STREAMS: Mutex<BTreeMap<u64, Arc<ClientDataStreamCtrl>>> = Mutex::new(BTreeMap::new());
+
+/// Tracking of a stream's circuit; stops when dropped
+pub struct TrackedStream(u64);
+
+impl Drop for TrackedStream {
+    fn drop(&mut self) {
+        STREAMS.lock_or_recover().remove(&self.0);
+    }
+}
+
+/// Track the circuit of `stream`, registered for traffic as `stream_id`
+pub fn track(stream_id: u64, stream: &DataStream) -> TrackedStream {
+    if let Some(ctrl) = stream.client_stream_ctrl() {
+        STREAMS.lock_or_recover().insert(stream_id, Arc::clone(ctrl));
+    }
+    TrackedStream(stream_id)
+}
+
+/// Id of the circuit carrying `stream`, as in `list_json`
+pub fn circuit_id(stream: &DataStream) -> Option<String> {
+    let tunnel = stream.client_stream_ctrl()?.tunnel()?;
+    Some(tunnel.unique_id().display_chan_circ().to_string())
+}
+
+#[derive(Serialize)]
+struct CircuitInfo {
+    id: String,
+    stream_ids: Vec<u64>,
+    /// One path per leg; more than one only for conflux tunnels
+    paths: Vec<Vec<HopInfo>>,
+}
+
+#[derive(Serialize)]
+struct HopInfo {
+    role: &'static str,
+    nickname: Option<String>,
+    fingerprint: Option<String>,
+    country_code: Option<String>,
+}
+
+/// The circuits behind the tracked streams as a JSON array
+pub fn list_json(client: Option<&TorClient<PreferredRuntime>>) -> String {
+    serde_json::to_string(&circuits(client, None)).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// The circuit behind stream `stream_id` as JSON, if it is still open
+pub fn stream_json(client: Option<&TorClient<PreferredRuntime>>, stream_id: u64) -> Option<String> {
+    let circuit = circuits(client, Some(stream_id)).into_iter().next()?;
+    serde_json::to_string(&circuit).ok()
+}
+
+fn circuits(client: Option<&TorClient<PreferredRuntime>>, only_stream: Option<u64>) -> Vec<CircuitInfo> {
+    let tunnels: Vec<_> = STREAMS
+        .lock_or_recover()
+        .iter()
+        .filter(|(id, _)| only_stream.is_none_or(|only| only == **id))
+        .filter_map(|(id, ctrl)| Some((*id, ctrl.tunnel()?)))
+        .collect();
+    let netdir = client.and_then(|client| client.dirmgr().netdir(Timeliness::Unchecked).ok());
+
+    let mut circuits: Vec<CircuitInfo> = Vec::new();
+    for (stream_id, tunnel) in tunnels {
+        if tunnel.is_closed() {
+            continue;
+        }
+        let id = tunnel.unique_id().display_chan_circ().to_string();
+        match circuits.iter_mut().find(|circuit| circuit.id == id) {
+            Some(circuit) => circuit.stream_ids.push(stream_id),
+            None => circuits.push(CircuitInfo {
+                id,
+                stream_ids: vec![stream_id],
+                paths: tunnel.all_paths().iter().map(|path| hops(path, netdir.as_deref())).collect(),
+            }),
+        }
+    }
+    circuits
+}
+
+/// Hops of `path` with their roles
+///
+/// Onion service circuits end in a virtual hop standing for the service;
+/// the relay before it is the rendezvous point rather than an exit.
+fn hops(path: &Path, netdir: Option<&NetDir>) -> Vec<HopInfo> {
+    let hops = path.hops();
+    let onion = hops.iter().any(|hop| hop.as_chan_target().is_none());
+    let last_relay = hops.iter().rposition(|hop| hop.as_chan_target().is_some());
+
+    hops.iter()
+        .enumerate()
+        .map(|(i, hop)| match hop.as_chan_target() {
+            Some(target) => {
+                let role = if i == 0 {
+                    "guard"
+                } else if Some(i) == last_relay {
+                    if onion { "rendezvous" } else { "exit" }
+                } else {
+                    "middle"
+                };
+                relay_hop(role, target, netdir)
+            }
+            None => HopInfo {
+                role: "onion_service",
+                nickname: None,
+                fingerprint: None,
+                country_code: None,
+            },
+        })
+        .collect()
+}
+
+/// The embedded GeoIP database, for relays missing from the consensus
+static GEOIP: OnceLock<Arc<GeoipDb>> = OnceLock::new();
+
+fn relay_hop<T: ChanTarget>(role: &'static str, target: &T, netdir: Option<&NetDir>) -> HopInfo {
+    let relay = netdir.and_then(|netdir| netdir.by_ids(target));
+    let country_code = match &relay {
+        Some(relay) => relay.country_code(),
+        None => GEOIP
+            .get_or_init(GeoipDb::new_embedded)
+            .lookup_country_code_multi(target.addrs().map(|addr| addr.ip()))
+            .cloned(),
+    };
+
+    HopInfo {
+        role,
+        nickname: relay.as_ref().map(|relay| relay.rs().nickname().to_string()),
+        fingerprint: target.rsa_identity().map(|id| hex::encode_upper(id.as_bytes())),
+        country_code: country_code.map(|cc| cc.get().to_string()),
+    }
+}
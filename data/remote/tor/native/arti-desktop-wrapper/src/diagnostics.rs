@@ -0,0 +1,242 @@
+//! Diagnostics for bug reports
+//!
+//! The most recent log lines and events are kept in a bounded in-memory ring
+//! buffer the host can fetch as JSON at any time. Optionally, log lines are
+//! also written to `<data dir>/logs/arti.log`, rotated by size and age, so a
+//! "share diagnostics" action has something to attach on devices without a
+//! console. Only log lines go to the file. Both are redacted by safe
+//! logging. Events keep their error and panic messages for the event
+//! callback, but the copies kept here have them scrubbed, since such free
+//! text can name the destination.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::guard::LockExt;
+
+/// Records kept in memory; older ones are dropped first
+const RING_CAPACITY: usize = 1000;
+
+/// Name of the current log file; rotated ones get `.1`, `.2`, ... appended
+const LOG_FILE_NAME: &str = "arti.log";
+
+/// Event fields holding destinations, peer sockets or free text that may contain them
+const SENSITIVE_EVENT_FIELDS: &[&str] = &["destination", "host", "peer", "error", "message"];
+
+/// A log line or event, as returned by `recent_json`
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Record {
+    Log {
+        timestamp_ms: u64,
+        level: &'static str,
+        target: String,
+        message: String,
+    },
+    Event {
+        #[serde(flatten)]
+        event: serde_json::Value,
+    },
+}
+
+static RING: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+/// Size and age limits of the log file
+#[derive(Clone, Copy)]
+pub struct FileLimits {
+    /// Rotate once the current file reaches this size
+    pub max_bytes: u64,
+    /// Rotated files to keep besides the current one
+    pub max_files: u32,
+    /// Rotate at least this often, and delete rotated files older than this
+    pub max_age: Option<Duration>,
+}
+
+struct LogFile {
+    dir: Option<PathBuf>,
+    limits: Option<FileLimits>,
+    file: Option<OpenFile>,
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+    opened: SystemTime,
+}
+
+static LOG_FILE: Mutex<LogFile> = Mutex::new(LogFile { dir: None, limits: None, file: None });
+
+/// Remember a log line and append it to the log file, if enabled
+pub fn record_log(level: &'static str, target: &str, message: &str) {
+    let timestamp_ms = now_ms();
+    let mut log_file = LOG_FILE.lock_or_recover();
+    if log_file.limits.is_some() {
+        log_file.write(&format!("{} {:<5} {}: {}\n", format_utc(timestamp_ms), level, target, message));
+    }
+    drop(log_file);
+
+    push(Record::Log { timestamp_ms, level, target: target.to_string(), message: message.to_string() });
+}
+
+/// Remember an event (its JSON object, `timestamp_ms` included)
+///
+/// Destinations, peers and error messages are scrubbed unless safe logging is off.
+pub fn record_event(mut event: serde_json::Value) {
+    if let Some(fields) = event.as_object_mut() {
+        for (name, value) in fields.iter_mut() {
+            if !SENSITIVE_EVENT_FIELDS.contains(&name.as_str()) {
+                continue;
+            }
+            if let Some(text) = value.as_str() {
+                *value = safelog::sensitive(text).to_string().into();
+            }
+        }
+    }
+    push(Record::Event { event });
+}
+
+/// The ring buffer as a JSON array, oldest first
+///
+/// Logs: `{"kind": "log", "timestamp_ms", "level", "target", "message"}`;
+/// events: `{"kind": "event", "type", "timestamp_ms", ...}`.
+pub fn recent_json() -> String {
+    let ring = RING.lock_or_recover();
+    serde_json::to_string(&*ring).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Write the log file into `dir` (called by `arti_initialize`)
+pub fn set_log_dir(dir: PathBuf) {
+    let mut log_file = LOG_FILE.lock_or_recover();
+    log_file.file = None;
+    log_file.dir = Some(dir);
+}
+
+/// Enable the log file with `limits`, or disable it with `None`
+///
+/// Existing files are kept either way; rotated files beyond the new limits
+/// are deleted the next time the file rotates.
+pub fn set_file_limits(limits: Option<FileLimits>) {
+    let mut log_file = LOG_FILE.lock_or_recover();
+    log_file.limits = limits;
+    log_file.file = None;
+}
+
+fn push(record: Record) {
+    let mut ring = RING.lock_or_recover();
+    if ring.len() == RING_CAPACITY {
+        ring.pop_front();
+    }
+    ring.push_back(record);
+}
+
+impl LogFile {
+    fn write(&mut self, line: &str) {
+        let (Some(dir), Some(limits)) = (self.dir.clone(), self.limits) else {
+            return;
+        };
+
+        let due = self.file.as_ref().is_some_and(|open| {
+            open.size >= limits.max_bytes
+                || limits.max_age.is_some_and(|age| open.opened.elapsed().unwrap_or_default() >= age)
+        });
+        if due {
+            self.file = None;
+            rotate(&dir, &limits);
+        }
+
+        if self.file.is_none() {
+            self.file = open(&dir);
+        }
+        // Write errors are not logged: that would end up right back here
+        if let Some(open) = self.file.as_mut() {
+            match open.file.write_all(line.as_bytes()) {
+                Ok(()) => open.size += line.len() as u64,
+                Err(_) => self.file = None,
+            }
+        }
+    }
+}
+
+fn open(dir: &Path) -> Option<OpenFile> {
+    fs::create_dir_all(dir).ok()?;
+    let path = dir.join(LOG_FILE_NAME);
+    let file = OpenOptions::new().create(true).append(true).open(&path).ok()?;
+    let metadata = file.metadata().ok()?;
+    Some(OpenFile {
+        file,
+        size: metadata.len(),
+        // Appending to an existing file keeps its age
+        opened: metadata.created().or_else(|_| metadata.modified()).unwrap_or_else(|_| SystemTime::now()),
+    })
+}
+
+/// Shift `arti.log` to `arti.log.1` and so on, dropping files over the limits
+fn rotate(dir: &Path, limits: &FileLimits) {
+    let path = |n: u32| match n {
+        0 => dir.join(LOG_FILE_NAME),
+        n => dir.join(format!("{}.{}", LOG_FILE_NAME, n)),
+    };
+
+    // Anything past the count limit (with none, the current file itself),
+    // including leftovers from a higher limit
+    let mut n = limits.max_files;
+    while path(n).exists() {
+        let _ = fs::remove_file(path(n));
+        n += 1;
+    }
+    for n in (0..limits.max_files).rev() {
+        let _ = fs::rename(path(n), path(n + 1));
+    }
+
+    if let Some(max_age) = limits.max_age {
+        for n in 1..=limits.max_files {
+            let expired = fs::metadata(path(n))
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified.elapsed().unwrap_or_default() >= max_age);
+            if expired {
+                let _ = fs::remove_file(path(n));
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `YYYY-MM-DD HH:MM:SS.mmm` in UTC
+fn format_utc(timestamp_ms: u64) -> String {
+    let secs = timestamp_ms / 1000;
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        timestamp_ms % 1000
+    )
+}
@@ -0,0 +1,261 @@
+//! Ephemeral onion services
+//!
+//! Short-lived services, e.g. one per conversation, whose keys must never
+//! touch the disk. arti cannot switch the keystore of a running client, so
+//! these services run on a second client whose keystore is in memory only.
+//! It shares the directory cache of the main client and keeps its
+//! (key-free) state in a scratch directory outside the state dir. It is
+//! bootstrapped for the first ephemeral service and dropped, keys and all,
+//! once the last one is torn down. A create in progress holds a reservation
+//! that keeps the client alive until its service is registered.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arti_client::config::TorClientConfigBuilder;
+use arti_client::TorClient;
+use tor_config::ExplicitOrAuto;
+use tor_keymgr::config::ArtiKeystoreKind;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+use crate::onion::{self, OnionError};
+
+/// Prefix of the nicknames given to ephemeral services
+const NICKNAME_PREFIX: &str = "ephemeral-";
+
+/// An ephemeral service and its expiry timer
+struct EphemeralService {
+    nickname: String,
+    expiry: Option<tokio::task::AbortHandle>,
+}
+
+#[derive(Default)]
+struct EphemeralState {
+    /// Scratch state dir and shared cache dir, set by `arti_initialize`
+    dirs: Option<(PathBuf, PathBuf)>,
+    client: Option<Arc<TorClient<PreferredRuntime>>>,
+    /// Keyed by .onion address
+    services: BTreeMap<String, EphemeralService>,
+    /// Creates in progress, see `Reservation`
+    pending: usize,
+}
+
+static EPHEMERAL: Mutex<Option<EphemeralState>> = Mutex::new(None);
+
+/// Held while bootstrapping, so only one client is ever built in the scratch dir
+static BOOTSTRAP: Mutex<()> = Mutex::new(());
+
+/// Use `scratch_dir` for the ephemeral client's state and share `cache_dir`
+///
+/// Anything left in `scratch_dir` by a previous run is removed.
+pub fn set_directories(scratch_dir: PathBuf, cache_dir: PathBuf) {
+    if let Err(e) = remove_scratch_dir(&scratch_dir) {
+        log_error!("Failed to remove {:?}: {}", scratch_dir, e);
+    }
+    let mut state = EPHEMERAL.lock_or_recover();
+    state.get_or_insert_with(EphemeralState::default).dirs = Some((scratch_dir, cache_dir));
+}
+
+/// Whether `nickname` is reserved for ephemeral services
+///
+/// Those are managed through `create` and `destroy` only, so the registry
+/// here and the one in `onion` cannot disagree.
+pub fn is_ephemeral(nickname: &str) -> bool {
+    nickname.starts_with(NICKNAME_PREFIX)
+}
+
+/// Create an ephemeral service forwarding `virtual_port` to `target`
+///
+/// Returns its .onion address, which also identifies it for `destroy`. With
+/// a `ttl`, the service is torn down automatically once it expires. Blocks
+/// while the ephemeral client bootstraps, so it must not be called on a
+/// runtime thread.
+pub fn create(
+    runtime: &tokio::runtime::Handle,
+    virtual_port: u16,
+    target: &str,
+    ttl: Option<Duration>,
+) -> Result<String, OnionError> {
+    // Bootstrapping the ephemeral client uses `block_on`, which would panic there
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(OnionError::RuntimeThread);
+    }
+    // Check the target before paying for a bootstrap
+    onion::LocalTarget::parse(target).map_err(OnionError::InvalidTarget)?;
+    let reservation = Reservation::new();
+    let client = client(runtime)?;
+
+    let mut suffix = [0u8; 8];
+    getrandom::fill(&mut suffix).map_err(|e| OnionError::Config(e.to_string()))?;
+    let nickname = format!("{}{}", NICKNAME_PREFIX, hex::encode(suffix));
+
+    let address = onion::start(runtime, client, &nickname, virtual_port, target)?;
+    reservation.fill(runtime, address.clone(), nickname, ttl);
+    Ok(address)
+}
+
+/// A create in progress
+///
+/// While any is held, the ephemeral client and its scratch dir stay, even if
+/// every registered service is destroyed. Dropping it without `fill`, e.g.
+/// when the create fails, releases the client if nothing else uses it.
+struct Reservation;
+
+impl Reservation {
+    fn new() -> Self {
+        let mut state = EPHEMERAL.lock_or_recover();
+        state.get_or_insert_with(EphemeralState::default).pending += 1;
+        Reservation
+    }
+
+    /// Register the started service in place of the reservation
+    fn fill(self, runtime: &tokio::runtime::Handle, address: String, nickname: String, ttl: Option<Duration>) {
+        let mut state = EPHEMERAL.lock_or_recover();
+        let state = state.get_or_insert_with(EphemeralState::default);
+        // The expiry timer needs the lock for destroy, so it cannot fire
+        // before the service is registered
+        let expiry = ttl.map(|ttl| {
+            let address = address.clone();
+            runtime
+                .spawn(async move {
+                    tokio::time::sleep(ttl).await;
+                    if destroy(&address) {
+                        log_info!("Ephemeral onion service {} expired", safelog::sensitive(&address));
+                    }
+                })
+                .abort_handle()
+        });
+        state.services.insert(address, EphemeralService { nickname, expiry });
+        state.pending -= 1;
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(state) = EPHEMERAL.lock_or_recover().as_mut() {
+            state.pending -= 1;
+        }
+        release_client_if_unused();
+    }
+}
+
+/// Tear down an ephemeral service; returns whether it existed
+pub fn destroy(address: &str) -> bool {
+    let removed = EPHEMERAL
+        .lock_or_recover()
+        .as_mut()
+        .and_then(|state| state.services.remove(address));
+    let Some(service) = removed else {
+        return false;
+    };
+
+    if let Some(expiry) = service.expiry {
+        expiry.abort();
+    }
+    onion::stop(&service.nickname);
+    release_client_if_unused();
+    true
+}
+
+/// Tear down every ephemeral service and drop their keys
+pub fn destroy_all() {
+    let services = match EPHEMERAL.lock_or_recover().as_mut() {
+        Some(state) => std::mem::take(&mut state.services),
+        None => return,
+    };
+    for (_, service) in services {
+        if let Some(expiry) = service.expiry {
+            expiry.abort();
+        }
+        onion::stop(&service.nickname);
+    }
+    release_client_if_unused();
+}
+
+/// The ephemeral client, bootstrapping it if needed
+fn client(runtime: &tokio::runtime::Handle) -> Result<Arc<TorClient<PreferredRuntime>>, OnionError> {
+    let (scratch_dir, cache_dir) = {
+        let state = EPHEMERAL.lock_or_recover();
+        let state = state.as_ref().ok_or_else(|| OnionError::Config("Not initialized".to_string()))?;
+        if let Some(client) = &state.client {
+            return Ok(Arc::clone(client));
+        }
+        state.dirs.clone().ok_or_else(|| OnionError::Config("Not initialized".to_string()))?
+    };
+
+    // Bootstrap without holding the state lock; expiring services may need it
+    // meanwhile. Concurrent creates wait here for the one client.
+    let _bootstrap = BOOTSTRAP.lock_or_recover();
+    if let Some(client) = EPHEMERAL.lock_or_recover().as_ref().and_then(|state| state.client.clone()) {
+        return Ok(client);
+    }
+    log_info!("Bootstrapping client for ephemeral onion services");
+    if let Err(e) = remove_scratch_dir(&scratch_dir) {
+        log_error!("Failed to remove {:?}: {}", scratch_dir, e);
+    }
+    let mut builder = TorClientConfigBuilder::from_directories(&scratch_dir, &cache_dir);
+    builder
+        .storage()
+        .keystore()
+        .primary()
+        .kind(ExplicitOrAuto::Explicit(ArtiKeystoreKind::Ephemeral));
+    onion::apply_pow_max_effort(&mut builder);
+    let config = builder.build().map_err(|e| OnionError::Config(e.to_string()))?;
+    let client = runtime
+        .block_on(TorClient::create_bootstrapped(config))
+        .map_err(OnionError::Launch)?;
+
+    let client = Arc::new(client);
+    let mut state = EPHEMERAL.lock_or_recover();
+    state.get_or_insert_with(EphemeralState::default).client = Some(Arc::clone(&client));
+    Ok(client)
+}
+
+/// Drop the ephemeral client, and the keys in it, if no service or create in
+/// progress uses it
+///
+/// Logs and deletes with the state lock released, since a log callback may
+/// call back into the wrapper.
+fn release_client_if_unused() {
+    let client = {
+        let mut state = EPHEMERAL.lock_or_recover();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+        if !state.services.is_empty() || state.pending > 0 {
+            return;
+        }
+        state.client.take()
+    };
+    let Some(client) = client else {
+        return;
+    };
+    drop(client);
+    log_info!("Last ephemeral onion service gone - dropped its client");
+
+    // Clients are only built under the bootstrap lock, so with it held and
+    // no client registered, nothing is using the scratch dir
+    let removed = {
+        let _bootstrap = BOOTSTRAP.lock_or_recover();
+        let scratch_dir = match EPHEMERAL.lock_or_recover().as_ref() {
+            Some(state) if state.client.is_none() => state.dirs.as_ref().map(|(scratch_dir, _)| scratch_dir.clone()),
+            _ => None,
+        };
+        scratch_dir.map(|dir| (remove_scratch_dir(&dir), dir))
+    };
+    if let Some((Err(e), dir)) = removed {
+        log_error!("Failed to remove {:?}: {}", dir, e);
+    }
+}
+
+/// Remove `dir` and everything in it; a missing dir is not an error
+fn remove_scratch_dir(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,65 @@
+//! Structured events for the host app
+//!
+//! Unlike log lines, events are machine-readable: each one is serialized as a
+//! JSON object with a `type` tag and delivered through the event callback.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Events reported to the host app
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A SOCKS client requested an IP-literal destination while safe-socks is on
+    SafeSocksViolation {
+        peer: &'a str,
+        destination: &'a str,
+        rejected: bool,
+    },
+    /// A SOCKS request was refused by the destination policy
+    DestinationRejected { peer: &'a str, destination: &'a str },
+    /// A hosted onion service changed state or hit a new problem
+    OnionServiceStatus {
+        nickname: &'a str,
+        #[serde(flatten)]
+        health: &'a crate::onion::ServiceHealth,
+    },
+    /// arti started solving an onion service's proof-of-work puzzle
+    PowSolveStarted { effort: u32 },
+    /// A proof-of-work puzzle was solved (or the solver failed)
+    PowSolved { effort: u32, duration_ms: u32, success: bool },
+    /// A stream was requested; stream events are only sent once enabled
+    StreamOpened { stream_id: u64, destination: &'a str },
+    /// A stream is connected through the circuit `circuit_id`
+    StreamConnected { stream_id: u64, circuit_id: Option<&'a str> },
+    /// A stream could not be opened; `error_kind` is arti's `ErrorKind`
+    StreamFailed { stream_id: u64, error_kind: &'a str, error: &'a str },
+    /// A connected stream was closed
+    StreamClosed { stream_id: u64, bytes_up: u64, bytes_down: u64, duration_ms: u64 },
+    /// The wrapper panicked; the call it happened in failed with `PANIC_ERROR`
+    Panic { message: &'a str, location: Option<&'a str> },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Serialize an event and hand it to the platform event callback
+pub fn emit(event: &Event<'_>) {
+    let envelope = Envelope {
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        event,
+    };
+
+    if let Ok(value) = serde_json::to_value(&envelope) {
+        crate::send_event(&value.to_string());
+        crate::diagnostics::record_event(value);
+    }
+}
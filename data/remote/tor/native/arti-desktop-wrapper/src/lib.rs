@@ -3,15 +3,20 @@
 //! Platform-agnostic JNI bindings without the `jni` crate.
 //! Uses raw FFI types that work on macOS, Linux, and Windows.
 
-use std::ffi::{c_char, c_void, CStr};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, Once};
 
 use arti_client::TorClient;
 use arti_client::config::TorClientConfigBuilder;
 use tor_rtcompat::PreferredRuntime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use anyhow::Result;
 
+use crate::guard::LockExt;
+
 // ============================================================================
 // Raw JNI Types (platform-agnostic)
 // ============================================================================
@@ -36,18 +41,73 @@ pub struct JObject {
     _private: [u8; 0],
 }
 
+#[repr(C)]
+pub struct JavaVM {
+    _private: [u8; 0],
+}
+
 pub type jint = i32;
+pub type jboolean = u8;
+pub type jlong = i64;
 pub type jstring = *mut JString;
+pub type jobject = *mut JObject;
+pub type jmethodID = *mut c_void;
+
+/// Argument of the `Call<type>MethodA` functions (`j` keeps it 64 bits wide everywhere)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union jvalue {
+    pub l: jobject,
+    pub j: jlong,
+    pub i: jint,
+}
 
-// JNI function table pointer (simplified - we only need GetStringUTFChars/NewStringUTF)
+const JNI_OK: jint = 0;
+const JNI_EDETACHED: jint = -2;
+const JNI_VERSION_1_6: jint = 0x00010006;
+
+// JNI function table pointer (simplified - only the functions used below)
 type GetStringUTFCharsFn = unsafe extern "C" fn(*mut JNIEnv, jstring, *mut u8) -> *const c_char;
 type ReleaseStringUTFCharsFn = unsafe extern "C" fn(*mut JNIEnv, jstring, *const c_char);
 type NewStringUTFFn = unsafe extern "C" fn(*mut JNIEnv, *const c_char) -> jstring;
+type ExceptionClearFn = unsafe extern "C" fn(*mut JNIEnv);
+type NewGlobalRefFn = unsafe extern "C" fn(*mut JNIEnv, jobject) -> jobject;
+type DeleteRefFn = unsafe extern "C" fn(*mut JNIEnv, jobject);
+type GetObjectClassFn = unsafe extern "C" fn(*mut JNIEnv, jobject) -> jobject;
+type GetMethodIDFn = unsafe extern "C" fn(*mut JNIEnv, jobject, *const c_char, *const c_char) -> jmethodID;
+type CallVoidMethodAFn = unsafe extern "C" fn(*mut JNIEnv, jobject, jmethodID, *const jvalue);
+type GetJavaVMFn = unsafe extern "C" fn(*mut JNIEnv, *mut *mut JavaVM) -> jint;
+type ExceptionCheckFn = unsafe extern "C" fn(*mut JNIEnv) -> jboolean;
+
+// JNI invocation interface (JavaVM function table)
+type DetachCurrentThreadFn = unsafe extern "C" fn(*mut JavaVM) -> jint;
+type GetEnvFn = unsafe extern "C" fn(*mut JavaVM, *mut *mut JNIEnv, jint) -> jint;
+type AttachCurrentThreadAsDaemonFn = unsafe extern "C" fn(*mut JavaVM, *mut *mut JNIEnv, *mut c_void) -> jint;
 
 // JNI function table offsets (JNI 1.6+)
 const GET_STRING_UTF_CHARS_OFFSET: isize = 169;
 const RELEASE_STRING_UTF_CHARS_OFFSET: isize = 170;
 const NEW_STRING_UTF_OFFSET: isize = 167;
+const EXCEPTION_CLEAR_OFFSET: isize = 17;
+const NEW_GLOBAL_REF_OFFSET: isize = 21;
+const DELETE_GLOBAL_REF_OFFSET: isize = 22;
+const DELETE_LOCAL_REF_OFFSET: isize = 23;
+const GET_OBJECT_CLASS_OFFSET: isize = 31;
+const GET_METHOD_ID_OFFSET: isize = 33;
+const CALL_VOID_METHOD_A_OFFSET: isize = 63;
+const GET_JAVA_VM_OFFSET: isize = 219;
+const EXCEPTION_CHECK_OFFSET: isize = 228;
+
+// Invocation interface offsets
+const DETACH_CURRENT_THREAD_OFFSET: isize = 5;
+const GET_ENV_OFFSET: isize = 6;
+const ATTACH_CURRENT_THREAD_AS_DAEMON_OFFSET: isize = 7;
+
+/// Entry `offset` of the function table a `JNIEnv` or `JavaVM` points to
+unsafe fn table_fn<F: Copy>(table_owner: *mut c_void, offset: isize) -> F {
+    let func_table = *(table_owner as *const *const *const c_void);
+    std::mem::transmute_copy(&*func_table.offset(offset))
+}
 
 unsafe fn get_string_utf_chars(env: *mut JNIEnv, s: jstring) -> *const c_char {
     let func_table = *(env as *const *const *const c_void);
@@ -67,31 +127,321 @@ unsafe fn new_string_utf(env: *mut JNIEnv, chars: *const c_char) -> jstring {
     func(env, chars)
 }
 
+unsafe fn exception_clear(env: *mut JNIEnv) {
+    table_fn::<ExceptionClearFn>(env as *mut c_void, EXCEPTION_CLEAR_OFFSET)(env)
+}
+
+unsafe fn exception_check(env: *mut JNIEnv) -> bool {
+    table_fn::<ExceptionCheckFn>(env as *mut c_void, EXCEPTION_CHECK_OFFSET)(env) != 0
+}
+
+unsafe fn new_global_ref(env: *mut JNIEnv, obj: jobject) -> jobject {
+    table_fn::<NewGlobalRefFn>(env as *mut c_void, NEW_GLOBAL_REF_OFFSET)(env, obj)
+}
+
+unsafe fn delete_global_ref(env: *mut JNIEnv, obj: jobject) {
+    table_fn::<DeleteRefFn>(env as *mut c_void, DELETE_GLOBAL_REF_OFFSET)(env, obj)
+}
+
+unsafe fn delete_local_ref(env: *mut JNIEnv, obj: jobject) {
+    table_fn::<DeleteRefFn>(env as *mut c_void, DELETE_LOCAL_REF_OFFSET)(env, obj)
+}
+
+unsafe fn get_object_class(env: *mut JNIEnv, obj: jobject) -> jobject {
+    table_fn::<GetObjectClassFn>(env as *mut c_void, GET_OBJECT_CLASS_OFFSET)(env, obj)
+}
+
+unsafe fn get_method_id(env: *mut JNIEnv, class: jobject, name: *const c_char, sig: *const c_char) -> jmethodID {
+    table_fn::<GetMethodIDFn>(env as *mut c_void, GET_METHOD_ID_OFFSET)(env, class, name, sig)
+}
+
+unsafe fn call_void_method_a(env: *mut JNIEnv, obj: jobject, method: jmethodID, args: *const jvalue) {
+    table_fn::<CallVoidMethodAFn>(env as *mut c_void, CALL_VOID_METHOD_A_OFFSET)(env, obj, method, args)
+}
+
+unsafe fn get_java_vm(env: *mut JNIEnv) -> Option<*mut JavaVM> {
+    let mut vm = std::ptr::null_mut();
+    let status = table_fn::<GetJavaVMFn>(env as *mut c_void, GET_JAVA_VM_OFFSET)(env, &mut vm);
+    (status == JNI_OK && !vm.is_null()).then_some(vm)
+}
+
+/// Copy a Java string into a Rust `String` (`None` for null or invalid UTF-8)
+unsafe fn jstring_to_string(env: *mut JNIEnv, s: jstring) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let chars = get_string_utf_chars(env, s);
+    if chars.is_null() {
+        return None;
+    }
+    let result = CStr::from_ptr(chars).to_str().ok().map(|s| s.to_string());
+    release_string_utf_chars(env, s, chars);
+    result
+}
+
 // ============================================================================
 // Global State
 // ============================================================================
 
 static ARTI_CLIENT: Mutex<Option<Arc<TorClient<PreferredRuntime>>>> = Mutex::new(None);
 static TOKIO_RUNTIME: Mutex<Option<tokio::runtime::Runtime>> = Mutex::new(None);
+static STATE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
 static SOCKS_TASK: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
 static INIT_ONCE: Once = Once::new();
 
+/// Java log callback, set by `nativeSetLogCallback`
+static LOG_CALLBACK: Mutex<Option<Arc<JavaCallback>>> = Mutex::new(None);
+
+/// Leveled Java log callback, set by `nativeSetLogCallbackV2`
+static LOG_CALLBACK_V2: Mutex<Option<Arc<JavaCallback>>> = Mutex::new(None);
+
+/// Java event callback, set by `nativeSetEventCallback`
+static EVENT_CALLBACK: Mutex<Option<Arc<JavaCallback>>> = Mutex::new(None);
+
 // ============================================================================
-// Logging (desktop - uses stderr)
+// Logging (desktop - stderr and the Java log callback)
 // ============================================================================
 
+/// Global reference to a Java callback object and the void method called on it
+///
+/// Shared through an `Arc` so callers can release the lock holding it before
+/// calling into Java; the global reference is deleted with the last clone.
+struct JavaCallback {
+    vm: *mut JavaVM,
+    callback: jobject,
+    method: jmethodID,
+}
+
+// A global reference and method ID may be used from any thread attached to the JVM
+unsafe impl Send for JavaCallback {}
+unsafe impl Sync for JavaCallback {}
+
+/// Argument of a Java callback method
+enum JavaArg<'a> {
+    Int(jint),
+    Long(jlong),
+    Str(&'a str),
+}
+
+impl JavaCallback {
+    /// Keep a global reference to `callback`, whose class must have method `name` with signature `sig`
+    unsafe fn new(env: *mut JNIEnv, callback: jobject, name: &CStr, sig: &CStr) -> Result<Arc<Self>, String> {
+        let Some(vm) = get_java_vm(env) else {
+            return Err("Failed to get JavaVM".to_string());
+        };
+        let class = get_object_class(env, callback);
+        let method = get_method_id(env, class, name.as_ptr(), sig.as_ptr());
+        delete_local_ref(env, class);
+        if method.is_null() {
+            // GetMethodID threw NoSuchMethodError
+            exception_clear(env);
+            return Err(format!("Callback has no {}{} method", name.to_string_lossy(), sig.to_string_lossy()));
+        }
+
+        let callback = new_global_ref(env, callback);
+        if callback.is_null() {
+            exception_clear(env);
+            return Err("Failed to create global reference to callback".to_string());
+        }
+        Ok(Arc::new(JavaCallback { vm, callback, method }))
+    }
+
+    /// Call the method with `args`, attaching the current thread to the JVM if needed
+    unsafe fn call(&self, args: &[JavaArg<'_>]) {
+        let Some(env) = attached_env(self.vm) else {
+            return;
+        };
+
+        let mut strings = Vec::new();
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(match arg {
+                JavaArg::Int(i) => jvalue { i: *i },
+                JavaArg::Long(j) => jvalue { j: *j },
+                JavaArg::Str(s) => {
+                    let jstr = CString::new(*s).map_or(std::ptr::null_mut(), |c| new_string_utf(env, c.as_ptr()));
+                    if jstr.is_null() {
+                        exception_clear(env);
+                        strings.into_iter().for_each(|s| delete_local_ref(env, s));
+                        return;
+                    }
+                    strings.push(jstr as jobject);
+                    jvalue { l: jstr as jobject }
+                }
+            });
+        }
+
+        call_void_method_a(env, self.callback, self.method, values.as_ptr());
+        if exception_check(env) {
+            // An exception thrown by the callback must not leak into unrelated JNI calls
+            exception_clear(env);
+        }
+        // Threads attached here have no Java frame to free local references on return
+        strings.into_iter().for_each(|s| delete_local_ref(env, s));
+    }
+}
+
+impl Drop for JavaCallback {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(env) = attached_env(self.vm) {
+                delete_global_ref(env, self.callback);
+            }
+        }
+    }
+}
+
+/// Detaches a thread this library attached to the JVM once the thread exits
+struct ThreadAttachment(*mut JavaVM);
+
+impl Drop for ThreadAttachment {
+    fn drop(&mut self) {
+        unsafe {
+            table_fn::<DetachCurrentThreadFn>(self.0 as *mut c_void, DETACH_CURRENT_THREAD_OFFSET)(self.0);
+        }
+    }
+}
+
+thread_local! {
+    static ATTACHMENT: RefCell<Option<ThreadAttachment>> = const { RefCell::new(None) };
+}
+
+/// JNIEnv of the current thread, attaching it to the JVM until it exits if needed
+///
+/// Tokio worker threads are attached as daemons, so they never keep the JVM
+/// alive; threads the JVM attached itself are left alone.
+unsafe fn attached_env(vm: *mut JavaVM) -> Option<*mut JNIEnv> {
+    let mut env = std::ptr::null_mut();
+    match table_fn::<GetEnvFn>(vm as *mut c_void, GET_ENV_OFFSET)(vm, &mut env, JNI_VERSION_1_6) {
+        JNI_OK => return Some(env),
+        JNI_EDETACHED => {}
+        _ => return None,
+    }
+
+    let attach = table_fn::<AttachCurrentThreadAsDaemonFn>(vm as *mut c_void, ATTACH_CURRENT_THREAD_AS_DAEMON_OFFSET);
+    if attach(vm, &mut env, std::ptr::null_mut()) != JNI_OK {
+        return None;
+    }
+    if ATTACHMENT.try_with(|a| *a.borrow_mut() = Some(ThreadAttachment(vm))).is_err() {
+        // Thread-locals are already gone (thread exiting); skip rather than stay attached
+        drop(ThreadAttachment(vm));
+        return None;
+    }
+    Some(env)
+}
+
+/// Send a JSON event to the Java event callback
+fn send_event(json: &str) {
+    let callback = EVENT_CALLBACK.lock_or_recover().clone();
+    if let Some(callback) = callback {
+        unsafe { callback.call(&[JavaArg::Str(json)]) };
+    }
+}
+
+/// Log severities, matching the leveled log callback of the other wrappers
+const LOG_LEVEL_TRACE: jint = 0;
+const LOG_LEVEL_DEBUG: jint = 1;
+const LOG_LEVEL_INFO: jint = 2;
+const LOG_LEVEL_WARN: jint = 3;
+const LOG_LEVEL_ERROR: jint = 4;
+
+/// Write a log record to stderr and the Java log callbacks
+///
+/// `onLogLine` gets a single line: errors are prefixed with "ERROR: " and
+/// records from arti itself with their level and target. `onLog` gets the
+/// level, timestamp, target and message separately.
+fn send_log(level: jint, target: &str, message: &str) {
+    diagnostics::record_log(log_level_name(level), target, message);
+
+    let line = if target.starts_with(module_path!()) {
+        message.to_string()
+    } else {
+        format!("[{}] {}: {}", log_level_name(level), target, message)
+    };
+
+    if level == LOG_LEVEL_ERROR {
+        eprintln!("[Arti ERROR] {}", line);
+    } else {
+        eprintln!("[Arti] {}", line);
+    }
+
+    // Cloned out of the locks so a callback may call back into the wrapper
+    let callback = LOG_CALLBACK.lock_or_recover().clone();
+    if let Some(callback) = callback {
+        let prefix = if level == LOG_LEVEL_ERROR { "ERROR: " } else { "" };
+        unsafe { callback.call(&[JavaArg::Str(&format!("{}{}", prefix, line))]) };
+    }
+
+    let callback_v2 = LOG_CALLBACK_V2.lock_or_recover().clone();
+    if let Some(callback) = callback_v2 {
+        unsafe {
+            callback.call(&[
+                JavaArg::Int(level),
+                JavaArg::Long(unix_time_ms()),
+                JavaArg::Str(target),
+                JavaArg::Str(message),
+            ])
+        };
+    }
+}
+
+fn log_level_name(level: jint) -> &'static str {
+    match level {
+        LOG_LEVEL_TRACE => "TRACE",
+        LOG_LEVEL_DEBUG => "DEBUG",
+        LOG_LEVEL_INFO => "INFO",
+        LOG_LEVEL_WARN => "WARN",
+        _ => "ERROR",
+    }
+}
+
+fn unix_time_ms() -> jlong {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as jlong)
+        .unwrap_or(0)
+}
+
 macro_rules! log_info {
     ($($arg:tt)*) => {
-        eprintln!("[Arti] {}", format!($($arg)*));
+        $crate::send_log($crate::LOG_LEVEL_INFO, module_path!(), &format!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::send_log($crate::LOG_LEVEL_WARN, module_path!(), &format!($($arg)*))
     };
 }
 
 macro_rules! log_error {
     ($($arg:tt)*) => {
-        eprintln!("[Arti ERROR] {}", format!($($arg)*));
+        $crate::send_log($crate::LOG_LEVEL_ERROR, module_path!(), &format!($($arg)*))
     };
 }
 
+mod addrmap;
+mod auth;
+mod circuits;
+mod clientauth;
+mod diagnostics;
+mod ephemeral;
+mod events;
+#[cfg(unix)]
+mod fdstream;
+mod guard;
+mod identity;
+mod isolation;
+mod metrics;
+mod onion;
+mod policy;
+mod pow;
+mod redact;
+mod resolve;
+mod streamevents;
+mod tracelog;
+mod traffic;
+
 // ============================================================================
 // JNI Functions
 // ============================================================================
@@ -101,103 +451,305 @@ pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetVersion(
     env: *mut JNIEnv,
     _class: *mut JClass,
 ) -> jstring {
-    let version = format!("Arti {} (desktop build)\0", env!("CARGO_PKG_VERSION"));
-    new_string_utf(env, version.as_ptr() as *const c_char)
+    guard::catch(|| {
+        let version = format!("Arti {} (desktop build)\0", env!("CARGO_PKG_VERSION"));
+        new_string_utf(env, version.as_ptr() as *const c_char)
+    })
 }
 
+/// Set log callback for Arti logs (null to unset); logs also keep going to stderr
+///
+/// `callback.onLogLine(String)` is called from Tor worker threads.
 #[no_mangle]
 pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogCallback(
-    _env: *mut JNIEnv,
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    callback: *mut JObject,
+) {
+    guard::catch(|| {
+        let previous = LOG_CALLBACK.lock_or_recover().take();
+        drop(previous);
+        if callback.is_null() {
+            return;
+        }
+
+        match JavaCallback::new(env, callback, c"onLogLine", c"(Ljava/lang/String;)V") {
+            Ok(callback) => {
+                *LOG_CALLBACK.lock_or_recover() = Some(callback);
+                log_info!("Log callback registered");
+            }
+            Err(e) => log_error!("{}", e),
+        }
+    })
+}
+
+/// Set leveled log callback, receiving the same records as the log callback
+///
+/// `callback.onLog(level, timestampMs, target, message)` gets the level
+/// (0 = trace ... 4 = error), unix time in milliseconds, module target and
+/// message as separate arguments. Passing null unsets it.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogCallbackV2(
+    env: *mut JNIEnv,
     _class: *mut JClass,
-    _callback: *mut JObject,
+    callback: *mut JObject,
 ) {
-    // Desktop: log callback not implemented (uses stderr)
-    log_info!("Log callback registered (desktop uses stderr)");
+    guard::catch(|| {
+        let previous = LOG_CALLBACK_V2.lock_or_recover().take();
+        drop(previous);
+        if callback.is_null() {
+            return;
+        }
+
+        match JavaCallback::new(env, callback, c"onLog", c"(IJLjava/lang/String;Ljava/lang/String;)V") {
+            Ok(callback) => {
+                *LOG_CALLBACK_V2.lock_or_recover() = Some(callback);
+                log_info!("Leveled log callback registered");
+            }
+            Err(e) => log_error!("{}", e),
+        }
+    })
 }
 
+/// Set event callback for structured (JSON) events (null to unset)
+///
+/// `callback.onEvent(String)` is called from Tor worker threads.
 #[no_mangle]
-pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeInitialize(
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetEventCallback(
     env: *mut JNIEnv,
     _class: *mut JClass,
-    data_dir: jstring,
+    callback: *mut JObject,
+) {
+    guard::catch(|| {
+        let previous = EVENT_CALLBACK.lock_or_recover().take();
+        drop(previous);
+        if callback.is_null() {
+            return;
+        }
+
+        match JavaCallback::new(env, callback, c"onEvent", c"(Ljava/lang/String;)V") {
+            Ok(callback) => {
+                *EVENT_CALLBACK.lock_or_recover() = Some(callback);
+                log_info!("Event callback registered");
+            }
+            Err(e) => log_error!("{}", e),
+        }
+    })
+}
+
+/// Set which of arti's internal log events are logged, e.g. `warn` or `info,tor_guardmgr=debug`
+///
+/// Returns 0 on success, -1 if the directives do not parse.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogFilter(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    directives: jstring,
 ) -> jint {
-    // Get data directory string
-    let chars = get_string_utf_chars(env, data_dir);
-    if chars.is_null() {
-        log_error!("Failed to get data_dir string");
-        return -1;
-    }
-    let data_dir_str = match CStr::from_ptr(chars).to_str() {
-        Ok(s) => s.to_string(),
-        Err(e) => {
-            log_error!("Invalid UTF-8 in data_dir: {:?}", e);
-            release_string_utf_chars(env, data_dir, chars);
+    guard::catch(|| {
+        let Some(directives) = jstring_to_string(env, directives) else {
+            log_error!("Failed to get directives string");
             return -1;
-        }
-    };
-    release_string_utf_chars(env, data_dir, chars);
+        };
 
-    log_info!("AMEx: state changed to Initialized");
-    log_info!("Initializing Arti with data directory: {}", data_dir_str);
+        match tracelog::set_filter(&directives) {
+            Ok(()) => {
+                log_info!("Log filter set to {}", directives);
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid log filter {:?}: {}", directives, e);
+                -1
+            }
+        }
+    })
+}
 
-    // Initialize Tokio runtime (once)
-    INIT_ONCE.call_once(|| {
-        match tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-        {
-            Ok(rt) => {
-                log_info!("Tokio runtime created");
-                *TOKIO_RUNTIME.lock().unwrap() = Some(rt);
+/// Turn safe logging on (the default) or off
+///
+/// With safe logging on, destination hosts, onion addresses and peer sockets
+/// are logged as "[scrubbed]". Returns 0 on success, -1 if it could not be changed.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetSafeLogging(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    enabled: jboolean,
+) -> jint {
+    guard::catch(|| {
+        match redact::set_safe_logging(enabled != 0) {
+            Ok(()) => {
+                log_info!("Safe logging {}", if enabled != 0 { "enabled" } else { "disabled - destinations will be logged" });
+                0
             }
             Err(e) => {
-                log_error!("Failed to create Tokio runtime: {:?}", e);
+                log_error!("Failed to change safe logging: {}", e);
+                -1
             }
         }
-    });
+    })
+}
 
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
+/// Recent log lines and events, oldest first, as a JSON array
+///
+/// Keeps the last 1000 records, whether or not a callback was set.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetRecentLogs(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        match CString::new(diagnostics::recent_json()) {
+            Ok(json) => new_string_utf(env, json.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
         }
-    };
+    })
+}
 
-    let data_path = PathBuf::from(data_dir_str);
-    let cache_dir = data_path.join("cache");
-    let state_dir = data_path.join("state");
+/// Also write log lines to `<data dir>/logs/arti.log`, or stop doing so
+///
+/// The file is rotated to `arti.log.1`, `arti.log.2`, ... once it reaches
+/// `maxBytes` or, if `maxAgeSeconds` > 0, gets that old; up to `maxFiles`
+/// rotated files are kept and those older than `maxAgeSeconds` deleted.
+/// `maxBytes` <= 0 turns the file off. Returns 0 on success, -1 on invalid limits.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetLogFile(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    max_bytes: jlong,
+    max_files: jint,
+    max_age_seconds: jlong,
+) -> jint {
+    guard::catch(|| {
+        if max_bytes <= 0 {
+            diagnostics::set_file_limits(None);
+            log_info!("Log file disabled");
+            return 0;
+        }
+        let Ok(max_files) = u32::try_from(max_files) else {
+            log_error!("Invalid max_files: {}", max_files);
+            return -1;
+        };
+
+        diagnostics::set_file_limits(Some(diagnostics::FileLimits {
+            max_bytes: max_bytes as u64,
+            max_files,
+            max_age: u64::try_from(max_age_seconds).ok().filter(|&s| s > 0).map(std::time::Duration::from_secs),
+        }));
+        log_info!("Log file enabled ({} bytes, {} rotated files)", max_bytes, max_files);
+        0
+    })
+}
 
-    std::fs::create_dir_all(&cache_dir).ok();
-    std::fs::create_dir_all(&state_dir).ok();
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeInitialize(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    data_dir: jstring,
+) -> jint {
+    guard::catch(|| {
+        // Get data directory string
+        let chars = get_string_utf_chars(env, data_dir);
+        if chars.is_null() {
+            log_error!("Failed to get data_dir string");
+            return -1;
+        }
+        let data_dir_str = match CStr::from_ptr(chars).to_str() {
+            Ok(s) => s.to_string(),
+            Err(e) => {
+                log_error!("Invalid UTF-8 in data_dir: {:?}", e);
+                release_string_utf_chars(env, data_dir, chars);
+                return -1;
+            }
+        };
+        release_string_utf_chars(env, data_dir, chars);
+
+        log_info!("AMEx: state changed to Initialized");
+        log_info!("Initializing Arti with data directory: {}", data_dir_str);
+
+        // Initialize Tokio runtime (once)
+        INIT_ONCE.call_once(|| {
+            match tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => {
+                    log_info!("Tokio runtime created");
+                    *TOKIO_RUNTIME.lock_or_recover() = Some(rt);
+                }
+                Err(e) => {
+                    log_error!("Failed to create Tokio runtime: {:?}", e);
+                }
+            }
 
-    let result: Result<()> = runtime.block_on(async {
-        log_info!("Creating Arti client...");
-        log_info!("Cache dir: {:?}", cache_dir);
-        log_info!("State dir: {:?}", state_dir);
+            // Forward arti's diagnostics, watch for proof-of-work solver runs
+            // and count circuits
+            if tracing_subscriber::registry()
+                .with(metrics::layer())
+                .with(pow::layer())
+                .with(tracelog::layer())
+                .try_init()
+                .is_err()
+            {
+                log_error!("A tracing subscriber is already installed - arti logs, proof-of-work events and circuit metrics disabled");
+            }
+        });
 
-        let config = TorClientConfigBuilder::from_directories(state_dir, cache_dir)
-            .build()?;
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
+
+        let data_path = PathBuf::from(data_dir_str);
+        let cache_dir = data_path.join("cache");
+        let state_dir = data_path.join("state");
+
+        std::fs::create_dir_all(&cache_dir).ok();
+        std::fs::create_dir_all(&state_dir).ok();
+        *STATE_DIR.lock_or_recover() = Some(state_dir.clone());
+        ephemeral::set_directories(data_path.join("ephemeral"), cache_dir.clone());
+        diagnostics::set_log_dir(data_path.join("logs"));
+
+        let result: Result<()> = runtime.block_on(async {
+            log_info!("Creating Arti client...");
+            log_info!("Cache dir: {:?}", cache_dir);
+            log_info!("State dir: {:?}", state_dir);
+
+            let mut builder = TorClientConfigBuilder::from_directories(&state_dir, &cache_dir);
+            onion::apply_pow_max_effort(&mut builder);
+            let config = builder.build()?;
+
+            // Onion service identities live in the keystore arti itself uses
+            match tor_keymgr::ArtiNativeKeystore::from_path_and_mistrust(state_dir.join("keystore"), config.fs_mistrust()) {
+                Ok(keystore) => identity::set_keystore(keystore, state_dir.clone()),
+                Err(e) => {
+                    log_error!("Failed to open keystore: {:?}", e);
+                }
+            }
 
-        let client = TorClient::create_bootstrapped(config).await?;
+            let client = TorClient::create_bootstrapped(config).await?;
 
-        log_info!("Arti client created successfully");
-        *ARTI_CLIENT.lock().unwrap() = Some(Arc::new(client));
+            log_info!("Arti client created successfully");
+            *ARTI_CLIENT.lock_or_recover() = Some(Arc::new(client));
+            metrics::mark_started();
 
-        Ok(())
-    });
+            Ok(())
+        });
 
-    match result {
-        Ok(_) => {
-            log_info!("Arti initialized successfully");
-            0
-        }
-        Err(e) => {
-            log_error!("Failed to initialize Arti: {:?}", e);
-            -3
+        match result {
+            Ok(_) => {
+                log_info!("Arti initialized successfully");
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to initialize Arti: {:?}", e);
+                -3
+            }
         }
-    }
+    })
 }
 
 #[no_mangle]
@@ -206,85 +758,88 @@ pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeStartSocksProxy(
     _class: *mut JClass,
     port: jint,
 ) -> jint {
-    log_info!("AMEx: state changed to Starting");
-    log_info!("Starting SOCKS proxy on port {}", port);
-
-    // Stop existing task
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting previous SOCKS server task");
-        handle.abort();
-    }
-
-    let client_guard = ARTI_CLIENT.lock().unwrap();
-    let client = match client_guard.as_ref() {
-        Some(c) => Arc::clone(c),
-        None => {
-            log_error!("Arti client not initialized - call initialize() first");
-            return -1;
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Starting");
+        log_info!("Starting SOCKS proxy on port {}", port);
+
+        // Stop existing task
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting previous SOCKS server task");
+            handle.abort();
         }
-    };
-    drop(client_guard);
-
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
-        }
-    };
 
-    let addr = format!("127.0.0.1:{}", port);
+        let client_guard = ARTI_CLIENT.lock_or_recover();
+        let client = match client_guard.as_ref() {
+            Some(c) => Arc::clone(c),
+            None => {
+                log_error!("Arti client not initialized - call initialize() first");
+                return -1;
+            }
+        };
+        drop(client_guard);
+
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
 
-    // Bind synchronously to detect errors
-    let listener = match runtime.block_on(tokio::net::TcpListener::bind(&addr)) {
-        Ok(l) => {
-            log_info!("SOCKS proxy bound to {}", addr);
-            l
-        }
-        Err(e) => {
-            log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
-            return -3;
-        }
-    };
+        let addr = format!("127.0.0.1:{}", port);
 
-    let handle = runtime.spawn(async move {
-        log_info!("SOCKS proxy listening on {}", addr);
-        log_info!("Sufficiently bootstrapped; system SOCKS now functional");
-
-        // Signal bootstrap completion (expected by TorManager Kotlin code)
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        log_info!("We have found that guard [scrubbed] is usable.");
-
-        loop {
-            match listener.accept().await {
-                Ok((stream, peer)) => {
-                    log_info!("SOCKS connection from: {}", peer);
-                    let client_clone = Arc::clone(&client);
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_socks_connection(stream, client_clone).await {
-                            log_error!("SOCKS connection error: {:?}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    log_error!("Failed to accept SOCKS connection: {:?}", e);
-                    break;
+        // Bind synchronously to detect errors
+        let listener = match runtime.block_on(tokio::net::TcpListener::bind(&addr)) {
+            Ok(l) => {
+                log_info!("SOCKS proxy bound to {}", addr);
+                l
+            }
+            Err(e) => {
+                log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
+                return -3;
+            }
+        };
+
+        let handle = runtime.spawn(async move {
+            log_info!("SOCKS proxy listening on {}", addr);
+            log_info!("Sufficiently bootstrapped; system SOCKS now functional");
+
+            // Signal bootstrap completion (expected by TorManager Kotlin code)
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            log_info!("We have found that guard [scrubbed] is usable.");
+
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        log_info!("SOCKS connection from: {}", safelog::sensitive(peer));
+                        let client_clone = Arc::clone(&client);
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_socks_connection(stream, peer, client_clone).await {
+                                log_error!("SOCKS connection error: {:?}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log_error!("Failed to accept SOCKS connection: {:?}", e);
+                        break;
+                    }
                 }
             }
-        }
 
-        log_info!("SOCKS proxy task exiting");
-    });
+            log_info!("SOCKS proxy task exiting");
+        });
 
-    *SOCKS_TASK.lock().unwrap() = Some(handle);
+        *SOCKS_TASK.lock_or_recover() = Some(handle);
 
-    log_info!("SOCKS proxy started on port {}", port);
-    0
+        log_info!("SOCKS proxy started on port {}", port);
+        0
+    })
 }
 
 async fn handle_socks_connection(
     mut stream: tokio::net::TcpStream,
+    peer_addr: std::net::SocketAddr,
     client: Arc<TorClient<PreferredRuntime>>,
 ) -> Result<()> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -297,8 +852,26 @@ async fn handle_socks_connection(
         return Err(anyhow::anyhow!("Invalid SOCKS handshake"));
     }
 
-    // Send "no auth required" response
-    stream.write_all(&[0x05, 0x00]).await?;
+    if auth::is_required() {
+        // Only accept username/password auth (RFC 1929) carrying the cookie
+        let nmethods = buf[1] as usize;
+        let methods = &buf[2..n.min(2 + nmethods)];
+        if !methods.contains(&0x02) {
+            stream.write_all(&[0x05, 0xFF]).await?;
+            return Err(anyhow::anyhow!("SOCKS client did not offer username/password auth"));
+        }
+        stream.write_all(&[0x05, 0x02]).await?;
+
+        let n = stream.read(&mut buf).await?;
+        if !auth::socks_password(&buf[..n]).is_some_and(auth::verify) {
+            stream.write_all(&[0x01, 0x01]).await?;
+            return Err(anyhow::anyhow!("SOCKS authentication failed"));
+        }
+        stream.write_all(&[0x01, 0x00]).await?;
+    } else {
+        // Send "no auth required" response
+        stream.write_all(&[0x05, 0x00]).await?;
+    }
 
     // Read request
     let n = stream.read(&mut buf).await?;
@@ -359,27 +932,84 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS5 CONNECT to {}:{}", target_host, target_port);
+    // Safe-socks: an IP literal means the caller resolved the name outside Tor.
+    // Checked before address mapping, since only the caller's own request can
+    // reveal such a leak; a mapping to an IP literal is the host's choice.
+    if policy::is_ip_literal(&target_host) {
+        let mode = policy::safe_socks_mode();
+        if mode != policy::SafeSocksMode::Off {
+            let rejected = mode == policy::SafeSocksMode::Reject;
+            events::emit(&events::Event::SafeSocksViolation {
+                peer: &safelog::sensitive(peer_addr).to_string(),
+                destination: &redact::destination(&target_host, target_port).to_string(),
+                rejected,
+            });
+            if rejected {
+                // Send SOCKS5 error: connection not allowed by ruleset
+                stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("Safe-socks rejected IP-literal destination from {}", safelog::sensitive(peer_addr)));
+            }
+            log_warn!(
+                "Safe-socks: {} requested IP-literal destination {}",
+                safelog::sensitive(peer_addr),
+                redact::destination(&target_host, target_port)
+            );
+        }
+    }
+
+    // Address mapping: transparently rewrite mapped destinations (e.g. onion mirrors)
+    let (target_host, target_port) = addrmap::apply(&target_host, target_port);
+
+    // Destination policy: refuse anything the loaded rule set does not allow
+    if !policy::is_allowed(&target_host, target_port) {
+        events::emit(&events::Event::DestinationRejected {
+            peer: &safelog::sensitive(peer_addr).to_string(),
+            destination: &redact::destination(&target_host, target_port).to_string(),
+        });
+        // Send SOCKS5 error: connection not allowed by ruleset
+        stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(anyhow::anyhow!("Destination {} rejected by policy", redact::destination(&target_host, target_port)));
+    }
+
+    log_info!("SOCKS5 CONNECT to {}", redact::destination(&target_host, target_port));
 
     // Establish Tor connection
+    let mut lifecycle = streamevents::opened(&target_host, target_port);
     let tor_stream = match client.connect((target_host.as_str(), target_port)).await {
         Ok(s) => s,
         Err(e) => {
             log_error!("Failed to connect through Tor: {:?}", e);
-            // Send SOCKS5 error: general failure
-            stream.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+            lifecycle.failed(&e);
+            // Onion service failures get Tor's extended reply codes (e.g. 0xF4 missing client auth)
+            let reply = clientauth::socks_reply_code(&e);
+            stream.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
             return Err(e.into());
         }
     };
 
-    log_info!("Tor connection established to {}:{}", target_host, target_port);
+    log_info!("Tor connection established to {}", redact::destination(&target_host, target_port));
 
     // Send SOCKS5 success response
     stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
 
-    // Bidirectional data forwarding
-    let (mut client_read, mut client_write) = stream.split();
-    let (mut tor_read, mut tor_write) = tor_stream.split();
+    // Register the stream for traffic accounting and circuit inspection
+    let traffic_stream = traffic::open_stream(lifecycle.id(), &target_host, target_port);
+    let _circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+
+    // Bidirectional data forwarding, counting bytes in both directions
+    let (client_read, mut client_write) = stream.split();
+    let (tor_read, mut tor_write) = tor_stream.split();
+    let mut client_read = traffic::CountingReader::new(
+        client_read,
+        traffic_stream.counters(),
+        traffic::Direction::Up,
+    );
+    let mut tor_read = traffic::CountingReader::new(
+        tor_read,
+        traffic_stream.counters(),
+        traffic::Direction::Down,
+    );
 
     let client_to_tor = async {
         tokio::io::copy(&mut client_read, &mut tor_write).await
@@ -403,7 +1033,13 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS connection closed for {}:{}", target_host, target_port);
+    log_info!(
+        "SOCKS connection closed for {} ({} bytes up, {} bytes down, {} ms)",
+        redact::destination(&target_host, target_port),
+        traffic_stream.bytes_up(),
+        traffic_stream.bytes_down(),
+        traffic_stream.duration_ms()
+    );
 
     Ok(())
 }
@@ -413,24 +1049,1065 @@ pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeStop(
     _env: *mut JNIEnv,
     _class: *mut JClass,
 ) -> jint {
-    log_info!("AMEx: state changed to Stopping");
-    log_info!("Stopping Arti...");
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Stopping");
+        log_info!("Stopping Arti...");
+
+        // Abort SOCKS proxy task (releases the port)
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting SOCKS server task");
+            handle.abort();
+        }
 
-    // Abort SOCKS proxy task (releases the port)
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting SOCKS server task");
-        handle.abort();
-    }
+        // Take down hosted onion services, dropping ephemeral keys
+        ephemeral::destroy_all();
+        onion::stop_all();
 
-    // Give the abort a moment to complete and release the port
-    if let Some(rt) = TOKIO_RUNTIME.lock().unwrap().as_ref() {
-        rt.block_on(async {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        });
-    }
+        // Give the abort a moment to complete and release the port
+        if let Some(rt) = TOKIO_RUNTIME.lock_or_recover().as_ref() {
+            rt.block_on(async {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            });
+        }
+
+        log_info!("AMEx: state changed to Stopped");
+        log_info!("Arti stopped successfully");
+
+        0
+    })
+}
 
-    log_info!("AMEx: state changed to Stopped");
-    log_info!("Arti stopped successfully");
+// ============================================================================
+// Loopback Authentication
+// ============================================================================
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetCookieAuth(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    enabled: jboolean,
+) -> jint {
+    guard::catch(|| {
+        let state_dir = match STATE_DIR.lock_or_recover().clone() {
+            Some(dir) => dir,
+            None => {
+                log_error!("Arti not initialized - call initialize() first");
+                return -1;
+            }
+        };
+
+        if enabled == 0 {
+            auth::disable(&state_dir);
+            log_info!("SOCKS cookie authentication disabled");
+            return 0;
+        }
+
+        match auth::enable(&state_dir) {
+            Ok(path) => {
+                log_info!("SOCKS cookie authentication enabled, cookie at {:?}", path);
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to write SOCKS auth cookie: {:?}", e);
+                -2
+            }
+        }
+    })
+}
 
-    0
+// ============================================================================
+// Destination Policy
+// ============================================================================
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetSafeSocksMode(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    mode: jint,
+) -> jint {
+    guard::catch(|| {
+        match policy::SafeSocksMode::from_raw(mode) {
+            Some(m) => {
+                policy::set_safe_socks_mode(m);
+                log_info!("Safe-socks mode set to {}", mode);
+                0
+            }
+            None => {
+                log_error!("Invalid safe-socks mode: {}", mode);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetDestinationPolicy(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    rules_json: jstring,
+) -> jint {
+    guard::catch(|| {
+        // null clears the policy
+        let json = if rules_json.is_null() {
+            String::new()
+        } else {
+            match jstring_to_string(env, rules_json) {
+                Some(s) => s,
+                None => {
+                    log_error!("Failed to get rules_json string");
+                    return -1;
+                }
+            }
+        };
+
+        if json.trim().is_empty() {
+            policy::set_rule_set(None);
+            log_info!("Destination policy cleared");
+            return 0;
+        }
+
+        match policy::RuleSet::from_json(&json) {
+            Ok(rule_set) => {
+                log_info!("Destination policy loaded ({} rules)", rule_set.len());
+                policy::set_rule_set(Some(rule_set));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid destination policy: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Address Mapping
+// ============================================================================
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetAddressMap(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    map_json: jstring,
+) -> jint {
+    guard::catch(|| {
+        // null clears the table
+        let json = if map_json.is_null() {
+            String::new()
+        } else {
+            match jstring_to_string(env, map_json) {
+                Some(s) => s,
+                None => {
+                    log_error!("Failed to get map_json string");
+                    return -1;
+                }
+            }
+        };
+        let json = if json.trim().is_empty() { "[]" } else { json.as_str() };
+
+        match addrmap::load_json(json) {
+            Ok(count) => {
+                log_info!("Address map loaded ({} mappings)", count);
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address map: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeAddAddressMapping(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    from: jstring,
+    to: jstring,
+) -> jint {
+    guard::catch(|| {
+        let (Some(from), Some(to)) = (jstring_to_string(env, from), jstring_to_string(env, to)) else {
+            log_error!("Failed to get address mapping strings");
+            return -1;
+        };
+
+        match addrmap::insert(&from, &to) {
+            Ok(()) => {
+                log_info!("Address mapping added: {} -> {}", safelog::sensitive(from), safelog::sensitive(to));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeRemoveAddressMapping(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    from: jstring,
+) -> jint {
+    guard::catch(|| {
+        let Some(from) = jstring_to_string(env, from) else {
+            log_error!("Failed to get from string");
+            return -1;
+        };
+
+        match addrmap::remove(&from) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetAddressMap(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        match CString::new(addrmap::snapshot_json()) {
+            Ok(json) => new_string_utf(env, json.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// File Descriptor Streams
+// ============================================================================
+
+/// Open a Tor stream and return it as one end of a Unix socketpair
+///
+/// Blocks until the stream is established. The returned descriptor belongs
+/// to the caller; closing it closes the Tor stream. Windows has no
+/// socketpair, so there this always fails with -3.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeConnectFd(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    host: jstring,
+    port: jint,
+    isolation: jlong,
+) -> jint {
+    guard::catch(|| {
+        let Some(host) = jstring_to_string(env, host) else {
+            log_error!("Failed to get host string");
+            return -1;
+        };
+        let Ok(port) = u16::try_from(port) else {
+            log_error!("Invalid port: {}", port);
+            return -1;
+        };
+
+        connect_fd(&host, port, isolation)
+    })
+}
+
+#[cfg(unix)]
+fn connect_fd(host: &str, port: u16, isolation: jlong) -> jint {
+    let Some((client, runtime)) = client_and_runtime() else {
+        return -2;
+    };
+
+    match fdstream::connect_fd(&runtime, client, host, port, isolation) {
+        Ok(fd) => {
+            log_info!("Tor fd stream open to {}", redact::destination(host, port));
+            fd
+        }
+        Err(fdstream::FdError::Socketpair(e)) => {
+            log_error!("Failed to create socketpair: {:?}", e);
+            -3
+        }
+        Err(fdstream::FdError::RuntimeThread) => {
+            log_error!("Tor fd streams cannot be opened from a Tor runtime thread");
+            -8
+        }
+        Err(fdstream::FdError::Rejected) => {
+            log_error!("Destination {} rejected by policy", redact::destination(host, port));
+            -4
+        }
+        Err(fdstream::FdError::Tor(e)) => {
+            log_error!("Failed to connect through Tor: {:?}", e);
+            match arti_client::HasKind::kind(&e) {
+                arti_client::ErrorKind::OnionServiceMissingClientAuth => -6,
+                arti_client::ErrorKind::OnionServiceWrongClientAuth => -7,
+                _ => -5,
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn connect_fd(_host: &str, _port: u16, _isolation: jlong) -> jint {
+    log_error!("Tor fd streams need Unix sockets, which this platform lacks");
+    -3
+}
+
+// ============================================================================
+// DNS Resolution
+// ============================================================================
+
+/// Deliver a lookup result to `callback.onResolved(long, int, String)`
+///
+/// Status 0 comes with the result JSON, -5 with `{"error": ...}`.
+fn complete_lookup(callback: Arc<JavaCallback>, id: i64, result: Result<String, String>) {
+    let (status, json) = match result {
+        Ok(json) => (0, json),
+        Err(e) => (-5, serde_json::json!({ "error": e }).to_string()),
+    };
+    unsafe { callback.call(&[JavaArg::Long(id), JavaArg::Int(status), JavaArg::Str(&json)]) };
+}
+
+/// Resolve a hostname to all of its addresses through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolve(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    hostname: jstring,
+    isolation: jlong,
+    callback: *mut JObject,
+) -> jlong {
+    guard::catch(|| {
+        let Some(hostname) = jstring_to_string(env, hostname) else {
+            log_error!("Failed to get hostname string");
+            return -1;
+        };
+        let Some(callback) = resolve_callback(env, callback) else {
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        resolve::resolve(&runtime, client, hostname, isolation, move |id, result| {
+            complete_lookup(callback, id, result)
+        })
+    })
+}
+
+/// Resolve an IP address back to hostnames through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolvePtr(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    address: jstring,
+    isolation: jlong,
+    callback: *mut JObject,
+) -> jlong {
+    guard::catch(|| {
+        let Some(address) = jstring_to_string(env, address) else {
+            log_error!("Failed to get address string");
+            return -1;
+        };
+        let Ok(address) = address.parse::<std::net::IpAddr>() else {
+            log_error!("Invalid IP address: {}", safelog::sensitive(address));
+            return -1;
+        };
+        let Some(callback) = resolve_callback(env, callback) else {
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        resolve::resolve_ptr(&runtime, client, address, isolation, move |id, result| {
+            complete_lookup(callback, id, result)
+        })
+    })
+}
+
+/// Cancel a running lookup; `onResolved` is not called for it
+///
+/// Returns 0 on success, -2 if the lookup already finished or never existed.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeResolveCancel(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    lookup_id: jlong,
+) -> jint {
+    guard::catch(|| {
+        if resolve::cancel(lookup_id) { 0 } else { -2 }
+    })
+}
+
+unsafe fn resolve_callback(env: *mut JNIEnv, callback: *mut JObject) -> Option<Arc<JavaCallback>> {
+    if callback.is_null() {
+        log_error!("Resolve callback is null");
+        return None;
+    }
+    match JavaCallback::new(env, callback, c"onResolved", c"(JILjava/lang/String;)V") {
+        Ok(callback) => Some(callback),
+        Err(e) => {
+            log_error!("{}", e);
+            None
+        }
+    }
+}
+
+// ============================================================================
+// Onion Services
+// ============================================================================
+
+/// Publish the onion service `nickname`, forwarding `virtualPort` to `localTarget`
+///
+/// `localTarget` is `host:port` or `unix:/path/to/socket`. Returns the .onion
+/// address, or null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStart(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    virtual_port: jint,
+    local_target: jstring,
+) -> jstring {
+    guard::catch(|| {
+        let (Some(nickname), Some(local_target)) = (jstring_to_string(env, nickname), jstring_to_string(env, local_target)) else {
+            log_error!("Failed to get onion service strings");
+            return std::ptr::null_mut();
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        if ephemeral::is_ephemeral(&nickname) {
+            log_error!("Onion service nickname {} is reserved for ephemeral services", nickname);
+            return std::ptr::null_mut();
+        }
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        let address = match onion::start(&runtime, client, &nickname, virtual_port, &local_target) {
+            Ok(address) => address,
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid onion service configuration: {}", e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::AlreadyRunning) => {
+                log_error!("Onion service {} is already running", nickname);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch onion service {}: {:?}", nickname, e);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::NoAddress) => {
+                log_error!("Onion service {} has no identity key", nickname);
+                return std::ptr::null_mut();
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Onion services cannot be started from a Tor runtime thread");
+                return std::ptr::null_mut();
+            }
+        };
+
+        log_info!("Onion service {} published at {}", nickname, redact::destination(&address, virtual_port));
+        match CString::new(address) {
+            Ok(address) => new_string_utf(env, address.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Stop a hosted onion service
+///
+/// Returns 0 on success, -2 if no service with that nickname is running, -3
+/// for an ephemeral service (use `nativeOnionEphemeralDestroy`).
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStop(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+) -> jint {
+    guard::catch(|| {
+        let Some(nickname) = jstring_to_string(env, nickname) else {
+            log_error!("Failed to get nickname string");
+            return -1;
+        };
+
+        if ephemeral::is_ephemeral(&nickname) {
+            log_error!("Onion service {} is ephemeral - destroy it by its address instead", nickname);
+            return -3;
+        }
+
+        if onion::stop(&nickname) {
+            log_info!("Onion service {} stopped", nickname);
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+/// State of every hosted onion service as a JSON array
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceStatus(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        to_jstring(env, onion::snapshot_json())
+    })
+}
+
+/// Configure the DoS defenses of an onion service from JSON
+///
+/// Returns 0 if in effect, 1 if they apply from the service's next start, -1 on invalid input.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceSetDosParams(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    config_json: jstring,
+) -> jint {
+    guard::catch(|| {
+        let (Some(nickname), Some(config_json)) = (jstring_to_string(env, nickname), jstring_to_string(env, config_json)) else {
+            log_error!("Failed to get DoS defense strings");
+            return -1;
+        };
+
+        match onion::set_dos_params(&nickname, &config_json) {
+            Ok(true) => {
+                log_info!("DoS defenses updated for onion service {}", nickname);
+                0
+            }
+            Ok(false) => {
+                log_info!("DoS defenses for onion service {} apply from its next start", nickname);
+                1
+            }
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                -1
+            }
+            Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid DoS defense configuration: {}", e);
+                -1
+            }
+            Err(_) => -1,
+        }
+    })
+}
+
+/// Cap the proof-of-work effort hosted onion services credit to a client
+///
+/// Read when a client is created, so call it before nativeInitialize. A
+/// negative `max_effort` goes back to the consensus value. Returns 0.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceSetPowMaxEffort(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    max_effort: jint,
+) -> jint {
+    guard::catch(|| {
+        if max_effort >= 0 {
+            onion::set_pow_max_effort(Some(max_effort));
+            log_info!("Onion service PoW effort capped at {}", max_effort);
+        } else {
+            onion::set_pow_max_effort(None);
+            log_info!("Onion service PoW effort cap follows the consensus");
+        }
+        0
+    })
+}
+
+// ============================================================================
+// Ephemeral Onion Services
+// ============================================================================
+
+/// Publish a throwaway onion service whose keys never touch the disk
+///
+/// `ttl_seconds` > 0 tears it down after that long. Returns the .onion
+/// address, or null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionEphemeralCreate(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    virtual_port: jint,
+    local_target: jstring,
+    ttl_seconds: jlong,
+) -> jstring {
+    guard::catch(|| {
+        let Some(local_target) = jstring_to_string(env, local_target) else {
+            log_error!("Failed to get local target string");
+            return std::ptr::null_mut();
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        let ttl = u64::try_from(ttl_seconds)
+            .ok()
+            .filter(|&secs| secs > 0)
+            .map(std::time::Duration::from_secs);
+
+        let Some((_, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        match ephemeral::create(&runtime, virtual_port, &local_target, ttl) {
+            Ok(address) => {
+                log_info!("Ephemeral onion service published at {}", redact::destination(&address, virtual_port));
+                to_jstring(env, address)
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid ephemeral onion service configuration: {}", e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Ephemeral onion services cannot be created from a Tor runtime thread");
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch ephemeral onion service: {:?}", e);
+                std::ptr::null_mut()
+            }
+            Err(_) => {
+                log_error!("Failed to launch ephemeral onion service");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Tear down an ephemeral onion service; returns 0, or -2 if it does not exist
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionEphemeralDestroy(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    onion_address: jstring,
+) -> jint {
+    guard::catch(|| {
+        let Some(onion_address) = jstring_to_string(env, onion_address) else {
+            log_error!("Failed to get onion address string");
+            return -1;
+        };
+
+        if ephemeral::destroy(&onion_address) {
+            log_info!("Ephemeral onion service {} destroyed", safelog::sensitive(onion_address));
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Identity
+// ============================================================================
+
+/// Map an identity error to a status code, logging it
+fn identity_error_code(error: identity::IdentityError, nickname: &str) -> jint {
+    match error {
+        identity::IdentityError::InvalidNickname(e) => {
+            log_error!("Invalid onion service nickname {}: {}", nickname, e);
+            -1
+        }
+        identity::IdentityError::InvalidKey(e) => {
+            log_error!("Invalid onion service identity key: {}", e);
+            -1
+        }
+        identity::IdentityError::NotInitialized => {
+            log_error!("Arti client not initialized - call initialize() first");
+            -2
+        }
+        identity::IdentityError::Exists => {
+            log_error!("Onion service {} already has a different identity", nickname);
+            -3
+        }
+        identity::IdentityError::Running => {
+            log_error!("Onion service {} is running - stop it first", nickname);
+            -4
+        }
+        identity::IdentityError::NotFound => {
+            log_error!("Onion service {} has no identity key", nickname);
+            -5
+        }
+        identity::IdentityError::Decrypt => {
+            log_error!("Failed to decrypt onion service identity (wrong passphrase?)");
+            -6
+        }
+        identity::IdentityError::Keystore(e) => {
+            log_error!("Keystore error: {}", e);
+            -7
+        }
+    }
+}
+
+/// Hand a Rust string to Java, or null
+unsafe fn to_jstring(env: *mut JNIEnv, s: String) -> jstring {
+    match CString::new(s) {
+        Ok(s) => new_string_utf(env, s.as_ptr()),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Import a hex-encoded ed25519 seed (32 bytes) or expanded secret key (64 bytes)
+///
+/// Returns the .onion address, or null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityImport(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    secret_hex: jstring,
+    overwrite: jboolean,
+) -> jstring {
+    guard::catch(|| {
+        let (Some(nickname), Some(secret_hex)) = (jstring_to_string(env, nickname), jstring_to_string(env, secret_hex)) else {
+            log_error!("Failed to get identity strings");
+            return std::ptr::null_mut();
+        };
+        let Ok(secret) = hex::decode(secret_hex.trim()).map(zeroize::Zeroizing::new) else {
+            log_error!("Identity key is not valid hex");
+            return std::ptr::null_mut();
+        };
+
+        match identity::import(&nickname, &secret, overwrite != 0) {
+            Ok(address) => {
+                log_info!("Identity imported for onion service {}", nickname);
+                to_jstring(env, address)
+            }
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Import an identity exported with nativeOnionIdentityExport
+///
+/// Returns the .onion address, or null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityImportEncrypted(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    exported: jstring,
+    passphrase: jstring,
+    overwrite: jboolean,
+) -> jstring {
+    guard::catch(|| {
+        let (Some(nickname), Some(exported), Some(passphrase)) = (
+            jstring_to_string(env, nickname),
+            jstring_to_string(env, exported),
+            jstring_to_string(env, passphrase),
+        ) else {
+            log_error!("Failed to get identity strings");
+            return std::ptr::null_mut();
+        };
+
+        match identity::import_encrypted(&nickname, &exported, passphrase.as_bytes(), overwrite != 0) {
+            Ok(address) => {
+                log_info!("Identity imported for onion service {}", nickname);
+                to_jstring(env, address)
+            }
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Export the identity of `nickname` encrypted with `passphrase`, as hex
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionIdentityExport(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    passphrase: jstring,
+) -> jstring {
+    guard::catch(|| {
+        let (Some(nickname), Some(passphrase)) = (jstring_to_string(env, nickname), jstring_to_string(env, passphrase)) else {
+            log_error!("Failed to get identity strings");
+            return std::ptr::null_mut();
+        };
+
+        match identity::export_encrypted(&nickname, passphrase.as_bytes()) {
+            Ok(exported) => to_jstring(env, exported),
+            Err(e) => {
+                identity_error_code(e, &nickname);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Compute the v3 .onion address for a hex-encoded 32-byte ed25519 public key
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionAddressFromPublicKey(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    public_key_hex: jstring,
+) -> jstring {
+    guard::catch(|| {
+        let Some(public_key) = jstring_to_string(env, public_key_hex).and_then(|s| hex::decode(s.trim()).ok()) else {
+            log_error!("Public key is not valid hex");
+            return std::ptr::null_mut();
+        };
+
+        match identity::onion_address_for_public_key(&public_key) {
+            Ok(address) => to_jstring(env, address),
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Client Authorization
+// ============================================================================
+
+/// Authorize a client, by its `descriptor:x25519:...` public key, to discover `nickname`
+///
+/// Returns 0 on success or a negative identity error code.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceAuthorizeClient(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    client_nickname: jstring,
+    public_key: jstring,
+) -> jint {
+    guard::catch(|| {
+        let (Some(nickname), Some(client_nickname), Some(public_key)) = (
+            jstring_to_string(env, nickname),
+            jstring_to_string(env, client_nickname),
+            jstring_to_string(env, public_key),
+        ) else {
+            log_error!("Failed to get client authorization strings");
+            return -1;
+        };
+
+        match clientauth::authorize_client(&nickname, &client_nickname, &public_key) {
+            Ok(()) => {
+                log_info!("Client {} authorized for onion service {}", client_nickname, nickname);
+                0
+            }
+            Err(e) => identity_error_code(e, &nickname),
+        }
+    })
+}
+
+/// Revoke a client; returns 0 on success, -5 if it was not authorized
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionServiceRevokeClient(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    nickname: jstring,
+    client_nickname: jstring,
+) -> jint {
+    guard::catch(|| {
+        let (Some(nickname), Some(client_nickname)) = (jstring_to_string(env, nickname), jstring_to_string(env, client_nickname)) else {
+            log_error!("Failed to get client authorization strings");
+            return -1;
+        };
+
+        match clientauth::revoke_client(&nickname, &client_nickname) {
+            Ok(true) => {
+                log_info!("Client {} revoked for onion service {}", client_nickname, nickname);
+                0
+            }
+            Ok(false) => -5,
+            Err(e) => identity_error_code(e, &nickname),
+        }
+    })
+}
+
+/// Store the client key for a restricted onion service
+///
+/// Returns the matching public key for the service operator, or null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthSet(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    onion_address: jstring,
+    secret_key: jstring,
+) -> jstring {
+    guard::catch(|| {
+        let (Some(onion_address), Some(secret_key)) = (jstring_to_string(env, onion_address), jstring_to_string(env, secret_key)) else {
+            log_error!("Failed to get client key strings");
+            return std::ptr::null_mut();
+        };
+
+        match clientauth::set_client_key(&onion_address, &secret_key) {
+            Ok(public_key) => to_jstring(env, public_key),
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Generate and store a new client key; returns its public key, or null on failure
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthGenerate(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    onion_address: jstring,
+) -> jstring {
+    guard::catch(|| {
+        let Some(onion_address) = jstring_to_string(env, onion_address) else {
+            log_error!("Failed to get onion address string");
+            return std::ptr::null_mut();
+        };
+
+        match clientauth::generate_client_key(&onion_address) {
+            Ok(public_key) => to_jstring(env, public_key),
+            Err(e) => {
+                identity_error_code(e, "");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Forget the client key for an onion service; returns 0 on success, -5 if none was stored
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeOnionClientAuthRemove(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    onion_address: jstring,
+) -> jint {
+    guard::catch(|| {
+        let Some(onion_address) = jstring_to_string(env, onion_address) else {
+            log_error!("Failed to get onion address string");
+            return -1;
+        };
+
+        match clientauth::remove_client_key(&onion_address) {
+            Ok(true) => 0,
+            Ok(false) => -5,
+            Err(e) => identity_error_code(e, ""),
+        }
+    })
+}
+
+// ============================================================================
+// Traffic Accounting
+// ============================================================================
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetTrafficSnapshot(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        match CString::new(traffic::snapshot_json()) {
+            Ok(json) => new_string_utf(env, json.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetTrafficRecordDestinations(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    enabled: jboolean,
+) {
+    guard::catch(|| {
+        traffic::set_record_destinations(enabled != 0);
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeSetStreamEvents(
+    _env: *mut JNIEnv,
+    _class: *mut JClass,
+    enabled: jboolean,
+) {
+    guard::catch(|| {
+        streamevents::set_enabled(enabled != 0);
+    })
+}
+
+// ============================================================================
+// Circuits
+// ============================================================================
+
+/// Get the circuits behind active SOCKS streams as a JSON array
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetCircuits(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match CString::new(circuits::list_json(client.as_deref())) {
+            Ok(json) => new_string_utf(env, json.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Get the circuit behind a stream, by its id in the traffic snapshot
+///
+/// Returns null if the stream is not active.
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetStreamCircuit(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+    stream_id: jlong,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match circuits::stream_json(client.as_deref(), stream_id as u64).map(CString::new) {
+            Some(Ok(json)) => new_string_utf(env, json.as_ptr()),
+            _ => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Get a JSON snapshot of the Tor subsystem's metrics (see `arti_get_metrics`)
+#[no_mangle]
+pub unsafe extern "C" fn Java_com_bitchat_tor_TorManager_nativeGetMetrics(
+    env: *mut JNIEnv,
+    _class: *mut JClass,
+) -> jstring {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match CString::new(metrics::snapshot_json(client.as_deref())) {
+            Ok(json) => new_string_utf(env, json.as_ptr()),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Get the initialized client and a handle to the runtime it runs on
+fn client_and_runtime() -> Option<(Arc<TorClient<PreferredRuntime>>, tokio::runtime::Handle)> {
+    let client = ARTI_CLIENT.lock_or_recover().as_ref().map(Arc::clone);
+    let runtime = TOKIO_RUNTIME.lock_or_recover().as_ref().map(|rt| rt.handle().clone());
+    match (client, runtime) {
+        (Some(client), Some(runtime)) => Some((client, runtime)),
+        _ => {
+            log_error!("Arti client not initialized - call initialize() first");
+            None
+        }
+    }
 }
@@ -0,0 +1,266 @@
+//! Metrics snapshot of the Tor subsystem
+//!
+//! One JSON object with everything a status screen or bug report needs:
+//! bootstrap progress, the consensus validity window, usable relays, circuit
+//! counts, proxied streams, bytes transferred and uptime. arti does not
+//! expose circuit counts, so they are taken from its traces: a circuit is
+//! open while its tunnel reactor runs, and builds are counted when the
+//! circuit manager reports them. That makes them best effort, and the
+//! snapshot says so: they depend on arti's exact messages (pinned by the
+//! tests below for the arti in `ARTI_VERSION`), and successes are only
+//! traced while arti keeps circuit timeout statistics, which it does not
+//! when another process holds its state lock.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use arti_client::TorClient;
+use serde::Serialize;
+use tor_netdir::Timeliness;
+use tor_rtcompat::PreferredRuntime;
+use tracing::field::{Field, Visit};
+use tracing::subscriber::Interest;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::filter::Filtered;
+use tracing_subscriber::layer::{Context, Filter, Layer};
+
+use crate::guard::LockExt;
+
+/// Traces of circuits starting and stopping
+const REACTOR_TARGET: &str = "tor_proto::client::reactor";
+/// Traces of completed circuit builds
+const TIMEOUTS_TARGET: &str = "tor_circmgr::timeouts::pareto";
+/// Traces of failed circuit builds
+const CIRCMGR_TARGET: &str = "tor_circmgr::mgr";
+
+/// When the client was created, for the uptime
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+static CIRCUITS_RUNNING: AtomicU64 = AtomicU64::new(0);
+static CIRCUITS_STOPPED: AtomicU64 = AtomicU64::new(0);
+static BUILD_SUCCESSES: AtomicU64 = AtomicU64::new(0);
+static BUILD_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize)]
+pub struct MetricsSnapshot {
+    pub timestamp_ms: u64,
+    pub uptime_seconds: Option<u64>,
+    pub bootstrap: Option<BootstrapMetrics>,
+    pub consensus: Option<ConsensusMetrics>,
+    pub usable_relays: Option<usize>,
+    pub circuits: CircuitMetrics,
+    pub streams: StreamMetrics,
+}
+
+#[derive(Serialize)]
+pub struct BootstrapMetrics {
+    pub percent: u8,
+    pub ready_for_traffic: bool,
+    pub blocked: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConsensusMetrics {
+    pub valid_after_ms: u64,
+    pub fresh_until_ms: u64,
+    pub valid_until_ms: u64,
+}
+
+/// Circuit counts, taken from arti's traces
+#[derive(Serialize)]
+pub struct CircuitMetrics {
+    pub open: u64,
+    pub build_successes: u64,
+    pub build_failures: u64,
+    /// Always true: the counts above may miss circuits, see the module docs
+    pub best_effort: bool,
+}
+
+#[derive(Serialize)]
+pub struct StreamMetrics {
+    pub active: usize,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+/// Start counting uptime (called once the client is created)
+pub fn mark_started() {
+    *STARTED.lock_or_recover() = Some(Instant::now());
+}
+
+/// Serialize the current metrics as JSON
+///
+/// Without a `client`, the bootstrap, consensus and relay fields are `null`.
+pub fn snapshot_json(client: Option<&TorClient<PreferredRuntime>>) -> String {
+    serde_json::to_string(&snapshot(client)).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// The current metrics
+pub fn snapshot(client: Option<&TorClient<PreferredRuntime>>) -> MetricsSnapshot {
+    let bootstrap = client.map(|client| {
+        let status = client.bootstrap_status();
+        BootstrapMetrics {
+            percent: (status.as_frac() * 100.0) as u8,
+            ready_for_traffic: status.ready_for_traffic(),
+            blocked: status.blocked().map(|blockage| blockage.to_string()),
+        }
+    });
+
+    let netdir = client.and_then(|client| client.dirmgr().netdir(Timeliness::Unchecked).ok());
+    let consensus = netdir.as_ref().map(|netdir| {
+        let lifetime = netdir.lifetime();
+        ConsensusMetrics {
+            valid_after_ms: unix_ms(lifetime.valid_after()),
+            fresh_until_ms: unix_ms(lifetime.fresh_until()),
+            valid_until_ms: unix_ms(lifetime.valid_until()),
+        }
+    });
+    let usable_relays = netdir.as_ref().map(|netdir| netdir.relays().count());
+
+    // Stopped first: a circuit closing in between only makes `open` lag
+    let stopped = CIRCUITS_STOPPED.load(Ordering::Relaxed);
+    let running = CIRCUITS_RUNNING.load(Ordering::Relaxed);
+    let (bytes_up, bytes_down) = crate::traffic::total_bytes();
+    MetricsSnapshot {
+        timestamp_ms: unix_ms(SystemTime::now()),
+        uptime_seconds: STARTED.lock_or_recover().map(|started| started.elapsed().as_secs()),
+        bootstrap,
+        consensus,
+        usable_relays,
+        circuits: CircuitMetrics {
+            open: running.saturating_sub(stopped),
+            build_successes: BUILD_SUCCESSES.load(Ordering::Relaxed),
+            build_failures: BUILD_FAILURES.load(Ordering::Relaxed),
+            best_effort: true,
+        },
+        streams: StreamMetrics {
+            active: crate::traffic::active_stream_count(),
+            bytes_up,
+            bytes_down,
+        },
+    }
+}
+
+// ============================================================================
+// Circuit Counting
+// ============================================================================
+
+/// Layer counting circuits from arti's traces
+pub struct CircuitLayer;
+
+/// Lets through the exact targets above only, not their submodules, which
+/// trace every cell
+pub struct CircuitTraces;
+
+/// `CircuitLayer`, limited to the traces it counts
+pub fn layer<S>() -> Filtered<CircuitLayer, CircuitTraces, S>
+where
+    S: Subscriber,
+{
+    CircuitLayer.with_filter(CircuitTraces)
+}
+
+impl CircuitTraces {
+    fn wanted(metadata: &Metadata<'_>) -> bool {
+        match metadata.target() {
+            REACTOR_TARGET | TIMEOUTS_TARGET => true,
+            CIRCMGR_TARGET => *metadata.level() <= Level::DEBUG,
+            _ => false,
+        }
+    }
+}
+
+impl<S> Filter<S> for CircuitTraces {
+    fn enabled(&self, metadata: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        Self::wanted(metadata)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if Self::wanted(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for CircuitLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let message = message.0;
+
+        let counter = match event.metadata().target() {
+            REACTOR_TARGET if message == "Running tunnel reactor" => &CIRCUITS_RUNNING,
+            // Followed by the error, if the reactor stopped on one
+            REACTOR_TARGET if message.starts_with("Tunnel reactor stopped") => &CIRCUITS_STOPPED,
+            TIMEOUTS_TARGET if message == "Circuit creation success" => &BUILD_SUCCESSES,
+            // "Circuit attempt N failed."
+            CIRCMGR_TARGET if message.starts_with("Circuit attempt ") && message.ends_with(" failed.") => {
+                &BUILD_FAILURES
+            }
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Collects the formatted message of an event
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    //! The traces below are copied verbatim from arti 1.7.0 (crates 0.36):
+    //! tor-proto `client/reactor.rs` (`TunnelReactor::run`), tor-circmgr
+    //! `timeouts/pareto.rs` (`note_hop_completed`) and `mgr.rs`
+    //! (`take_action`). When updating arti, check they are still emitted as
+    //! written, from the same modules and at the same levels.
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    fn counts() -> [u64; 4] {
+        [&CIRCUITS_RUNNING, &CIRCUITS_STOPPED, &BUILD_SUCCESSES, &BUILD_FAILURES].map(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn counts_arti_circuit_traces() {
+        let subscriber = tracing_subscriber::registry().with(layer());
+        let before = counts();
+        tracing::subscriber::with_default(subscriber, || {
+            const MSG: &str = "Tunnel reactor stopped";
+            let tunnel_id = 7;
+            tracing::trace!(target: REACTOR_TARGET, %tunnel_id, "Running tunnel reactor");
+            tracing::trace!(target: REACTOR_TARGET, %tunnel_id, "{MSG}");
+            // debug_report! appends the error to the message
+            tracing::debug!(target: REACTOR_TARGET, %tunnel_id, "{MSG}: channel closed");
+            tracing::trace!(target: TIMEOUTS_TARGET, hop = 2, "Circuit creation success");
+            tracing::debug!(target: CIRCMGR_TARGET, "Circuit attempt {} failed.", 1);
+
+            // Other messages, submodules and levels are not counted
+            tracing::trace!(target: REACTOR_TARGET, "Tunnel reactor shutting down: all circuits have closed");
+            tracing::trace!(target: "tor_proto::client::reactor::circuit", "Running tunnel reactor");
+            tracing::trace!(target: CIRCMGR_TARGET, "Circuit attempt 1 failed.");
+        });
+        let after = counts();
+
+        let added: Vec<u64> = after.iter().zip(before).map(|(after, before)| after - before).collect();
+        assert_eq!(added, [1, 2, 1, 1]);
+    }
+}
@@ -0,0 +1,324 @@
+//! Destination policy for the SOCKS proxy
+//!
+//! Checks run in `handle_socks_connection` after the request is parsed and
+//! before anything is sent into Tor: the safe-socks IP-literal check and a
+//! host-supplied rule set that can be swapped at runtime.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+
+use crate::guard::RwLockExt;
+
+/// How IP-literal destinations are treated
+///
+/// An application that asks the proxy for an IP address has usually resolved
+/// the name outside Tor already, leaking the lookup.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SafeSocksMode {
+    /// Accept IP literals silently
+    Off = 0,
+    /// Accept IP literals but report them
+    Warn = 1,
+    /// Refuse IP literals and report them
+    Reject = 2,
+}
+
+impl SafeSocksMode {
+    pub fn from_raw(raw: i32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Off),
+            1 => Some(Self::Warn),
+            2 => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Current safe-socks mode (off unless the host opts in)
+static SAFE_SOCKS_MODE: AtomicU8 = AtomicU8::new(SafeSocksMode::Off as u8);
+
+pub fn set_safe_socks_mode(mode: SafeSocksMode) {
+    SAFE_SOCKS_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn safe_socks_mode() -> SafeSocksMode {
+    SafeSocksMode::from_raw(SAFE_SOCKS_MODE.load(Ordering::Relaxed) as i32).unwrap_or(SafeSocksMode::Off)
+}
+
+/// Whether a SOCKS destination host is an IP literal rather than a hostname
+///
+/// Covers ATYP 0x01/0x04 as well as addresses sent as ATYP 0x03 strings.
+pub fn is_ip_literal(host: &str) -> bool {
+    host.parse::<IpAddr>().is_ok()
+}
+
+// ============================================================================
+// Rule Set
+// ============================================================================
+
+/// What happens to a destination matched by a rule
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Reject,
+}
+
+/// Port or port range as written in the rule JSON: `443` or `"8000-8100"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PortSpec {
+    Port(u16),
+    Range(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    action: Action,
+    #[serde(default)]
+    host_globs: Vec<String>,
+    #[serde(default)]
+    host_suffixes: Vec<String>,
+    #[serde(default)]
+    ports: Vec<PortSpec>,
+    #[serde(default)]
+    onion_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRuleSet {
+    #[serde(default = "default_action")]
+    default: Action,
+    #[serde(default)]
+    rules: Vec<RawRule>,
+}
+
+fn default_action() -> Action {
+    Action::Allow
+}
+
+/// A single rule; every non-empty criterion has to match
+struct Rule {
+    action: Action,
+    host_globs: Vec<String>,
+    host_suffixes: Vec<String>,
+    ports: Vec<(u16, u16)>,
+    onion_only: bool,
+}
+
+impl Rule {
+    fn matches(&self, host: &str, port: u16) -> bool {
+        if self.onion_only && !host.ends_with(".onion") {
+            return false;
+        }
+        if !self.host_globs.is_empty() && !self.host_globs.iter().any(|g| glob_matches(g, host)) {
+            return false;
+        }
+        if !self.host_suffixes.is_empty() && !self.host_suffixes.iter().any(|s| suffix_matches(s, host)) {
+            return false;
+        }
+        if !self.ports.is_empty() && !self.ports.iter().any(|&(lo, hi)| lo <= port && port <= hi) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Ordered rules, evaluated first-match-wins, with a fallback action
+pub struct RuleSet {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Parse a rule set from JSON
+    ///
+    /// ```json
+    /// {
+    ///   "default": "reject",
+    ///   "rules": [
+    ///     { "action": "allow", "onion_only": true },
+    ///     { "action": "allow", "host_suffixes": ["relay.example.com"], "ports": [443, 80] },
+    ///     { "action": "allow", "host_globs": ["nostr-*.example.net"], "ports": ["8000-8100"] }
+    ///   ]
+    /// }
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let raw: RawRuleSet = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let mut rules = Vec::with_capacity(raw.rules.len());
+        for raw_rule in raw.rules {
+            let mut ports = Vec::with_capacity(raw_rule.ports.len());
+            for spec in raw_rule.ports {
+                ports.push(parse_port_spec(&spec)?);
+            }
+            rules.push(Rule {
+                action: raw_rule.action,
+                host_globs: raw_rule.host_globs.iter().map(|h| normalize_host(h)).collect(),
+                host_suffixes: raw_rule.host_suffixes.iter().map(|h| normalize_host(h)).collect(),
+                ports,
+                onion_only: raw_rule.onion_only,
+            });
+        }
+
+        Ok(Self { default: raw.default, rules })
+    }
+
+    /// Decide what to do with `host:port`
+    pub fn evaluate(&self, host: &str, port: u16) -> Action {
+        let host = normalize_host(host);
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(&host, port))
+            .map(|rule| rule.action)
+            .unwrap_or(self.default)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+/// Active rule set (`None` allows everything)
+static RULE_SET: RwLock<Option<Arc<RuleSet>>> = RwLock::new(None);
+
+/// Replace the active rule set; `None` removes all restrictions
+pub fn set_rule_set(rule_set: Option<RuleSet>) {
+    *RULE_SET.write_or_recover() = rule_set.map(Arc::new);
+}
+
+/// Whether the active rule set allows a connection to `host:port`
+pub fn is_allowed(host: &str, port: u16) -> bool {
+    let rule_set = RULE_SET.read_or_recover().clone();
+    match rule_set {
+        Some(rules) => rules.evaluate(host, port) == Action::Allow,
+        None => true,
+    }
+}
+
+fn parse_port_spec(spec: &PortSpec) -> Result<(u16, u16), String> {
+    match spec {
+        PortSpec::Port(p) => Ok((*p, *p)),
+        PortSpec::Range(r) => {
+            let parse = |s: &str| s.trim().parse::<u16>().map_err(|_| format!("Invalid port range: {}", r));
+            let (lo, hi) = match r.split_once('-') {
+                Some((lo, hi)) => (parse(lo)?, parse(hi)?),
+                None => {
+                    let p = parse(r)?;
+                    (p, p)
+                }
+            };
+            if lo > hi {
+                return Err(format!("Invalid port range: {}", r));
+            }
+            Ok((lo, hi))
+        }
+    }
+}
+
+/// Lowercase and drop a trailing root dot
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// `example.com` matches itself and any subdomain; a leading dot is optional
+fn suffix_matches(suffix: &str, host: &str) -> bool {
+    let suffix = suffix.trim_start_matches('.');
+    host == suffix || host.strip_suffix(suffix).is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Glob match supporting `*` (any run of characters) and `?` (one character)
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = backtrack {
+            pi = star_pi + 1;
+            ti = star_ti + 1;
+            backtrack = Some((star_pi, star_ti + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("nostr-*.example.net", "nostr-1.example.net"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a?c", "abc"));
+        assert!(!glob_matches("a?c", "ac"));
+        // A later `*` has to backtrack over an earlier partial match
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b", "acbd"));
+        assert!(!glob_matches("*.example.net", "example.net"));
+    }
+
+    #[test]
+    fn is_ip_literal_only_accepts_addresses() {
+        assert!(is_ip_literal("127.0.0.1"));
+        assert!(is_ip_literal("::1"));
+        assert!(is_ip_literal("0000:0000:0000:0000:0000:0000:0000:0001"));
+        assert!(!is_ip_literal("[::1]"));
+        assert!(!is_ip_literal("1.2.3"));
+        assert!(!is_ip_literal("example.com"));
+        assert!(!is_ip_literal(""));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rule_set = RuleSet::from_json(
+            r#"{
+                "default": "reject",
+                "rules": [
+                    { "action": "reject", "host_suffixes": ["bad.example.com"] },
+                    { "action": "allow", "host_suffixes": [".example.com"], "ports": [443, "8000-8100"] },
+                    { "action": "reject", "onion_only": true, "ports": [80] },
+                    { "action": "allow", "onion_only": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rule_set.len(), 4);
+        assert!(rule_set.evaluate("www.bad.example.com", 443) == Action::Reject);
+        assert!(rule_set.evaluate("WWW.Example.com.", 443) == Action::Allow);
+        assert!(rule_set.evaluate("example.com", 8050) == Action::Allow);
+        assert!(rule_set.evaluate("notexample.com", 443) == Action::Reject);
+        assert!(rule_set.evaluate("www.example.com", 80) == Action::Reject);
+        assert!(rule_set.evaluate("xyz.onion", 80) == Action::Reject);
+        assert!(rule_set.evaluate("xyz.onion", 443) == Action::Allow);
+    }
+
+    #[test]
+    fn rejects_invalid_rule_sets() {
+        assert!(RuleSet::from_json(r#"{ "rules": [{ "action": "allow", "ports": ["8100-8000"] }] }"#).is_err());
+        assert!(RuleSet::from_json(r#"{ "rules": [{ "action": "allow", "ports": ["80-http"] }] }"#).is_err());
+        assert!(RuleSet::from_json(r#"{ "rules": [{ "action": "deny" }] }"#).is_err());
+        assert!(RuleSet::from_json(r#"{ "rules": [{ "action": "allow", "hosts": [] }] }"#).is_err());
+        assert!(RuleSet::from_json("{}").unwrap().evaluate("example.com", 443) == Action::Allow);
+    }
+}
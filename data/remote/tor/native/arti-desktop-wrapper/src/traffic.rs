@@ -0,0 +1,253 @@
+//! SOCKS traffic accounting
+//!
+//! Every proxied stream gets byte counters that are updated as data flows,
+//! and all streams feed process-wide totals. A JSON snapshot of both is
+//! exposed through the FFI so the settings screen can show live throughput
+//! and data usage.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::guard::LockExt;
+
+// ============================================================================
+// Global State
+// ============================================================================
+
+/// Whether stream destinations may be recorded (privacy settings opt-in)
+static RECORD_DESTINATIONS: AtomicBool = AtomicBool::new(false);
+
+/// Next stream identifier handed out by `new_stream_id`
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Streams that are currently being proxied, keyed by stream id
+static ACTIVE_STREAMS: Mutex<BTreeMap<u64, Arc<StreamCounters>>> = Mutex::new(BTreeMap::new());
+
+/// Process-wide totals (never reset, survive proxy restarts)
+static TOTAL_BYTES_UP: AtomicU64 = AtomicU64::new(0);
+static TOTAL_BYTES_DOWN: AtomicU64 = AtomicU64::new(0);
+static STREAMS_OPENED: AtomicU64 = AtomicU64::new(0);
+static STREAMS_CLOSED: AtomicU64 = AtomicU64::new(0);
+
+// ============================================================================
+// Per-stream Counters
+// ============================================================================
+
+/// Direction of a byte transfer, seen from the local application
+#[derive(Clone, Copy)]
+pub enum Direction {
+    /// Application -> Tor
+    Up,
+    /// Tor -> application
+    Down,
+}
+
+/// Counters for a single proxied stream
+pub struct StreamCounters {
+    id: u64,
+    destination: Option<String>,
+    started: Instant,
+    started_unix_ms: u64,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+}
+
+impl StreamCounters {
+    fn add(&self, direction: Direction, n: u64) {
+        match direction {
+            Direction::Up => {
+                self.bytes_up.fetch_add(n, Ordering::Relaxed);
+                TOTAL_BYTES_UP.fetch_add(n, Ordering::Relaxed);
+            }
+            Direction::Down => {
+                self.bytes_down.fetch_add(n, Ordering::Relaxed);
+                TOTAL_BYTES_DOWN.fetch_add(n, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Bytes sent and received so far
+    pub fn bytes(&self) -> (u64, u64) {
+        (self.bytes_up.load(Ordering::Relaxed), self.bytes_down.load(Ordering::Relaxed))
+    }
+
+    fn snapshot(&self) -> StreamSnapshot {
+        StreamSnapshot {
+            id: self.id,
+            destination: self.destination.clone(),
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            started_unix_ms: self.started_unix_ms,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// Registration of a live stream; unregisters itself when dropped
+pub struct StreamHandle(Arc<StreamCounters>);
+
+impl StreamHandle {
+    pub fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    pub fn counters(&self) -> Arc<StreamCounters> {
+        Arc::clone(&self.0)
+    }
+
+    pub fn bytes_up(&self) -> u64 {
+        self.0.bytes_up.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_down(&self) -> u64 {
+        self.0.bytes_down.load(Ordering::Relaxed)
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.0.started.elapsed().as_millis() as u64
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        ACTIVE_STREAMS.lock_or_recover().remove(&self.0.id);
+        STREAMS_CLOSED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Allocate an id for a stream about to be opened
+pub fn new_stream_id() -> u64 {
+    NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Register a new stream to `host:port` under an id from `new_stream_id`
+///
+/// The destination is only kept if recording destinations is enabled.
+pub fn open_stream(id: u64, host: &str, port: u16) -> StreamHandle {
+    let destination = if RECORD_DESTINATIONS.load(Ordering::Relaxed) {
+        Some(format!("{}:{}", host, port))
+    } else {
+        None
+    };
+
+    let counters = Arc::new(StreamCounters {
+        id,
+        destination,
+        started: Instant::now(),
+        started_unix_ms: unix_time_ms(),
+        bytes_up: AtomicU64::new(0),
+        bytes_down: AtomicU64::new(0),
+    });
+
+    ACTIVE_STREAMS.lock_or_recover().insert(counters.id, Arc::clone(&counters));
+    STREAMS_OPENED.fetch_add(1, Ordering::Relaxed);
+
+    StreamHandle(counters)
+}
+
+/// Enable or disable recording of stream destinations
+pub fn set_record_destinations(enabled: bool) {
+    RECORD_DESTINATIONS.store(enabled, Ordering::Relaxed);
+}
+
+// ============================================================================
+// Counting Reader
+// ============================================================================
+
+/// `AsyncRead` adapter that adds every byte read to a stream's counters
+pub struct CountingReader<R> {
+    inner: R,
+    counters: Arc<StreamCounters>,
+    direction: Direction,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, counters: Arc<StreamCounters>, direction: Direction) -> Self {
+        Self { inner, counters, direction }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let n = (buf.filled().len() - before) as u64;
+            self.counters.add(self.direction, n);
+        }
+        result
+    }
+}
+
+// ============================================================================
+// Snapshot
+// ============================================================================
+
+#[derive(Serialize)]
+struct StreamSnapshot {
+    id: u64,
+    destination: Option<String>,
+    bytes_up: u64,
+    bytes_down: u64,
+    started_unix_ms: u64,
+    duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct TrafficSnapshot {
+    timestamp_ms: u64,
+    total_bytes_up: u64,
+    total_bytes_down: u64,
+    streams_opened: u64,
+    streams_closed: u64,
+    active_streams: Vec<StreamSnapshot>,
+}
+
+/// Serialize current per-stream counters and totals as JSON
+pub fn snapshot_json() -> String {
+    let active_streams = ACTIVE_STREAMS
+        .lock_or_recover()
+        .values()
+        .map(|c| c.snapshot())
+        .collect();
+
+    let snapshot = TrafficSnapshot {
+        timestamp_ms: unix_time_ms(),
+        total_bytes_up: TOTAL_BYTES_UP.load(Ordering::Relaxed),
+        total_bytes_down: TOTAL_BYTES_DOWN.load(Ordering::Relaxed),
+        streams_opened: STREAMS_OPENED.load(Ordering::Relaxed),
+        streams_closed: STREAMS_CLOSED.load(Ordering::Relaxed),
+        active_streams,
+    };
+
+    serde_json::to_string(&snapshot).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Total bytes sent and received over all streams so far
+pub fn total_bytes() -> (u64, u64) {
+    (TOTAL_BYTES_UP.load(Ordering::Relaxed), TOTAL_BYTES_DOWN.load(Ordering::Relaxed))
+}
+
+/// Number of streams currently being proxied
+pub fn active_stream_count() -> usize {
+    ACTIVE_STREAMS.lock_or_recover().len()
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
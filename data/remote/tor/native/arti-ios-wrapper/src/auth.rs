@@ -0,0 +1,135 @@
+//! Loopback client authentication
+//!
+//! When enabled, a random cookie is written to `<state dir>/socks_auth_cookie`
+//! (mode 0600 on Unix) and the SOCKS proxy only accepts clients that present
+//! it as the RFC 1929 username/password password. Processes that cannot read
+//! the state dir therefore cannot use the proxy.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::guard::LockExt;
+
+/// File name of the auth cookie inside the state dir
+pub const COOKIE_FILE_NAME: &str = "socks_auth_cookie";
+
+/// Cookie length in random bytes (hex-encoded when written out)
+const COOKIE_LEN: usize = 32;
+
+/// Cookie clients must present, `None` while authentication is disabled
+static AUTH_COOKIE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Path of the cookie file for the given state dir
+pub fn cookie_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(COOKIE_FILE_NAME)
+}
+
+/// Generate a fresh cookie, write it into `state_dir` and require it from now on
+pub fn enable(state_dir: &Path) -> io::Result<PathBuf> {
+    let mut bytes = [0u8; COOKIE_LEN];
+    getrandom::fill(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    let cookie = hex::encode(bytes);
+
+    let path = cookie_path(state_dir);
+    write_private_file(&path, cookie.as_bytes())?;
+
+    *AUTH_COOKIE.lock_or_recover() = Some(cookie);
+    Ok(path)
+}
+
+/// Stop requiring authentication and remove the cookie file
+pub fn disable(state_dir: &Path) {
+    *AUTH_COOKIE.lock_or_recover() = None;
+    fs::remove_file(cookie_path(state_dir)).ok();
+}
+
+/// Whether clients currently have to authenticate
+pub fn is_required() -> bool {
+    AUTH_COOKIE.lock_or_recover().is_some()
+}
+
+/// Check a presented credential against the current cookie
+///
+/// Returns `true` when authentication is disabled.
+pub fn verify(presented: &[u8]) -> bool {
+    match AUTH_COOKIE.lock_or_recover().as_ref() {
+        Some(cookie) => constant_time_eq(cookie.as_bytes(), presented),
+        None => true,
+    }
+}
+
+/// Compare two byte strings without an early exit on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Write `contents` to `path`, readable only by the owner on Unix
+#[cfg(unix)]
+pub fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies on creation; tighten a pre-existing file as well
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Extract the password from an RFC 1929 username/password request
+///
+/// Layout: VER(1) ULEN(1) UNAME(ULEN) PLEN(1) PASSWD(PLEN)
+pub fn socks_password(msg: &[u8]) -> Option<&[u8]> {
+    if msg.len() < 2 || msg[0] != 0x01 {
+        return None;
+    }
+    let plen_pos = 2 + msg[1] as usize;
+    let plen = *msg.get(plen_pos)? as usize;
+    msg.get(plen_pos + 1..plen_pos + 1 + plen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_rfc1929_password() {
+        assert_eq!(socks_password(b"\x01\x04user\x06secret"), Some(&b"secret"[..]));
+        assert_eq!(socks_password(b"\x01\x00\x00"), Some(&b""[..]));
+        // Wrong version, or cut off inside the username or password
+        assert_eq!(socks_password(b"\x05\x04user\x06secret"), None);
+        assert_eq!(socks_password(b"\x01"), None);
+        assert_eq!(socks_password(b"\x01\x04use"), None);
+        assert_eq!(socks_password(b"\x01\x04user\x06secre"), None);
+    }
+
+    #[test]
+    fn cookie_must_match_exactly() {
+        assert!(constant_time_eq(b"cookie", b"cookie"));
+        assert!(!constant_time_eq(b"cookie", b"cookie!"));
+        assert!(!constant_time_eq(b"cookie", b"cooKie"));
+        assert!(!constant_time_eq(b"cookie", b""));
+
+        *AUTH_COOKIE.lock_or_recover() = Some("cookie".to_string());
+        let (accepted, rejected) = (verify(b"cookie"), verify(b"cookiE"));
+        *AUTH_COOKIE.lock_or_recover() = None;
+        assert!(accepted && !rejected);
+        assert!(verify(b"anything"));
+    }
+}
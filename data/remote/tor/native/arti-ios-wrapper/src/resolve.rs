@@ -0,0 +1,169 @@
+//! DNS resolution through Tor
+//!
+//! Forward and reverse lookups are answered by an exit relay, so names can be
+//! resolved (for NIP-05 checks, relay health probes) without touching the
+//! system resolver. Lookups run on the Tor runtime and report back through a
+//! completion closure, which the FFI layers adapt to their callback styles.
+//!
+//! Every answer has a `ttl` field, which is always `TTL_UNKNOWN` for now:
+//! arti drops TTLs when it parses the RESOLVED cell (`ClientTunnel::resolve`
+//! keeps only the answers), and the cell itself is not reachable through its
+//! public API. The field is there so hosts need no change once arti has them.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use arti_client::TorClient;
+use serde::Serialize;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+use crate::isolation;
+
+/// Next id handed out by `resolve` / `resolve_ptr`
+static NEXT_LOOKUP_ID: AtomicI64 = AtomicI64::new(1);
+
+/// Running lookups, so they can be cancelled
+static LOOKUPS: Mutex<BTreeMap<i64, tokio::task::AbortHandle>> = Mutex::new(BTreeMap::new());
+
+/// `ttl` of an answer whose TTL is not known
+const TTL_UNKNOWN: i64 = -1;
+
+#[derive(Serialize)]
+struct AddressEntry {
+    address: String,
+    /// Seconds the answer may be cached, or `TTL_UNKNOWN`
+    ttl: i64,
+}
+
+#[derive(Serialize)]
+struct HostnameEntry {
+    hostname: String,
+    /// Seconds the answer may be cached, or `TTL_UNKNOWN`
+    ttl: i64,
+}
+
+#[derive(Serialize)]
+struct ResolveResult {
+    query: String,
+    addresses: Vec<AddressEntry>,
+}
+
+#[derive(Serialize)]
+struct ResolvePtrResult {
+    query: String,
+    hostnames: Vec<HostnameEntry>,
+}
+
+/// Resolve `hostname` to all of its addresses
+///
+/// `done` receives the result JSON or an error message. Returns the lookup id.
+pub fn resolve<F>(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    hostname: String,
+    isolation: i64,
+    done: F,
+) -> i64
+where
+    F: FnOnce(i64, Result<String, String>) + Send + 'static,
+{
+    let prefs = isolation::stream_prefs(isolation);
+
+    spawn_lookup(runtime, move |id| async move {
+        let result = client
+            .resolve_with_prefs(&hostname, &prefs)
+            .await
+            .map_err(|e| e.to_string())
+            .map(|addrs| {
+                let result = ResolveResult {
+                    query: hostname.clone(),
+                    addresses: addrs
+                        .into_iter()
+                        .map(|a| AddressEntry { address: a.to_string(), ttl: TTL_UNKNOWN })
+                        .collect(),
+                };
+                serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+            });
+        if let Err(ref e) = result {
+            log_error!("DNS lookup {} for {} failed: {}", id, safelog::sensitive(&hostname), e);
+        }
+        result
+    }, done)
+}
+
+/// Resolve `address` back to hostnames
+///
+/// `done` receives the result JSON or an error message. Returns the lookup id.
+pub fn resolve_ptr<F>(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    address: IpAddr,
+    isolation: i64,
+    done: F,
+) -> i64
+where
+    F: FnOnce(i64, Result<String, String>) + Send + 'static,
+{
+    let prefs = isolation::stream_prefs(isolation);
+
+    spawn_lookup(runtime, move |id| async move {
+        let result = client
+            .resolve_ptr_with_prefs(address, &prefs)
+            .await
+            .map_err(|e| e.to_string())
+            .map(|names| {
+                let result = ResolvePtrResult {
+                    query: address.to_string(),
+                    hostnames: names
+                        .into_iter()
+                        .map(|n| HostnameEntry { hostname: n, ttl: TTL_UNKNOWN })
+                        .collect(),
+                };
+                serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+            });
+        if let Err(ref e) = result {
+            log_error!("Reverse DNS lookup {} for {} failed: {}", id, safelog::sensitive(address), e);
+        }
+        result
+    }, done)
+}
+
+/// Abort a running lookup; its completion is never called
+pub fn cancel(id: i64) -> bool {
+    match LOOKUPS.lock_or_recover().remove(&id) {
+        Some(handle) => {
+            handle.abort();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Run a lookup as a registered task and hand its result to `done`
+///
+/// `lookup` gets the id, for its log lines.
+fn spawn_lookup<L, Fut, F>(runtime: &tokio::runtime::Handle, lookup: L, done: F) -> i64
+where
+    L: FnOnce(i64) -> Fut,
+    Fut: Future<Output = Result<String, String>> + Send + 'static,
+    F: FnOnce(i64, Result<String, String>) + Send + 'static,
+{
+    let id = NEXT_LOOKUP_ID.fetch_add(1, Ordering::Relaxed);
+    let lookup = lookup(id);
+
+    // Hold the registry lock until the task is registered, so a lookup that
+    // finishes immediately cannot complete before its id is handed out
+    let mut lookups = LOOKUPS.lock_or_recover();
+    let task = runtime.spawn(async move {
+        let result = lookup.await;
+        LOOKUPS.lock_or_recover().remove(&id);
+        done(id, result);
+    });
+    lookups.insert(id, task.abort_handle());
+
+    id
+}
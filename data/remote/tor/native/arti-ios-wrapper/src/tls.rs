@@ -0,0 +1,48 @@
+//! TLS for native clients running over Tor streams
+//!
+//! Uses rustls with the ring provider (the same one arti is built with) and
+//! the Mozilla root store from `webpki-roots`, so no platform trust store is
+//! needed on embedded targets.
+
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// Build a client config advertising the given ALPN protocols
+fn client_config(alpn: &[&[u8]]) -> Result<ClientConfig, rustls::Error> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let mut config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(config)
+}
+
+/// Run a TLS handshake for `host` on top of an established stream
+pub async fn connect<S>(stream: S, host: &str, alpn: &[&[u8]]) -> io::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let config = client_config(alpn).map_err(io::Error::other)?;
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    TlsConnector::from(Arc::new(config)).connect(server_name, stream).await
+}
+
+/// Whether a handshake error means the server certificate was rejected
+pub fn is_certificate_error(error: &io::Error) -> bool {
+    matches!(
+        error.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()),
+        Some(rustls::Error::InvalidCertificate(_)) | Some(rustls::Error::NoCertificatesPresented)
+    )
+}
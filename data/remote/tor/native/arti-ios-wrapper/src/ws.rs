@@ -0,0 +1,342 @@
+//! WebSocket client over Tor
+//!
+//! Opens `ws://` and `wss://` connections through `TorClient::connect`, with
+//! TLS done in-process by rustls. Connection events are delivered through C
+//! callbacks shaped like OkHttp's `WebSocketListener` (open, message,
+//! closing, closed, failure), which is what NostrRelay already expects.
+
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CString};
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arti_client::TorClient;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+use crate::{addrmap, circuits, policy, redact, streamevents, tls, traffic};
+
+// ============================================================================
+// Callbacks
+// ============================================================================
+
+/// Callback table supplied by the host; every function pointer may be NULL
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WsCallbacks {
+    pub context: *mut c_void,
+    pub on_open: Option<extern "C" fn(*mut c_void, i64)>,
+    pub on_message: Option<extern "C" fn(*mut c_void, i64, i32, *const u8, usize)>,
+    pub on_closing: Option<extern "C" fn(*mut c_void, i64, i32, *const c_char)>,
+    pub on_closed: Option<extern "C" fn(*mut c_void, i64, i32, *const c_char)>,
+    pub on_failure: Option<extern "C" fn(*mut c_void, i64, *const c_char)>,
+}
+
+// The context pointer is owned by the host, which must accept callbacks from
+// any thread.
+unsafe impl Send for WsCallbacks {}
+unsafe impl Sync for WsCallbacks {}
+
+impl WsCallbacks {
+    fn open(&self, handle: i64) {
+        if let Some(cb) = self.on_open {
+            cb(self.context, handle);
+        }
+    }
+
+    fn message(&self, handle: i64, is_binary: bool, data: &[u8]) {
+        if let Some(cb) = self.on_message {
+            cb(self.context, handle, is_binary as i32, data.as_ptr(), data.len());
+        }
+    }
+
+    fn closing(&self, handle: i64, code: u16, reason: &str) {
+        if let Some(cb) = self.on_closing {
+            let reason = CString::new(reason).unwrap_or_default();
+            cb(self.context, handle, code as i32, reason.as_ptr());
+        }
+    }
+
+    fn closed(&self, handle: i64, code: u16, reason: &str) {
+        if let Some(cb) = self.on_closed {
+            let reason = CString::new(reason).unwrap_or_default();
+            cb(self.context, handle, code as i32, reason.as_ptr());
+        }
+    }
+
+    fn failure(&self, handle: i64, error: &str) {
+        if let Some(cb) = self.on_failure {
+            let error = CString::new(error).unwrap_or_default();
+            cb(self.context, handle, error.as_ptr());
+        }
+    }
+}
+
+// ============================================================================
+// Connection Registry
+// ============================================================================
+
+/// Requests from FFI calls to a connection task
+enum Command {
+    Text(String),
+    Binary(Vec<u8>),
+    Close(u16, String),
+}
+
+/// Time from `open` until the WebSocket handshake has to be complete
+///
+/// Generous because reaching an onion service may take a fresh rendezvous
+/// circuit and a proof-of-work solve before the TLS and WebSocket handshakes.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Time the peer gets to finish the closing handshake, as in OkHttp
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest close reason: a close frame carries 125 bytes, two of them the code
+pub const MAX_CLOSE_REASON_LEN: usize = 123;
+
+/// Next handle handed out by `open`
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+/// Command channels of live connections, keyed by handle
+///
+/// A connection leaves the registry as soon as either side starts closing, so
+/// later sends fail with a status code instead of failing the connection.
+static CONNECTIONS: Mutex<BTreeMap<i64, mpsc::UnboundedSender<Command>>> = Mutex::new(BTreeMap::new());
+
+/// Reasons `open` can refuse a URL before any connection is attempted
+pub enum OpenError {
+    InvalidUrl(String),
+    Rejected,
+}
+
+/// Start connecting to `url`; returns the connection handle
+///
+/// The outcome of the connection attempt is reported through `callbacks`.
+pub fn open(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    url: &str,
+    callbacks: WsCallbacks,
+) -> Result<i64, OpenError> {
+    let parsed = url::Url::parse(url).map_err(|e| OpenError::InvalidUrl(e.to_string()))?;
+    let secure = match parsed.scheme() {
+        "ws" => false,
+        "wss" => true,
+        other => return Err(OpenError::InvalidUrl(format!("Unsupported scheme: {}", other))),
+    };
+    // `host_str` would keep the brackets around an IPv6 literal
+    let host = match parsed.host() {
+        Some(url::Host::Domain(domain)) => domain.to_string(),
+        Some(url::Host::Ipv4(ip)) => ip.to_string(),
+        Some(url::Host::Ipv6(ip)) => ip.to_string(),
+        None => return Err(OpenError::InvalidUrl("Missing host".to_string())),
+    };
+    let port = parsed.port_or_known_default().unwrap_or(if secure { 443 } else { 80 });
+
+    // Only the Tor stream goes to the mapped target; TLS and the handshake
+    // still name the host from the URL, as they would through SOCKS
+    let (target_host, target_port) = addrmap::apply(&host, port);
+    if !policy::is_allowed(&target_host, target_port) {
+        return Err(OpenError::Rejected);
+    }
+
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::unbounded_channel();
+    CONNECTIONS.lock_or_recover().insert(handle, tx);
+
+    let url = url.to_string();
+    runtime.spawn(async move {
+        let target = (target_host.as_str(), target_port);
+        let result = connect_and_run(handle, client, &url, &host, target, secure, rx, callbacks).await;
+        CONNECTIONS.lock_or_recover().remove(&handle);
+        if let Err(e) = result {
+            log_error!("WebSocket {} failed: {}", handle, e);
+            callbacks.failure(handle, &e);
+        }
+    });
+
+    Ok(handle)
+}
+
+/// Queue a text frame; returns false if the handle is unknown or closed
+pub fn send_text(handle: i64, text: String) -> bool {
+    send_command(handle, Command::Text(text))
+}
+
+/// Queue a binary frame; returns false if the handle is unknown or closed
+pub fn send_binary(handle: i64, data: Vec<u8>) -> bool {
+    send_command(handle, Command::Binary(data))
+}
+
+/// Start the closing handshake; returns false if the handle is unknown or closed
+pub fn close(handle: i64, code: u16, reason: String) -> bool {
+    CONNECTIONS
+        .lock_or_recover()
+        .remove(&handle)
+        .is_some_and(|tx| tx.send(Command::Close(code, reason)).is_ok())
+}
+
+/// `code` as a close code the host may send, or `None`
+///
+/// 1004-1006 and 1015 are reserved for reporting locally, 1016-2999 are
+/// unassigned, and 3000-4999 belong to libraries and applications.
+pub fn close_code(code: i32) -> Option<u16> {
+    match code {
+        1000..=1003 | 1007..=1014 | 3000..=4999 => Some(code as u16),
+        _ => None,
+    }
+}
+
+fn send_command(handle: i64, command: Command) -> bool {
+    CONNECTIONS
+        .lock_or_recover()
+        .get(&handle)
+        .is_some_and(|tx| tx.send(command).is_ok())
+}
+
+// ============================================================================
+// Connection Task
+// ============================================================================
+
+#[allow(clippy::too_many_arguments)]
+async fn connect_and_run(
+    handle: i64,
+    client: Arc<TorClient<PreferredRuntime>>,
+    url: &str,
+    host: &str,
+    (target_host, target_port): (&str, u16),
+    secure: bool,
+    rx: mpsc::UnboundedReceiver<Command>,
+    callbacks: WsCallbacks,
+) -> Result<(), String> {
+    log_info!("WebSocket {} connecting to {}", handle, redact::destination(target_host, target_port));
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let timed_out = |_: tokio::time::error::Elapsed| format!("Timed out connecting after {} s", CONNECT_TIMEOUT.as_secs());
+
+    // Shows up in the traffic snapshot, circuit list and stream events like a SOCKS stream
+    let mut lifecycle = streamevents::opened(target_host, target_port);
+    let tor_stream = match tokio::time::timeout_at(deadline, client.connect((target_host, target_port)))
+        .await
+        .map_err(timed_out)?
+    {
+        Ok(stream) => stream,
+        Err(e) => {
+            lifecycle.failed(&e);
+            return Err(format!("Tor connection failed: {}", e));
+        }
+    };
+    let traffic_stream = traffic::open_stream(lifecycle.id(), target_host, target_port);
+    let _circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+    let tor_stream = traffic::CountingStream::new(tor_stream, traffic_stream.counters());
+
+    if secure {
+        let tls_stream = tokio::time::timeout_at(deadline, tls::connect(tor_stream, host, &[]))
+            .await
+            .map_err(timed_out)?
+            .map_err(|e| format!("TLS handshake failed: {}", e))?;
+        let (ws, _) = tokio::time::timeout_at(deadline, tokio_tungstenite::client_async(url, tls_stream))
+            .await
+            .map_err(timed_out)?
+            .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+        run(handle, ws, rx, callbacks).await
+    } else {
+        let (ws, _) = tokio::time::timeout_at(deadline, tokio_tungstenite::client_async(url, tor_stream))
+            .await
+            .map_err(timed_out)?
+            .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+        run(handle, ws, rx, callbacks).await
+    }
+}
+
+/// Pump frames between the host and an open WebSocket until it closes
+async fn run<S>(
+    handle: i64,
+    ws: WebSocketStream<S>,
+    mut rx: mpsc::UnboundedReceiver<Command>,
+    callbacks: WsCallbacks,
+) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    log_info!("WebSocket {} open", handle);
+    callbacks.open(handle);
+
+    let (mut sink, mut stream) = ws.split();
+    let mut close_code = CloseCode::Status;
+    let mut close_reason = String::new();
+    // Set once a close frame is sent; nothing may be sent after it
+    let mut close_sent = false;
+    let mut close_received = false;
+    // Armed once closing starts, so a silent peer cannot keep the task alive
+    let close_timer = tokio::time::sleep(CLOSE_TIMEOUT);
+    tokio::pin!(close_timer);
+
+    loop {
+        tokio::select! {
+            command = rx.recv(), if !close_sent => {
+                let message = match command {
+                    Some(Command::Text(text)) => Message::Text(text.into()),
+                    Some(Command::Binary(data)) => Message::Binary(data.into()),
+                    Some(Command::Close(code, reason)) => Message::Close(Some(CloseFrame {
+                        code: CloseCode::from(code),
+                        reason: reason.into(),
+                    })),
+                    // Every sender is gone: nobody can use this connection any more
+                    None => Message::Close(None),
+                };
+                if matches!(message, Message::Close(_)) {
+                    close_sent = true;
+                    close_timer.as_mut().reset(Instant::now() + CLOSE_TIMEOUT);
+                }
+                sink.send(message).await.map_err(|e| e.to_string())?;
+            }
+            frame = stream.next() => {
+                match frame {
+                    Some(Ok(Message::Text(text))) => callbacks.message(handle, false, text.as_bytes()),
+                    Some(Ok(Message::Binary(data))) => callbacks.message(handle, true, &data),
+                    Some(Ok(Message::Close(frame))) => {
+                        // tungstenite answers the close frame itself
+                        CONNECTIONS.lock_or_recover().remove(&handle);
+                        if !close_sent {
+                            close_sent = true;
+                            close_timer.as_mut().reset(Instant::now() + CLOSE_TIMEOUT);
+                        }
+                        close_received = true;
+                        if let Some(frame) = frame {
+                            close_code = frame.code;
+                            close_reason = frame.reason.to_string();
+                        }
+                        callbacks.closing(handle, close_code.into(), &close_reason);
+                    }
+                    // Ping/pong are answered by tungstenite itself
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.to_string()),
+                    None => break,
+                }
+            }
+            () = &mut close_timer, if close_sent => {
+                if !close_received {
+                    return Err(format!("No close frame from the peer within {} s", CLOSE_TIMEOUT.as_secs()));
+                }
+                // The handshake is done; the peer just left the connection open
+                break;
+            }
+        }
+    }
+
+    log_info!("WebSocket {} closed ({})", handle, u16::from(close_code));
+    callbacks.closed(handle, close_code.into(), &close_reason);
+    Ok(())
+}
@@ -1,6 +1,7 @@
 #ifndef ARTI_LINUX_H
 #define ARTI_LINUX_H
 
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
@@ -10,20 +11,152 @@ extern "C" {
 /// Log callback function type
 typedef void (*arti_log_callback_t)(const char* message);
 
+/// Log levels passed to the leveled log callback
+#define ARTI_LOG_TRACE 0
+#define ARTI_LOG_DEBUG 1
+#define ARTI_LOG_INFO 2
+#define ARTI_LOG_WARN 3
+#define ARTI_LOG_ERROR 4
+
+/// Returned by functions with an integer result if the call panicked (functions
+/// returning strings return NULL). The panic is reported first as a
+/// {"type": "panic", "message", "location"} event; the library stays usable.
+#define ARTI_ERROR_PANIC -99
+
+/// Leveled log callback function type
+/// @param level One of the ARTI_LOG_* levels
+/// @param timestamp_ms Unix time of the record in milliseconds
+/// @param target Module the record comes from: arti's, e.g. "tor_guardmgr", or the wrapper's own
+/// @param message The log message, without level or target
+/// @param context The pointer given to arti_set_log_callback_v2
+typedef void (*arti_log_callback_v2_t)(int32_t level, int64_t timestamp_ms, const char* target, const char* message, void* context);
+
+/// Event callback function type
+/// Events are JSON objects with a "type" field and a "timestamp_ms" field
+typedef void (*arti_event_callback_t)(const char* event_json);
+
+/// WebSocket callbacks, mirroring OkHttp's WebSocketListener
+/// Every function pointer may be NULL. Callbacks run on Tor worker threads;
+/// pointers passed to them are only valid for the duration of the call.
+typedef struct {
+    /// Opaque pointer passed back to every callback
+    void* context;
+    /// Handshake completed
+    void (*on_open)(void* context, int64_t handle);
+    /// Frame received (is_binary = 0 for UTF-8 text)
+    void (*on_message)(void* context, int64_t handle, int32_t is_binary, const uint8_t* data, size_t len);
+    /// Peer sent a close frame
+    void (*on_closing)(void* context, int64_t handle, int32_t code, const char* reason);
+    /// Connection fully closed; no further callbacks follow
+    void (*on_closed)(void* context, int64_t handle, int32_t code, const char* reason);
+    /// Connection failed (Tor, TLS or WebSocket error); no further callbacks follow
+    void (*on_failure)(void* context, int64_t handle, const char* error);
+} arti_ws_callbacks_t;
+
+/// DNS lookup completion callback
+/// @param status 0 on success, -5 if the lookup failed
+/// @param result_json On success {"query": ..., "addresses": [{"address": "1.2.3.4", "ttl": -1}]}
+///                    (arti_resolve) or {"query": ..., "hostnames": [{"hostname": ..., "ttl": -1}]}
+///                    (arti_resolve_ptr); on failure {"error": "..."}. "ttl" is in seconds, or -1
+///                    if unknown, which it always is for now: arti does not expose the TTLs of
+///                    the RESOLVED cell. Only valid during the call.
+typedef void (*arti_resolve_callback_t)(void* context, int64_t lookup_id, int32_t status, const char* result_json);
+
+/// HTTP error kinds passed to arti_http_callbacks_t.on_error
+#define ARTI_HTTP_ERROR_TOR         1  ///< Could not open a stream through Tor
+#define ARTI_HTTP_ERROR_CERTIFICATE 2  ///< Server certificate rejected
+#define ARTI_HTTP_ERROR_TLS         3  ///< Other TLS failure
+#define ARTI_HTTP_ERROR_HTTP        4  ///< HTTP protocol or I/O error
+#define ARTI_HTTP_ERROR_TIMEOUT     5  ///< Request exceeded its timeout
+
+/// HTTP response callbacks
+/// Every function pointer may be NULL. Callbacks run on Tor worker threads;
+/// pointers passed to them are only valid for the duration of the call.
+typedef struct {
+    /// Opaque pointer passed back to every callback
+    void* context;
+    /// Response head: status code, protocol ("HTTP/1.1" or "HTTP/2.0") and
+    /// headers as a JSON array of [name, value] pairs
+    void (*on_response)(void* context, int64_t request_id, int32_t status, const char* version, const char* headers_json);
+    /// Next piece of the response body
+    void (*on_body_chunk)(void* context, int64_t request_id, const uint8_t* data, size_t len);
+    /// Body finished; no further callbacks follow
+    void (*on_complete)(void* context, int64_t request_id);
+    /// Request failed with one of ARTI_HTTP_ERROR_*; no further callbacks follow
+    void (*on_error)(void* context, int64_t request_id, int32_t error_kind, const char* message);
+} arti_http_callbacks_t;
+
 /// Get Arti version string
 /// @return Version string (caller must NOT free)
 const char* arti_get_version(void);
 
 /// Set log callback for Arti logs
+/// Besides the wrapper's own messages this receives arti's internal log events
+/// as "[LEVEL] target: message", subject to arti_set_log_filter. Errors are
+/// prefixed with "ERROR: ". Prefer arti_set_log_callback_v2 for new code.
 /// @param callback Function to call with log messages
 void arti_set_log_callback(arti_log_callback_t callback);
 
+/// Set leveled log callback, receiving the same records as arti_set_log_callback
+/// with level, timestamp, target and message as separate fields.
+/// Both callbacks may be set at once. Callbacks run on arbitrary threads.
+/// @param callback Function to call with log records, or NULL to unset
+/// @param context Opaque pointer passed back to every call
+void arti_set_log_callback_v2(arti_log_callback_v2_t callback, void* context);
+
+/// Set event callback for structured events
+/// Connecting to a PoW-protected onion service reports the solver's work as
+/// {"type": "pow_solve_started", "effort"} and
+/// {"type": "pow_solved", "effort", "duration_ms", "success"}.
+/// @param callback Function to call with JSON-encoded events, or NULL to unset
+void arti_set_event_callback(arti_event_callback_t callback);
+
+/// Set which of arti's internal log events reach the log callbacks
+/// Takes effect immediately; until called, events at "info" and above are forwarded.
+/// @param directives Comma-separated tracing directives, e.g. "warn" or
+///                   "info,tor_guardmgr=debug,tor_dirmgr=trace"
+/// @return 0 on success, -1 if the directives do not parse
+int32_t arti_set_log_filter(const char* directives);
+
+/// Turn safe logging on or off
+/// With safe logging on (the default), destination hosts, onion addresses and
+/// peer sockets appear as "[scrubbed]" in log lines, the wrapper's and arti's.
+/// Turn it off only for debugging: log lines then reveal where the user connects.
+/// @param enabled 1 to redact (default), 0 to log destinations in plain text
+/// @return 0 on success, -1 if it could not be changed
+int32_t arti_set_safe_logging(int32_t enabled);
+
+/// Get recent log lines and events for bug reports
+/// The last 1000 records are kept in memory, whether or not callbacks are set.
+/// Safe logging applies to the events too: their "destination", "host" and "peer"
+/// fields are "[scrubbed]", here as in the event callback, and so are the
+/// free-text "error" and "message" fields here.
+/// @return JSON array, oldest first, of {"kind": "log", "timestamp_ms", "level",
+///         "target", "message"} and {"kind": "event", "type", "timestamp_ms", ...};
+///         release with arti_free_string
+char* arti_get_recent_logs(void);
+
+/// Write log lines to a rotating file, <data_dir>/logs/arti.log
+/// Rotated files are named arti.log.1 (newest) to arti.log.<max_files>. Only log
+/// lines are written, not events, so safe logging keeps destinations out of it.
+/// May be called before arti_initialize; writing starts once the data dir is known.
+/// @param max_bytes Rotate once the file reaches this size; 0 or less disables the file
+/// @param max_files Number of rotated files to keep
+/// @param max_age_seconds If > 0, rotate at least this often and delete older rotated files
+/// @return 0 on success, -1 on invalid limits
+int32_t arti_set_log_file(int64_t max_bytes, int32_t max_files, int64_t max_age_seconds);
+
 /// Initialize Arti runtime
+/// If the ARTI_METRICS_PORT environment variable is set, also serves Prometheus
+/// metrics at http://127.0.0.1:<port>/metrics for the rest of the process lifetime.
 /// @param data_dir Path to data directory
 /// @return 0 on success, negative on error
 int32_t arti_initialize(const char* data_dir);
 
 /// Start SOCKS proxy on specified port
+/// Failed onion service connections get Tor's extended SOCKS5 reply codes:
+/// 0xF0 descriptor not found, 0xF1 invalid descriptor, 0xF2 introduction failed,
+/// 0xF4 client authorization missing, 0xF5 client authorization wrong, 0xF6 bad address.
 /// @param port Port number for SOCKS proxy
 /// @return 0 on success, negative on error
 int32_t arti_start_socks_proxy(int32_t port);
@@ -32,6 +165,355 @@ int32_t arti_start_socks_proxy(int32_t port);
 /// @return 0 on success, negative on error
 int32_t arti_stop(void);
 
+/// Enable or disable cookie authentication for the SOCKS proxy
+/// When enabled, a random cookie is written to <data_dir>/state/socks_auth_cookie
+/// (mode 0600) and SOCKS5 clients must send it as the username/password password.
+/// @param enabled Non-zero to require the cookie
+/// @return 0 on success, -1 if not initialized, -2 if the cookie could not be written
+int32_t arti_set_cookie_auth(int32_t enabled);
+
+/// Set how IP-literal SOCKS destinations (ATYP 0x01/0x04) are handled
+/// Violations are reported as "safe_socks_violation" events with the caller's peer address,
+/// which like the destination is "[scrubbed]" while safe logging is on.
+/// The check applies to the destination the caller asked for, before address
+/// mapping: a mapping whose target is an IP literal is not a violation.
+/// @param mode 0 = accept (default), 1 = accept and report (warn only), 2 = reject and report
+/// @return 0 on success, -1 on invalid mode
+int32_t arti_set_safe_socks_mode(int32_t mode);
+
+/// Load (or hot-swap) the SOCKS destination rule set
+/// Rules are evaluated first-match-wins before any Tor connection is made;
+/// rejected requests get SOCKS reply 0x02 and a "destination_rejected" event
+/// ("peer" and "destination" are "[scrubbed]" while safe logging is on).
+/// Example:
+///   {"default": "reject",
+///    "rules": [{"action": "allow", "onion_only": true},
+///              {"action": "allow", "host_suffixes": ["relay.example.com"], "ports": [443, 80]},
+///              {"action": "allow", "host_globs": ["nostr-*.example.net"], "ports": ["8000-8100"]}]}
+/// @param rules_json Rule set JSON, or NULL/empty to allow all destinations
+/// @return 0 on success, -1 on invalid rules (previous rule set stays active)
+int32_t arti_set_destination_policy(const char* rules_json);
+
+/// Replace the address mapping table (MapAddress-style rewrites)
+/// SOCKS, fd, WebSocket and HTTP connections to a mapped source go to the target
+/// instead (TLS still names the source host); a source without a port matches
+/// every port and keeps the requested one. IPv6 literals
+/// take brackets with a port ("[2001:db8::1]:443") and may be bare without one.
+/// Example: [{"from": "relay.example.com:443", "to": "xyz.onion:443"},
+///           {"from": "relay2.example.com", "to": "abc.onion"}]
+/// @param map_json JSON array of mappings, or NULL/empty to clear the table
+/// @return 0 on success, -1 on invalid input (previous table stays active)
+int32_t arti_set_address_map(const char* map_json);
+
+/// Add a single address mapping, or retarget an existing one
+/// @param from Source "host" or "host:port"
+/// @param to Target "host" or "host:port"
+/// @return 0 on success, -1 on invalid input
+int32_t arti_add_address_mapping(const char* from, const char* to);
+
+/// Remove a single address mapping
+/// @param from Source "host" or "host:port" as it was added
+/// @return 0 if removed, 1 if no such mapping, -1 on invalid input
+int32_t arti_remove_address_mapping(const char* from);
+
+/// Get the address mapping table with per-mapping usage counts
+/// @return JSON array of {"from", "to", "uses"} (caller must free with arti_free_string)
+char* arti_get_address_map(void);
+
+/// Open a WebSocket (ws:// or wss://) through Tor, with TLS done by rustls
+/// @param url WebSocket URL
+/// @param callbacks Callback table (copied; may be freed after the call)
+/// Fails through on_failure if the handshake does not complete within 120 s.
+/// @return Connection handle (> 0), -1 on invalid arguments, -2 if not initialized,
+///         -4 if the destination policy rejects the host
+int64_t arti_ws_open(const char* url, const arti_ws_callbacks_t* callbacks);
+
+/// Send a text frame
+/// @return 0 on success, -1 on invalid arguments, -2 if the handle is not open
+///         or either side has started closing it
+int32_t arti_ws_send_text(int64_t handle, const char* text);
+
+/// Send a binary frame
+/// @return 0 on success, -1 on invalid arguments, -2 if the handle is not open
+///         or either side has started closing it
+int32_t arti_ws_send_binary(int64_t handle, const uint8_t* data, size_t len);
+
+/// Start the closing handshake (on_closed follows once it completes)
+/// A peer that does not answer within 60 s gets the connection dropped and
+/// on_failure is reported instead.
+/// @param code WebSocket close code: 1000-1003, 1007-1014 or 3000-4999
+/// @param reason Close reason of at most 123 bytes of UTF-8, or NULL
+/// @return 0 on success, -1 if code or reason is invalid, -2 if the handle is not open
+///         or either side has started closing it
+int32_t arti_ws_close(int64_t handle, int32_t code, const char* reason);
+
+/// Make an HTTP/1.1 or HTTP/2 request (negotiated via ALPN) through Tor
+/// @param method HTTP method, e.g. "GET"
+/// @param url http:// or https:// URL
+/// @param headers_json Request headers as a JSON array of [name, value] pairs, or NULL;
+///                     a "Host" header is ignored, the host is always that of the URL
+/// @param body Request body, or NULL
+/// @param body_len Length of body in bytes
+/// @param timeout_ms Timeout for the whole request including the body, 0 for none
+/// @param callbacks Callback table (copied; may be freed after the call)
+/// @return Request id (> 0), -1 on invalid arguments, -2 if not initialized,
+///         -4 if the destination policy rejects the host
+int64_t arti_http_request(const char* method, const char* url, const char* headers_json,
+                          const uint8_t* body, size_t body_len, int32_t timeout_ms,
+                          const arti_http_callbacks_t* callbacks);
+
+/// Cancel a running HTTP request; no callback is made for it once this returns 0
+/// A callback running on another thread is waited for. A request may also be
+/// cancelled from one of its own callbacks.
+/// @return 0 on success, -2 if the request is not running (its final callback
+///         may still be on its way)
+int32_t arti_http_cancel(int64_t request_id);
+
+/// Open a Tor stream and return it as one end of a Unix socketpair
+/// The wrapper pumps bytes between the socket and the Tor stream; closing or
+/// shutting down either side is propagated to the other. Blocks until the
+/// stream is established.
+/// @param host Destination hostname or .onion address
+/// @param port Destination port
+/// @param isolation 0 = default isolation, > 0 = streams with the same value may
+///                  share circuits, < 0 = circuits not shared with any other stream
+/// @return File descriptor owned by the caller (>= 0), -1 on invalid arguments,
+///         -2 if not initialized, -3 if the socketpair could not be created,
+///         -4 if the destination policy rejects the host, -5 if the Tor connection failed,
+///         -6 if the onion service requires client authorization and no key is stored,
+///         -7 if the stored client key is not authorized by the onion service,
+///         -8 if called on a Tor runtime thread (e.g. from a callback), where it cannot block
+int32_t arti_connect_fd(const char* host, int32_t port, int64_t isolation);
+
+/// Resolve a hostname to all of its addresses through Tor (no system resolver involved)
+/// @param hostname Name to resolve
+/// @param isolation Isolation selector, as for arti_connect_fd
+/// @param callback Called once on a Tor worker thread with the result
+/// @param context Opaque pointer passed back to callback
+/// @return Lookup id (> 0), -1 on invalid arguments, -2 if not initialized
+int64_t arti_resolve(const char* hostname, int64_t isolation, arti_resolve_callback_t callback, void* context);
+
+/// Resolve an IP address back to hostnames (PTR lookup) through Tor
+/// @param address IPv4 or IPv6 address in text form
+/// @param isolation Isolation selector, as for arti_connect_fd
+/// @param callback Called once on a Tor worker thread with the result
+/// @param context Opaque pointer passed back to callback
+/// @return Lookup id (> 0), -1 on invalid arguments, -2 if not initialized
+int64_t arti_resolve_ptr(const char* address, int64_t isolation, arti_resolve_callback_t callback, void* context);
+
+/// Cancel a running lookup; its callback is not called
+/// @return 0 on success, -2 if the lookup already finished or never existed
+int32_t arti_resolve_cancel(int64_t lookup_id);
+
+/// Publish an onion service and forward its virtual port to a local target
+/// Keys are kept in the state dir under the nickname, so the address is
+/// stable across restarts. Streams to any other port are refused.
+/// @param nickname Service nickname (letters, digits, '-' and '_'); "ephemeral-..." is reserved
+/// @param virtual_port Port clients connect to on the .onion address
+/// @param local_target "host:port" or "unix:/path/to/socket"
+/// @return ".onion" address (caller must free with arti_free_string), or NULL on failure
+char* arti_onion_service_start(const char* nickname, int32_t virtual_port, const char* local_target);
+
+/// Stop a hosted onion service
+/// @param nickname Service nickname passed to arti_onion_service_start
+/// @return 0 on success, -1 on invalid arguments, -2 if no such service is running,
+///         -3 for an ephemeral service (use arti_onion_ephemeral_destroy)
+int32_t arti_onion_service_stop(const char* nickname);
+
+/// Get the state of every hosted onion service
+/// Each entry has "nickname", "address", "virtual_port", "target", "state"
+/// ("bootstrapping", "running", "degraded_reachable", "degraded_unreachable",
+/// "recovering", "broken" or "shutdown"), "reachable", "problem" (string or null),
+/// "descriptor_upload_failures" and "intro_point_failures". The failure counts
+/// come from the most severe current problem only, so at most one is non-zero.
+/// arti does not report how many HSDirs hold the descriptor or how many
+/// introduction points are established, so neither count is available.
+/// Every change is also sent to the event callback as
+/// {"type": "onion_service_status", "nickname", ...}.
+/// @return JSON array (caller must free with arti_free_string)
+char* arti_onion_service_status(void);
+
+/// Configure the DoS defenses of an onion service
+/// Applies to the running service (if any) and to every later start. The
+/// proof-of-work effort the service asks of clients is not configurable: it
+/// raises it on its own while under load, up to arti_onion_service_set_pow_max_effort.
+/// @param nickname Service nickname
+/// @param config_json Object with optional fields: "enable_pow" (bool),
+///                    "pow_queue_depth" (queued intro requests while PoW is on),
+///                    "intro_rate" and "intro_burst" (intro requests per second the
+///                    intro points let through, set together), "max_streams_per_circuit"
+/// @return 0 if in effect, 1 if the service is running and the change applies from
+///         its next start (switching PoW on or off) or is still starting and may
+///         not have picked it up, -1 on invalid arguments
+int32_t arti_onion_service_set_dos_params(const char* nickname, const char* config_json);
+
+/// Cap the proof-of-work effort hosted onion services credit to a client
+/// Requests that spent more are queued as if they had spent the cap. This is a
+/// network parameter (HiddenServiceProofOfWorkV1MaxEffort), so it covers every
+/// service and is read when a client is created: call it before arti_initialize.
+/// It also applies to ephemeral services created after the call. The effort this
+/// client spends when connecting to other services is capped by arti itself and
+/// cannot be configured.
+/// @param max_effort Effort cap, or a negative value for the consensus value (10000 by default)
+/// @return 0
+int32_t arti_onion_service_set_pow_max_effort(int32_t max_effort);
+
+/// Publish a throwaway onion service, e.g. for a single conversation
+/// Its keys are kept in memory only and never written to disk; they are
+/// dropped once the last ephemeral service is gone. The first call bootstraps a
+/// separate client for ephemeral services, which can take a few seconds.
+/// Ephemeral services show up in arti_onion_service_status under an
+/// "ephemeral-..." nickname.
+/// @param virtual_port Port clients connect to on the .onion address
+/// @param local_target "host:port" or "unix:/path/to/socket"
+/// @param ttl_seconds Tear the service down after this many seconds, or 0 to keep it
+/// @return ".onion" address (caller must free with arti_free_string), or NULL on failure
+///         (also when called from a Tor runtime thread, e.g. inside a callback)
+char* arti_onion_ephemeral_create(int32_t virtual_port, const char* local_target, int64_t ttl_seconds);
+
+/// Tear down an ephemeral onion service
+/// @param onion_address Address returned by arti_onion_ephemeral_create
+/// @return 0 on success, -1 on invalid arguments, -2 if no such service exists (or it expired)
+int32_t arti_onion_ephemeral_destroy(const char* onion_address);
+
+/// Import an ed25519 identity key for an onion service into arti's keystore
+/// Replacing a different existing identity also discards the keys and state
+/// derived from it. The service must not be running.
+/// @param nickname Service nickname
+/// @param secret 32-byte ed25519 seed or 64-byte expanded secret key
+/// @param secret_len Length of secret
+/// @param overwrite Non-zero to replace a different existing identity
+/// @param onion_address_out If not NULL, receives the ".onion" address (free with arti_free_string)
+/// @return 0 on success, -1 on invalid arguments or key, -2 if not initialized,
+///         -3 if a different identity exists, -4 if the service is running, -7 on keystore errors
+int32_t arti_onion_identity_import(const char* nickname, const uint8_t* secret, size_t secret_len,
+                                   int32_t overwrite, char** onion_address_out);
+
+/// Import an identity previously exported with arti_onion_identity_export
+/// @param nickname Service nickname
+/// @param exported Hex string returned by arti_onion_identity_export
+/// @param passphrase Passphrase the identity was exported with
+/// @param overwrite Non-zero to replace a different existing identity
+/// @param onion_address_out If not NULL, receives the ".onion" address (free with arti_free_string)
+/// @return As for arti_onion_identity_import, or -6 if the passphrase is wrong or the data corrupted
+int32_t arti_onion_identity_import_encrypted(const char* nickname, const char* exported, const char* passphrase,
+                                             int32_t overwrite, char** onion_address_out);
+
+/// Export the identity key of an onion service, encrypted with a passphrase
+/// (scrypt key derivation, ChaCha20-Poly1305)
+/// @param nickname Service nickname
+/// @param passphrase Passphrase to encrypt with
+/// @return Hex string (caller must free with arti_free_string), or NULL if there is no identity
+char* arti_onion_identity_export(const char* nickname, const char* passphrase);
+
+/// Compute the v3 ".onion" address for an ed25519 public key, without starting anything
+/// @param public_key 32-byte ed25519 public key
+/// @param len Length of public_key
+/// @return ".onion" address (caller must free with arti_free_string), or NULL if the key is invalid
+char* arti_onion_address_from_public_key(const uint8_t* public_key, size_t len);
+
+/// Authorize a client to discover an onion service (restricted discovery)
+/// Once a service has authorized clients it only publishes its descriptor to
+/// them, from its next start on; changes made while it runs apply immediately.
+/// @param nickname Service nickname
+/// @param client_nickname Name for the client (letters, digits, '-' and '_')
+/// @param public_key Client's x25519 key as "descriptor:x25519:<base32>"
+/// @return 0 on success, -1 on invalid arguments or key, -2 if not initialized, -7 on storage errors
+int32_t arti_onion_service_authorize_client(const char* nickname, const char* client_nickname, const char* public_key);
+
+/// Revoke a client authorized with arti_onion_service_authorize_client
+/// The service stays in restricted discovery mode even without clients.
+/// @return 0 on success, -1 on invalid arguments, -2 if not initialized,
+///         -5 if the client was not authorized, -7 on storage errors
+int32_t arti_onion_service_revoke_client(const char* nickname, const char* client_nickname);
+
+/// Store the client key used to reach a restricted onion service
+/// Connections to the service (SOCKS, arti_connect_fd, ...) use it automatically.
+/// @param onion_address ".onion" address of the service
+/// @param secret_key x25519 secret as "descriptor:x25519:<base32>" or bare base32
+/// @param public_key_out If not NULL, receives the matching public key for the
+///                       service operator (free with arti_free_string)
+/// @return 0 on success, -1 on invalid arguments or key, -2 if not initialized, -7 on keystore errors
+int32_t arti_onion_client_auth_set(const char* onion_address, const char* secret_key, char** public_key_out);
+
+/// Generate and store a new client key for a restricted onion service
+/// @param onion_address ".onion" address of the service
+/// @return Public key as "descriptor:x25519:<base32>" to hand to the service
+///         operator (caller must free with arti_free_string), or NULL on failure
+char* arti_onion_client_auth_generate(const char* onion_address);
+
+/// Forget the client key stored for an onion service
+/// @return 0 on success, -1 on invalid arguments, -2 if not initialized,
+///         -5 if no key was stored, -7 on keystore errors
+int32_t arti_onion_client_auth_remove(const char* onion_address);
+
+/// Get a JSON snapshot of per-stream and aggregate SOCKS traffic counters
+/// @return JSON string (caller must free with arti_free_string), or NULL
+char* arti_get_traffic_snapshot(void);
+
+/// Allow or forbid recording stream destinations in traffic snapshots
+/// @param enabled Non-zero to record "host:port" for each stream
+void arti_set_traffic_record_destinations(int32_t enabled);
+
+/// Turn per-stream lifecycle events on or off, for a debug console
+/// Off by default. Once on, each SOCKS, fd, WebSocket and HTTP stream reports,
+/// through the event callback:
+///
+///   {"type": "stream_opened", "stream_id", "destination"}
+///   {"type": "stream_connected", "stream_id", "circuit_id"}
+///   {"type": "stream_failed", "stream_id", "error_kind", "error"}
+///   {"type": "stream_closed", "stream_id", "bytes_up", "bytes_down", "duration_ms"}
+///
+/// Every opened stream ends with exactly one "stream_failed" or
+/// "stream_closed". "stream_id" matches the traffic snapshot, "circuit_id"
+/// matches arti_get_circuits, and "destination" and "error" are "[scrubbed]"
+/// while safe logging is on. Streams report if events were enabled when they
+/// were opened, so toggling this never leaves a lifecycle half reported.
+/// @param enabled Non-zero to report stream events
+void arti_set_stream_events(int32_t enabled);
+
+/// Get the circuits behind active SOCKS, fd, WebSocket and HTTP streams
+///
+/// [{"id": "3.1", "stream_ids": [12, 14],
+///   "paths": [[{"role", "nickname", "fingerprint", "country_code"}, ...]]}]
+///
+/// "role" is "guard", "middle", "exit", "rendezvous" or "onion_service"
+/// (the service end of an onion circuit, with no other details). Each
+/// circuit usually has one path; conflux circuits have one per leg.
+/// "nickname" is null for relays outside the consensus (e.g. bridges).
+/// Circuits without an active stream (e.g. directory fetches) are not listed.
+/// @return JSON string (caller must free with arti_free_string), or NULL
+char* arti_get_circuits(void);
+
+/// Get the circuit behind a stream
+/// @param stream_id Stream id as in the traffic snapshot
+/// @return JSON object as in arti_get_circuits (caller must free with
+///         arti_free_string), or NULL if the stream is not active
+char* arti_get_stream_circuit(int64_t stream_id);
+
+/// Get a JSON snapshot of the Tor subsystem's metrics
+///
+/// {"timestamp_ms", "uptime_seconds",
+///  "bootstrap": {"percent", "ready_for_traffic", "blocked"},
+///  "consensus": {"valid_after_ms", "fresh_until_ms", "valid_until_ms"},
+///  "usable_relays",
+///  "circuits": {"open", "build_successes", "build_failures", "best_effort"},
+///  "streams": {"active", "bytes_up", "bytes_down"}}
+///
+/// Before arti_initialize succeeds, "uptime_seconds", "bootstrap",
+/// "consensus" and "usable_relays" are null; "consensus" and
+/// "usable_relays" also are until a consensus has been downloaded.
+/// Circuit and stream counters are totals since the process started.
+/// arti does not expose circuit counts, so they are read from its log traces and
+/// "best_effort" is always true: they can undercount, and "build_successes" stays
+/// 0 while another process holds arti's state lock.
+/// @return JSON string (caller must free with arti_free_string), or NULL
+char* arti_get_metrics(void);
+
+/// Free a string returned by the wrapper
+/// @param s String to free (NULL is ignored)
+void arti_free_string(char* s);
+
 #ifdef __cplusplus
 }
 #endif
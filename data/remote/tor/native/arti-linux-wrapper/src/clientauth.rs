@@ -0,0 +1,199 @@
+//! Restricted discovery (onion service client authorization)
+//!
+//! Service side: the x25519 public keys of authorized clients are kept as
+//! `<client>.auth` files in `<state dir>/hss_clients/<service>/`. Once a
+//! service has had a client authorized it always starts in restricted
+//! discovery mode, even after every client was revoked, and it watches the
+//! directory so later changes apply live.
+//!
+//! Client side: the x25519 secret key for a restricted .onion is stored in
+//! arti's keystore, where `TorClient::connect` picks it up.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use arti_client::{ErrorKind, HasKind};
+use tor_hsclient::HsClientDescEncKeypairSpecifier;
+use tor_hscrypto::pk::{HsClientDescEncKey, HsId};
+use tor_hsservice::config::restricted_discovery::HsClientNickname;
+use tor_hsservice::HsNickname;
+use tor_keymgr::{KeyType, Keystore};
+use tor_llcrypto::pk::curve25519;
+use zeroize::Zeroizing;
+
+use crate::auth;
+use crate::identity::{self, IdentityError};
+
+/// Directory under the state dir holding per-service authorized client keys
+const CLIENTS_DIR_NAME: &str = "hss_clients";
+
+/// Prefix of client keys in C Tor format
+const KEY_PREFIX: &str = "descriptor:x25519:";
+
+// ============================================================================
+// Service Side
+// ============================================================================
+
+/// Authorize `client` to discover `service`
+///
+/// `public_key` is `descriptor:x25519:<base32>` or just the base32 part.
+pub fn authorize_client(service: &str, client: &str, public_key: &str) -> Result<(), IdentityError> {
+    let client = parse_client_nickname(client)?;
+    let key = HsClientDescEncKey::from_str(&with_prefix(public_key))
+        .map_err(|e| IdentityError::InvalidKey(e.to_string()))?;
+
+    identity::with_store(|_, state_dir| {
+        let dir = clients_dir(state_dir, service)?;
+        create_private_dir(&dir)?;
+        auth::write_private_file(&dir.join(format!("{}.auth", client)), key.to_string().as_bytes())
+            .map_err(|e| IdentityError::Keystore(e.to_string()))
+    })
+}
+
+/// Revoke a client; returns whether it was authorized
+pub fn revoke_client(service: &str, client: &str) -> Result<bool, IdentityError> {
+    let client = parse_client_nickname(client)?;
+
+    identity::with_store(|_, state_dir| {
+        let path = clients_dir(state_dir, service)?.join(format!("{}.auth", client));
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(IdentityError::Keystore(e.to_string())),
+        }
+    })
+}
+
+/// Key directory to run `service` in restricted discovery mode with, if it ever had clients
+pub fn restricted_discovery_dir(service: &str) -> Option<PathBuf> {
+    identity::with_store(|_, state_dir| clients_dir(state_dir, service))
+        .ok()
+        .filter(|dir| dir.is_dir())
+}
+
+fn clients_dir(state_dir: &Path, service: &str) -> Result<PathBuf, IdentityError> {
+    // Validates the nickname, which also makes it safe as a path component
+    let service: HsNickname = service
+        .to_string()
+        .try_into()
+        .map_err(|e| IdentityError::InvalidNickname(format!("{}", e)))?;
+    Ok(state_dir.join(CLIENTS_DIR_NAME).join(service.to_string()))
+}
+
+fn parse_client_nickname(client: &str) -> Result<HsClientNickname, IdentityError> {
+    HsClientNickname::from_str(client).map_err(|e| IdentityError::InvalidNickname(e.to_string()))
+}
+
+// ============================================================================
+// Client Side
+// ============================================================================
+
+/// Store the x25519 secret key used to reach the restricted service `onion_address`
+///
+/// `secret_key` is `descriptor:x25519:<base32>` or just the base32 part.
+/// Returns the matching public key, in the format the service operator needs.
+pub fn set_client_key(onion_address: &str, secret_key: &str) -> Result<String, IdentityError> {
+    let hsid = parse_onion_address(onion_address)?;
+    let encoded = with_prefix(secret_key);
+    let encoded = encoded.strip_prefix(KEY_PREFIX).unwrap_or_default().to_uppercase();
+    let bytes = Zeroizing::new(
+        data_encoding::BASE32_NOPAD
+            .decode(encoded.as_bytes())
+            .map_err(|_| IdentityError::InvalidKey("Client key is not valid base32".to_string()))?,
+    );
+    let bytes: [u8; 32] = bytes[..]
+        .try_into()
+        .map_err(|_| IdentityError::InvalidKey(format!("Expected 32 bytes, got {}", bytes.len())))?;
+
+    store_client_key(hsid, curve25519::StaticSecret::from(bytes))
+}
+
+/// Generate and store a new client key for `onion_address`; returns its public key
+pub fn generate_client_key(onion_address: &str) -> Result<String, IdentityError> {
+    let hsid = parse_onion_address(onion_address)?;
+    let mut bytes = Zeroizing::new([0u8; 32]);
+    getrandom::fill(&mut bytes[..]).map_err(|e| IdentityError::Keystore(e.to_string()))?;
+
+    store_client_key(hsid, curve25519::StaticSecret::from(*bytes))
+}
+
+/// Forget the client key for `onion_address`; returns whether one was stored
+pub fn remove_client_key(onion_address: &str) -> Result<bool, IdentityError> {
+    let hsid = parse_onion_address(onion_address)?;
+    let spec = HsClientDescEncKeypairSpecifier::new(hsid);
+
+    identity::with_store(|keystore, _| {
+        keystore
+            .remove(&spec, &KeyType::X25519StaticKeypair.into())
+            .map(|removed| removed.is_some())
+            .map_err(|e| IdentityError::Keystore(e.to_string()))
+    })
+}
+
+fn store_client_key(hsid: HsId, secret: curve25519::StaticSecret) -> Result<String, IdentityError> {
+    let public = curve25519::PublicKey::from(&secret);
+    let keypair = curve25519::StaticKeypair { secret, public };
+    let spec = HsClientDescEncKeypairSpecifier::new(hsid);
+
+    identity::with_store(|keystore, _| {
+        keystore
+            .insert(&keypair, &spec)
+            .map_err(|e| IdentityError::Keystore(e.to_string()))
+    })?;
+    Ok(HsClientDescEncKey::from(public).to_string())
+}
+
+fn parse_onion_address(onion_address: &str) -> Result<HsId, IdentityError> {
+    let address = onion_address.trim().to_ascii_lowercase();
+    let address = address.trim_end_matches('.');
+    HsId::from_str(address).map_err(|e| IdentityError::InvalidKey(format!("Invalid onion address: {}", e)))
+}
+
+fn with_prefix(key: &str) -> String {
+    let key = key.trim();
+    if key.starts_with(KEY_PREFIX) {
+        key.to_string()
+    } else {
+        format!("{}{}", KEY_PREFIX, key)
+    }
+}
+
+// ============================================================================
+// Error Mapping
+// ============================================================================
+
+/// SOCKS5 reply code for a failed connection, using Tor's onion service extensions
+pub fn socks_reply_code(error: &arti_client::Error) -> u8 {
+    match error.kind() {
+        ErrorKind::OnionServiceNotFound => 0xF0,
+        ErrorKind::OnionServiceProtocolViolation => 0xF1,
+        ErrorKind::OnionServiceConnectionFailed => 0xF2,
+        ErrorKind::OnionServiceMissingClientAuth => 0xF4,
+        ErrorKind::OnionServiceWrongClientAuth => 0xF5,
+        ErrorKind::OnionServiceAddressInvalid => 0xF6,
+        // Connection refused
+        _ => 0x05,
+    }
+}
+
+// ============================================================================
+// Files
+// ============================================================================
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<(), IdentityError> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .and_then(|()| fs::set_permissions(dir, fs::Permissions::from_mode(0o700)))
+        .map_err(|e| IdentityError::Keystore(e.to_string()))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<(), IdentityError> {
+    fs::create_dir_all(dir).map_err(|e| IdentityError::Keystore(e.to_string()))
+}
@@ -0,0 +1,177 @@
+//! Tor streams exposed as file descriptors
+//!
+//! For consumers that cannot speak SOCKS (a custom socket factory, plain
+//! POSIX code), a Tor stream is bridged to one end of a Unix socketpair and
+//! the other end is handed out as a file descriptor. Bytes are pumped in both
+//! directions, and a close or half-close on either side is passed on.
+//!
+//! Writing to a socket whose peer is gone raises SIGPIPE, which kills the
+//! app. The wrapper writes its end with `MSG_NOSIGNAL` where that exists;
+//! on Apple platforms both ends get `SO_NOSIGPIPE` instead.
+
+use std::io;
+use std::os::fd::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use arti_client::TorClient;
+use tokio::io::{AsyncWrite, AsyncWriteExt, Interest};
+use tor_rtcompat::PreferredRuntime;
+
+use crate::{addrmap, circuits, isolation, policy, streamevents, traffic};
+
+/// Reasons a descriptor could not be produced
+pub enum FdError {
+    /// Called on a runtime thread (e.g. from a callback), where it cannot block
+    RuntimeThread,
+    Rejected,
+    Socketpair(std::io::Error),
+    Tor(arti_client::Error),
+}
+
+/// Open a Tor stream to `host:port` and return the caller's end of the socketpair
+///
+/// Blocks until the stream is established, so it must not be called on a
+/// runtime thread. The caller owns the descriptor and closes it when done;
+/// that ends the Tor stream as well.
+pub fn connect_fd(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    host: &str,
+    port: u16,
+    isolation: i64,
+) -> Result<RawFd, FdError> {
+    // `block_on` would panic there
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(FdError::RuntimeThread);
+    }
+    let (host, port) = addrmap::apply(host, port);
+    let host = host.as_str();
+    if !policy::is_allowed(host, port) {
+        return Err(FdError::Rejected);
+    }
+
+    let prefs = isolation::stream_prefs(isolation);
+    let mut lifecycle = streamevents::opened(host, port);
+    let tor_stream = match runtime.block_on(client.connect_with_prefs((host, port), &prefs)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            lifecycle.failed(&e);
+            return Err(FdError::Tor(e));
+        }
+    };
+
+    let (ours, theirs) = UnixStream::pair().map_err(FdError::Socketpair)?;
+    set_no_sigpipe(&ours).map_err(FdError::Socketpair)?;
+    set_no_sigpipe(&theirs).map_err(FdError::Socketpair)?;
+    ours.set_nonblocking(true).map_err(FdError::Socketpair)?;
+    let ours = {
+        let _guard = runtime.enter();
+        tokio::net::UnixStream::from_std(ours).map_err(FdError::Socketpair)?
+    };
+
+    let traffic_stream = traffic::open_stream(lifecycle.id(), host, port);
+    let circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+    let target = format!("{}:{}", host, port);
+    runtime.spawn(async move {
+        let (app_read, app_write) = ours.into_split();
+        let mut app_write = NoSignalWriter(app_write);
+        let (tor_read, mut tor_write) = tor_stream.split();
+        let mut app_read = traffic::CountingReader::new(app_read, traffic_stream.counters(), traffic::Direction::Up);
+        let mut tor_read = traffic::CountingReader::new(tor_read, traffic_stream.counters(), traffic::Direction::Down);
+
+        // Each direction passes its EOF on as a half-close of the other side
+        let app_to_tor = async {
+            tokio::io::copy(&mut app_read, &mut tor_write).await?;
+            tor_write.shutdown().await
+        };
+        let tor_to_app = async {
+            tokio::io::copy(&mut tor_read, &mut app_write).await?;
+            app_write.shutdown().await
+        };
+
+        if let Err(e) = tokio::try_join!(app_to_tor, tor_to_app) {
+            log_error!("Tor fd stream to {} failed: {:?}", safelog::sensitive(&target), e);
+        }
+        log_info!(
+            "Tor fd stream closed for {} ({} bytes up, {} bytes down)",
+            safelog::sensitive(&target),
+            traffic_stream.bytes_up(),
+            traffic_stream.bytes_down()
+        );
+        drop(circuit);
+        drop(lifecycle);
+    });
+
+    Ok(theirs.into_raw_fd())
+}
+
+/// Write half of our end of the socketpair, sending without raising SIGPIPE
+struct NoSignalWriter(tokio::net::unix::OwnedWriteHalf);
+
+impl AsyncWrite for NoSignalWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let stream: &tokio::net::UnixStream = self.0.as_ref();
+        loop {
+            ready!(stream.poll_write_ready(cx))?;
+            match stream.try_io(Interest::WRITABLE, || send(stream.as_raw_fd(), buf)) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+
+// Covered by SO_NOSIGPIPE on the socket instead
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS: libc::c_int = 0;
+
+fn send(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    // SAFETY: `buf` is valid for `buf.len()` bytes and `fd` stays open for the call
+    let sent = unsafe { libc::send(fd, buf.as_ptr().cast(), buf.len(), SEND_FLAGS) };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn set_no_sigpipe(stream: &UnixStream) -> io::Result<()> {
+    let on: libc::c_int = 1;
+    // SAFETY: `on` outlives the call and its size is passed along
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NOSIGPIPE,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Linux has no per-socket option; our writes use MSG_NOSIGNAL instead
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn set_no_sigpipe(_stream: &UnixStream) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,385 @@
+//! HTTPS requests over Tor
+//!
+//! One-shot HTTP/1.1 or HTTP/2 requests (negotiated through ALPN) made with
+//! hyper on top of a Tor stream, with TLS done by rustls. The response head
+//! and every body chunk are handed to C callbacks as they arrive, so large
+//! downloads never have to be buffered in the wrapper.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CString};
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arti_client::TorClient;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+use crate::{addrmap, circuits, policy, redact, streamevents, tls, traffic};
+
+// ============================================================================
+// Callbacks
+// ============================================================================
+
+/// Callback table supplied by the host; every function pointer may be NULL
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HttpCallbacks {
+    pub context: *mut c_void,
+    pub on_response: Option<extern "C" fn(*mut c_void, i64, i32, *const c_char, *const c_char)>,
+    pub on_body_chunk: Option<extern "C" fn(*mut c_void, i64, *const u8, usize)>,
+    pub on_complete: Option<extern "C" fn(*mut c_void, i64)>,
+    pub on_error: Option<extern "C" fn(*mut c_void, i64, i32, *const c_char)>,
+}
+
+// The context pointer is owned by the host, which must accept callbacks from
+// any thread.
+unsafe impl Send for HttpCallbacks {}
+unsafe impl Sync for HttpCallbacks {}
+
+impl HttpCallbacks {
+    fn response(&self, id: i64, status: u16, version: &str, headers_json: &str) {
+        if let Some(cb) = self.on_response {
+            let version = CString::new(version).unwrap_or_default();
+            let headers = CString::new(headers_json).unwrap_or_default();
+            cb(self.context, id, status as i32, version.as_ptr(), headers.as_ptr());
+        }
+    }
+
+    fn body_chunk(&self, id: i64, data: &[u8]) {
+        if let Some(cb) = self.on_body_chunk {
+            cb(self.context, id, data.as_ptr(), data.len());
+        }
+    }
+
+    fn complete(&self, id: i64) {
+        if let Some(cb) = self.on_complete {
+            cb(self.context, id);
+        }
+    }
+
+    fn error(&self, id: i64, error: &HttpError) {
+        if let Some(cb) = self.on_error {
+            let message = CString::new(error.message.as_str()).unwrap_or_default();
+            cb(self.context, id, error.kind as i32, message.as_ptr());
+        }
+    }
+}
+
+thread_local! {
+    /// Request whose callback is running on this thread, 0 if none
+    static IN_CALLBACK: Cell<i64> = const { Cell::new(0) };
+}
+
+/// Keeps a request's callbacks and `cancel` apart
+///
+/// Callbacks run holding `lock` and only while `cancelled` is unset, so once
+/// `cancel` has set it and taken the lock, none is running or can start.
+#[derive(Default)]
+struct CallbackGate {
+    cancelled: AtomicBool,
+    lock: Mutex<()>,
+}
+
+impl CallbackGate {
+    /// Run `f` (a callback of request `id`) unless the request was cancelled
+    fn deliver(&self, id: i64, f: impl FnOnce()) {
+        let _guard = self.lock.lock_or_recover();
+        if self.cancelled.load(Ordering::Acquire) {
+            return;
+        }
+        IN_CALLBACK.with(|current| current.set(id));
+        f();
+        IN_CALLBACK.with(|current| current.set(0));
+    }
+
+    /// Stop further callbacks, waiting for one running on another thread
+    fn cancel(&self, id: i64) {
+        self.cancelled.store(true, Ordering::Release);
+        // Called from one of this request's own callbacks, which holds the lock
+        if IN_CALLBACK.with(Cell::get) != id {
+            drop(self.lock.lock_or_recover());
+        }
+    }
+}
+
+// ============================================================================
+// Errors
+// ============================================================================
+
+/// Failure categories reported to `on_error`
+#[derive(Clone, Copy, Debug)]
+pub enum ErrorKind {
+    /// Could not open a stream through Tor
+    Tor = 1,
+    /// The server certificate was rejected
+    Certificate = 2,
+    /// Any other TLS failure
+    Tls = 3,
+    /// HTTP protocol or I/O error after the connection was established
+    Http = 4,
+    /// The request did not finish within its timeout
+    Timeout = 5,
+}
+
+struct HttpError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl HttpError {
+    fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+}
+
+/// Reasons a request is refused before anything is sent
+pub enum RequestError {
+    Invalid(String),
+    Rejected,
+}
+
+// ============================================================================
+// Requests
+// ============================================================================
+
+/// Everything needed to issue one request
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    /// Header name/value pairs, in order
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Zero means no timeout
+    pub timeout: Duration,
+}
+
+/// Next id handed out by `start`
+static NEXT_REQUEST_ID: AtomicI64 = AtomicI64::new(1);
+
+/// A request that can still be cancelled
+struct RunningRequest {
+    task: tokio::task::AbortHandle,
+    gate: Arc<CallbackGate>,
+}
+
+/// Running requests, so they can be cancelled
+static REQUESTS: Mutex<BTreeMap<i64, RunningRequest>> = Mutex::new(BTreeMap::new());
+
+/// Parse `[["Name", "value"], ...]` into header pairs
+pub fn parse_headers_json(json: &str) -> Result<Vec<(String, String)>, String> {
+    serde_json::from_str(json).map_err(|e| format!("Invalid headers JSON: {}", e))
+}
+
+/// Start a request; returns its id
+///
+/// The outcome is reported through `callbacks`: `on_response` once, then
+/// `on_body_chunk` for each piece of the body, then `on_complete`, or
+/// `on_error` at any point instead.
+pub fn start(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    request: HttpRequest,
+    callbacks: HttpCallbacks,
+) -> Result<i64, RequestError> {
+    let url = url::Url::parse(&request.url).map_err(|e| RequestError::Invalid(e.to_string()))?;
+    let secure = match url.scheme() {
+        "http" => false,
+        "https" => true,
+        other => return Err(RequestError::Invalid(format!("Unsupported scheme: {}", other))),
+    };
+    // `host_str` would keep the brackets around an IPv6 literal
+    let host = match url.host() {
+        Some(url::Host::Domain(domain)) => domain.to_string(),
+        Some(url::Host::Ipv4(ip)) => ip.to_string(),
+        Some(url::Host::Ipv6(ip)) => ip.to_string(),
+        None => return Err(RequestError::Invalid("Missing host".to_string())),
+    };
+    let port = url.port_or_known_default().unwrap_or(if secure { 443 } else { 80 });
+    let method = hyper::Method::from_bytes(request.method.as_bytes())
+        .map_err(|e| RequestError::Invalid(e.to_string()))?;
+
+    // Only the Tor stream goes to the mapped target; TLS and the Host header
+    // still name the host from the URL, as they would through SOCKS
+    let (target_host, target_port) = addrmap::apply(&host, port);
+    if !policy::is_allowed(&target_host, target_port) {
+        return Err(RequestError::Rejected);
+    }
+
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let timeout = request.timeout;
+    let gate = Arc::new(CallbackGate::default());
+    let task_gate = Arc::clone(&gate);
+
+    // Hold the registry lock until the task is registered, so a request that
+    // finishes immediately cannot try to unregister itself first
+    let mut requests = REQUESTS.lock_or_recover();
+    let task = runtime.spawn(async move {
+        let target = (target_host.as_str(), target_port);
+        let exchange = execute(id, client, method, url, &host, target, secure, request, callbacks, &task_gate);
+        let result = if timeout.is_zero() {
+            exchange.await
+        } else {
+            match tokio::time::timeout(timeout, exchange).await {
+                Ok(result) => result,
+                Err(_) => Err(HttpError::new(ErrorKind::Timeout, format!("Timed out after {} ms", timeout.as_millis()))),
+            }
+        };
+
+        // A later `cancel` returns false and the final callback is made; an
+        // earlier one has closed the gate already
+        REQUESTS.lock_or_recover().remove(&id);
+        match result {
+            Ok(()) => task_gate.deliver(id, || callbacks.complete(id)),
+            Err(e) => {
+                log_error!("HTTP request {} failed ({:?}): {}", id, e.kind, e.message);
+                task_gate.deliver(id, || callbacks.error(id, &e));
+            }
+        }
+    });
+    requests.insert(id, RunningRequest { task: task.abort_handle(), gate });
+
+    Ok(id)
+}
+
+/// Abort a running request; no callback is made for it once this returns true
+///
+/// A callback of the request running on another thread is waited for; one
+/// of its own callbacks may cancel it, too.
+pub fn cancel(id: i64) -> bool {
+    let Some(request) = REQUESTS.lock_or_recover().remove(&id) else {
+        return false;
+    };
+    request.gate.cancel(id);
+    request.task.abort();
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute(
+    id: i64,
+    client: Arc<TorClient<PreferredRuntime>>,
+    method: hyper::Method,
+    url: url::Url,
+    host: &str,
+    (target_host, target_port): (&str, u16),
+    secure: bool,
+    request: HttpRequest,
+    callbacks: HttpCallbacks,
+    gate: &CallbackGate,
+) -> Result<(), HttpError> {
+    log_info!("HTTP request {}: {} {}", id, method, redact::destination(target_host, target_port));
+
+    // Shows up in the traffic snapshot, circuit list and stream events like a SOCKS stream
+    let mut lifecycle = streamevents::opened(target_host, target_port);
+    let tor_stream = match client.connect((target_host, target_port)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            lifecycle.failed(&e);
+            return Err(HttpError::new(ErrorKind::Tor, e.to_string()));
+        }
+    };
+    let traffic_stream = traffic::open_stream(lifecycle.id(), target_host, target_port);
+    let _circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+    let tor_stream = traffic::CountingStream::new(tor_stream, traffic_stream.counters());
+
+    let response = if secure {
+        let tls_stream = tls::connect(tor_stream, host, &[b"h2", b"http/1.1"])
+            .await
+            .map_err(|e| {
+                let kind = if tls::is_certificate_error(&e) { ErrorKind::Certificate } else { ErrorKind::Tls };
+                HttpError::new(kind, e.to_string())
+            })?;
+        let h2 = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2".as_slice());
+        send(tls_stream, h2, &method, &url, request).await?
+    } else {
+        send(tor_stream, false, &method, &url, request).await?
+    };
+
+    let headers: Vec<(&str, String)> = response
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect();
+    let headers_json = serde_json::to_string(&headers).unwrap_or_else(|_| "[]".to_string());
+    let version = format!("{:?}", response.version());
+    gate.deliver(id, || callbacks.response(id, response.status().as_u16(), &version, &headers_json));
+
+    let mut body = response.into_body();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| HttpError::new(ErrorKind::Http, e.to_string()))?;
+        if let Some(data) = frame.data_ref() {
+            gate.deliver(id, || callbacks.body_chunk(id, data));
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the HTTP exchange on an established (possibly TLS) stream
+async fn send<S>(
+    stream: S,
+    h2: bool,
+    method: &hyper::Method,
+    url: &url::Url,
+    request: HttpRequest,
+) -> Result<Response<Incoming>, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let http_err = |e: hyper::Error| HttpError::new(ErrorKind::Http, e.to_string());
+    let io = TokioIo::new(stream);
+
+    // HTTP/2 wants the full URI; HTTP/1.1 the origin form plus a Host header.
+    // Neither sends the fragment, which is only meaningful to the client.
+    let mut builder = Request::builder().method(method.clone());
+    builder = if h2 {
+        let mut uri = url.clone();
+        uri.set_fragment(None);
+        builder.uri(uri.as_str())
+    } else {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        // `Url::port` is None when the port is the scheme's default, and
+        // `host_str` keeps the brackets an IPv6 literal needs here
+        let host = url.host_str().unwrap_or_default();
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        builder.uri(path).header(hyper::header::HOST, host_header)
+    };
+    // The host always comes from the URL, which the destination policy checked
+    for (name, value) in &request.headers {
+        if name.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let req = builder
+        .body(Full::new(Bytes::from(request.body)))
+        .map_err(|e| HttpError::new(ErrorKind::Http, e.to_string()))?;
+
+    if h2 {
+        let (mut sender, conn) = hyper::client::conn::http2::handshake(TokioExecutor::new(), io)
+            .await
+            .map_err(http_err)?;
+        tokio::spawn(conn);
+        sender.send_request(req).await.map_err(http_err)
+    } else {
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await.map_err(http_err)?;
+        tokio::spawn(conn);
+        sender.send_request(req).await.map_err(http_err)
+    }
+}
@@ -0,0 +1,232 @@
+//! Onion service identity keys
+//!
+//! Lets the host give a hosted service a known identity instead of the one
+//! arti generates on first launch: an ed25519 key can be imported into arti's
+//! keystore under the service nickname, exported encrypted with a passphrase,
+//! and a v3 .onion address can be computed from a public key alone.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use safelog::DisplayRedacted;
+use tor_hscrypto::pk::{HsId, HsIdKey};
+use tor_hsservice::{HsIdKeypairSpecifier, HsNickname};
+use tor_keymgr::{ArtiNativeKeystore, KeyType, Keystore};
+use tor_llcrypto::pk::ed25519;
+use zeroize::Zeroizing;
+
+use crate::guard::LockExt;
+use crate::onion;
+
+/// Format version of exported identity blobs
+const EXPORT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// scrypt cost: N = 2^15, r = 8, p = 1 (about 32 MiB and well under a second on phones)
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// arti's native keystore plus the state dir it lives in
+struct IdentityStore {
+    keystore: ArtiNativeKeystore,
+    state_dir: PathBuf,
+}
+
+/// Opened by `arti_initialize`, `None` before that
+static STORE: Mutex<Option<IdentityStore>> = Mutex::new(None);
+
+/// Reasons an identity or client key operation failed
+pub enum IdentityError {
+    NotInitialized,
+    InvalidNickname(String),
+    InvalidKey(String),
+    /// The service is running; stop it before changing its identity
+    Running,
+    /// A different identity already exists and overwriting was not requested
+    Exists,
+    NotFound,
+    /// Wrong passphrase or corrupted export
+    Decrypt,
+    Keystore(String),
+}
+
+/// Use `keystore`, rooted in `state_dir`, for all identity operations
+pub fn set_keystore(keystore: ArtiNativeKeystore, state_dir: PathBuf) {
+    *STORE.lock_or_recover() = Some(IdentityStore { keystore, state_dir });
+}
+
+/// Run `f` with the keystore and the state dir it lives in
+pub fn with_store<T>(f: impl FnOnce(&ArtiNativeKeystore, &Path) -> Result<T, IdentityError>) -> Result<T, IdentityError> {
+    let guard = STORE.lock_or_recover();
+    let store = guard.as_ref().ok_or(IdentityError::NotInitialized)?;
+    f(&store.keystore, &store.state_dir)
+}
+
+/// Store a 32-byte ed25519 seed or 64-byte expanded secret key as the identity of `nickname`
+///
+/// Returns the .onion address of the imported identity.
+pub fn import(nickname: &str, secret: &[u8], overwrite: bool) -> Result<String, IdentityError> {
+    let keypair = keypair_from_bytes(secret)?;
+    store_identity(nickname, keypair, overwrite)
+}
+
+/// Decrypt an identity produced by `export_encrypted` and store it for `nickname`
+pub fn import_encrypted(nickname: &str, blob_hex: &str, passphrase: &[u8], overwrite: bool) -> Result<String, IdentityError> {
+    let secret = decrypt(blob_hex, passphrase)?;
+    import(nickname, &secret[..], overwrite)
+}
+
+/// Export the identity of `nickname`, encrypted with `passphrase`, as hex
+///
+/// Layout: version(1) || salt(16) || nonce(12) || ChaCha20-Poly1305 ciphertext
+/// of the 64-byte expanded secret key, under a key derived with scrypt.
+pub fn export_encrypted(nickname: &str, passphrase: &[u8]) -> Result<String, IdentityError> {
+    let nickname = parse_nickname(nickname)?;
+    // scrypt takes a while; derive the key after releasing the store
+    let keypair = {
+        let guard = STORE.lock_or_recover();
+        let store = guard.as_ref().ok_or(IdentityError::NotInitialized)?;
+        load(store, &nickname)?.ok_or(IdentityError::NotFound)?
+    };
+    let secret = Zeroizing::new(keypair.to_secret_key_bytes());
+
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::fill(&mut salt).map_err(|e| IdentityError::Keystore(e.to_string()))?;
+    getrandom::fill(&mut nonce).map_err(|e| IdentityError::Keystore(e.to_string()))?;
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), &secret[..])
+        .map_err(|_| IdentityError::Keystore("Encryption failed".to_string()))?;
+
+    let mut blob = Vec::with_capacity(1 + SALT_LEN + NONCE_LEN + ciphertext.len());
+    blob.push(EXPORT_VERSION);
+    blob.extend_from_slice(&salt);
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&ciphertext);
+    Ok(hex::encode(blob))
+}
+
+/// Compute the v3 .onion address for a 32-byte ed25519 public key
+pub fn onion_address_for_public_key(public_key: &[u8]) -> Result<String, IdentityError> {
+    let bytes: &[u8; 32] = public_key
+        .try_into()
+        .map_err(|_| IdentityError::InvalidKey(format!("Expected 32 bytes, got {}", public_key.len())))?;
+    let public_key = ed25519::PublicKey::from_bytes(bytes)
+        .map_err(|_| IdentityError::InvalidKey("Not a valid ed25519 public key".to_string()))?;
+    Ok(onion_address(HsIdKey::from(public_key).id()))
+}
+
+fn store_identity(nickname: &str, keypair: ed25519::ExpandedKeypair, overwrite: bool) -> Result<String, IdentityError> {
+    let hs_nickname = parse_nickname(nickname)?;
+    onion::unless_running(nickname, || replace_identity(nickname, hs_nickname, keypair, overwrite))
+        .unwrap_or(Err(IdentityError::Running))
+}
+
+/// Check the existing identity and store the new one under a single store lock
+fn replace_identity(
+    nickname: &str,
+    hs_nickname: HsNickname,
+    keypair: ed25519::ExpandedKeypair,
+    overwrite: bool,
+) -> Result<String, IdentityError> {
+    let guard = STORE.lock_or_recover();
+    let store = guard.as_ref().ok_or(IdentityError::NotInitialized)?;
+    let address = onion_address(HsIdKey::from(*keypair.public()).id());
+
+    if let Some(existing) = load(store, &hs_nickname)? {
+        if existing.public() == keypair.public() {
+            return Ok(address);
+        }
+        if !overwrite {
+            return Err(IdentityError::Exists);
+        }
+        // Blinded keys, intro point keys and publisher state were all derived
+        // from the old identity; drop them so arti starts the service afresh
+        remove_service_dirs(&store.state_dir, nickname)?;
+    }
+
+    let spec = HsIdKeypairSpecifier::new(hs_nickname);
+    store
+        .keystore
+        .insert(&keypair, &spec)
+        .map_err(|e| IdentityError::Keystore(e.to_string()))?;
+    Ok(address)
+}
+
+fn load(store: &IdentityStore, nickname: &HsNickname) -> Result<Option<ed25519::ExpandedKeypair>, IdentityError> {
+    let spec = HsIdKeypairSpecifier::new(nickname.clone());
+    let erased = store
+        .keystore
+        .get(&spec, &KeyType::Ed25519ExpandedKeypair.into())
+        .map_err(|e| IdentityError::Keystore(e.to_string()))?;
+    match erased {
+        Some(key) => key
+            .downcast::<ed25519::ExpandedKeypair>()
+            .map(|key| Some(*key))
+            .map_err(|_| IdentityError::Keystore("Unexpected key type in keystore".to_string())),
+        None => Ok(None),
+    }
+}
+
+fn remove_service_dirs(state_dir: &Path, nickname: &str) -> Result<(), IdentityError> {
+    for dir in [state_dir.join("keystore").join("hss").join(nickname), state_dir.join("hss").join(nickname)] {
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(IdentityError::Keystore(format!("Failed to remove {:?}: {}", dir, e))),
+        }
+    }
+    Ok(())
+}
+
+fn keypair_from_bytes(secret: &[u8]) -> Result<ed25519::ExpandedKeypair, IdentityError> {
+    if let Ok(seed) = <&[u8; 32]>::try_from(secret) {
+        return Ok(ed25519::ExpandedKeypair::from(&ed25519::Keypair::from_bytes(seed)));
+    }
+    if let Ok(expanded) = <[u8; 64]>::try_from(secret) {
+        return ed25519::ExpandedKeypair::from_secret_key_bytes(expanded)
+            .ok_or_else(|| IdentityError::InvalidKey("Not a valid expanded ed25519 secret key".to_string()));
+    }
+    Err(IdentityError::InvalidKey(format!("Expected 32 or 64 bytes, got {}", secret.len())))
+}
+
+fn decrypt(blob_hex: &str, passphrase: &[u8]) -> Result<Zeroizing<Vec<u8>>, IdentityError> {
+    let blob = hex::decode(blob_hex.trim()).map_err(|_| IdentityError::Decrypt)?;
+    if blob.len() <= 1 + SALT_LEN + NONCE_LEN || blob[0] != EXPORT_VERSION {
+        return Err(IdentityError::Decrypt);
+    }
+    let (salt, rest) = blob[1..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| IdentityError::Decrypt)
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8]) -> Result<Key, IdentityError> {
+    let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, 32)
+        .map_err(|e| IdentityError::Keystore(e.to_string()))?;
+    let mut key = Key::default();
+    scrypt::scrypt(passphrase, salt, &params, &mut key)
+        .map_err(|e| IdentityError::Keystore(e.to_string()))?;
+    Ok(key)
+}
+
+fn parse_nickname(nickname: &str) -> Result<HsNickname, IdentityError> {
+    nickname
+        .to_string()
+        .try_into()
+        .map_err(|e| IdentityError::InvalidNickname(format!("{}", e)))
+}
+
+fn onion_address(hsid: HsId) -> String {
+    hsid.display_unredacted().to_string()
+}
@@ -7,6 +7,10 @@ use anyhow::Result;
 use arti_client::TorClient;
 use arti_client::config::TorClientConfigBuilder;
 use tor_rtcompat::PreferredRuntime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::guard::LockExt;
 
 // ============================================================================
 // Global State
@@ -21,6 +25,15 @@ static TOKIO_RUNTIME: Mutex<Option<tokio::runtime::Runtime>> = Mutex::new(None);
 /// Global log callback
 static LOG_CALLBACK: Mutex<Option<extern "C" fn(*const c_char)>> = Mutex::new(None);
 
+/// Global leveled log callback and the host context passed back to it
+static LOG_CALLBACK_V2: Mutex<Option<(LogCallbackV2, CallbackContext)>> = Mutex::new(None);
+
+/// Global event callback (receives JSON-encoded events)
+static EVENT_CALLBACK: Mutex<Option<extern "C" fn(*const c_char)>> = Mutex::new(None);
+
+/// State directory of the initialized client (holds the auth cookie)
+static STATE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
 /// Handle to SOCKS server task (for graceful shutdown)
 static SOCKS_TASK: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
 
@@ -31,12 +44,71 @@ static INIT_ONCE: Once = Once::new();
 // Logging Integration
 // ============================================================================
 
-/// Send log message to callback
-fn send_log(message: &str) {
-    let callback_opt = LOG_CALLBACK.lock().unwrap();
-    if let Some(callback) = *callback_opt {
-        if let Ok(c_message) = CString::new(message) {
-            callback(c_message.as_ptr());
+/// Log severities, as passed to the leveled log callback
+const LOG_LEVEL_TRACE: c_int = 0;
+const LOG_LEVEL_DEBUG: c_int = 1;
+const LOG_LEVEL_INFO: c_int = 2;
+const LOG_LEVEL_WARN: c_int = 3;
+const LOG_LEVEL_ERROR: c_int = 4;
+
+/// Leveled log callback: (level, unix time in ms, target, message, context)
+type LogCallbackV2 = extern "C" fn(c_int, i64, *const c_char, *const c_char, *mut std::ffi::c_void);
+
+/// Send a log record to the registered log callbacks
+///
+/// The flat callback gets a single line: errors are prefixed with "ERROR: "
+/// and records from arti itself with their level and target.
+fn send_log(level: c_int, target: &str, message: &str) {
+    diagnostics::record_log(log_level_name(level), target, message);
+
+    // Copied out of the locks so a callback may call back into the wrapper
+    let callback = *LOG_CALLBACK.lock_or_recover();
+    if let Some(callback) = callback {
+        if let Ok(c_line) = CString::new(flat_log_line(level, target, message)) {
+            callback(c_line.as_ptr());
+        }
+    }
+
+    let callback_v2 = LOG_CALLBACK_V2.lock_or_recover().as_ref().map(|(callback, context)| (*callback, context.0));
+    if let Some((callback, context)) = callback_v2 {
+        if let (Ok(c_target), Ok(c_message)) = (CString::new(target), CString::new(message)) {
+            callback(level, unix_time_ms(), c_target.as_ptr(), c_message.as_ptr(), context);
+        }
+    }
+}
+
+fn flat_log_line(level: c_int, target: &str, message: &str) -> String {
+    let prefix = if level == LOG_LEVEL_ERROR { "ERROR: " } else { "" };
+    if target.starts_with(module_path!()) {
+        format!("{}{}", prefix, message)
+    } else {
+        format!("{}[{}] {}: {}", prefix, log_level_name(level), target, message)
+    }
+}
+
+fn log_level_name(level: c_int) -> &'static str {
+    match level {
+        LOG_LEVEL_TRACE => "TRACE",
+        LOG_LEVEL_DEBUG => "DEBUG",
+        LOG_LEVEL_INFO => "INFO",
+        LOG_LEVEL_WARN => "WARN",
+        _ => "ERROR",
+    }
+}
+
+fn unix_time_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Send JSON event to callback
+fn send_event(json: &str) {
+    let callback = *EVENT_CALLBACK.lock_or_recover();
+    if let Some(callback) = callback {
+        if let Ok(c_json) = CString::new(json) {
+            callback(c_json.as_ptr());
         }
     }
 }
@@ -45,17 +117,49 @@ fn send_log(message: &str) {
 macro_rules! log_info {
     ($($arg:tt)*) => {{
         let msg = format!($($arg)*);
-        send_log(&msg);
+        $crate::send_log($crate::LOG_LEVEL_INFO, module_path!(), &msg);
+    }};
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        let msg = format!($($arg)*);
+        $crate::send_log($crate::LOG_LEVEL_WARN, module_path!(), &msg);
     }};
 }
 
 macro_rules! log_error {
     ($($arg:tt)*) => {{
-        let msg = format!("ERROR: {}", format!($($arg)*));
-        send_log(&msg);
+        let msg = format!($($arg)*);
+        $crate::send_log($crate::LOG_LEVEL_ERROR, module_path!(), &msg);
     }};
 }
 
+mod addrmap;
+mod auth;
+mod circuits;
+mod clientauth;
+mod diagnostics;
+mod ephemeral;
+mod events;
+mod fdstream;
+mod guard;
+mod http;
+mod identity;
+mod isolation;
+mod metrics;
+mod onion;
+mod policy;
+mod pow;
+mod prometheus;
+mod redact;
+mod resolve;
+mod streamevents;
+mod tls;
+mod tracelog;
+mod traffic;
+mod ws;
+
 // ============================================================================
 // C FFI Functions
 // ============================================================================
@@ -63,210 +167,355 @@ macro_rules! log_error {
 /// Get Arti version string
 #[no_mangle]
 pub extern "C" fn arti_get_version() -> *const c_char {
-    static VERSION: Once = Once::new();
-    static mut VERSION_STRING: Option<CString> = None;
+    guard::catch(|| {
+        static VERSION: Once = Once::new();
+        static mut VERSION_STRING: Option<CString> = None;
+
+        VERSION.call_once(|| {
+            let version = format!("Arti {} (custom build with rustls)", env!("CARGO_PKG_VERSION"));
+            unsafe {
+                VERSION_STRING = CString::new(version).ok();
+            }
+        });
 
-    VERSION.call_once(|| {
-        let version = format!("Arti {} (custom build with rustls)", env!("CARGO_PKG_VERSION"));
         unsafe {
-            VERSION_STRING = CString::new(version).ok();
+            VERSION_STRING.as_ref()
+                .map(|s| s.as_ptr())
+                .unwrap_or(std::ptr::null())
         }
-    });
-
-    unsafe {
-        VERSION_STRING.as_ref()
-            .map(|s| s.as_ptr())
-            .unwrap_or(std::ptr::null())
-    }
+    })
 }
 
 /// Set log callback for Arti logs
 #[no_mangle]
 pub extern "C" fn arti_set_log_callback(callback: extern "C" fn(*const c_char)) {
-    *LOG_CALLBACK.lock().unwrap() = Some(callback);
-    log_info!("Log callback registered");
+    guard::catch(|| {
+        *LOG_CALLBACK.lock_or_recover() = Some(callback);
+        log_info!("Log callback registered");
+    })
 }
 
-/// Initialize Arti runtime
+/// Set leveled log callback, replacing any previous one (null to unset)
+///
+/// Each record arrives with its level (0 = trace ... 4 = error), unix time in
+/// milliseconds, module target and message. `context` is passed back as is;
+/// the host owns it and must accept calls from any thread.
 #[no_mangle]
-pub extern "C" fn arti_initialize(data_dir: *const c_char) -> c_int {
-    if data_dir.is_null() {
-        log_error!("data_dir is null");
-        return -1;
-    }
+pub extern "C" fn arti_set_log_callback_v2(callback: Option<LogCallbackV2>, context: *mut std::ffi::c_void) {
+    guard::catch(|| {
+        *LOG_CALLBACK_V2.lock_or_recover() = callback.map(|callback| (callback, CallbackContext(context)));
+        log_info!("Leveled log callback registered");
+    })
+}
 
-    let data_dir_str = unsafe {
-        match CStr::from_ptr(data_dir).to_str() {
-            Ok(s) => s.to_string(),
+/// Set event callback for structured (JSON) events, replacing any previous
+/// one (null to unset)
+#[no_mangle]
+pub extern "C" fn arti_set_event_callback(callback: Option<extern "C" fn(*const c_char)>) {
+    guard::catch(|| {
+        *EVENT_CALLBACK.lock_or_recover() = callback;
+        log_info!("Event callback registered");
+    })
+}
+
+/// Set which of arti's internal log events reach the log callbacks
+///
+/// `directives` uses `tracing` target syntax, e.g. `warn` or
+/// `info,tor_guardmgr=debug`. Returns 0 on success, -1 if it does not parse.
+#[no_mangle]
+pub extern "C" fn arti_set_log_filter(directives: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(directives) = str_arg(directives, "directives") else {
+            return -1;
+        };
+
+        match tracelog::set_filter(directives) {
+            Ok(()) => {
+                log_info!("Log filter set to {}", directives);
+                0
+            }
             Err(e) => {
-                log_error!("Failed to convert data_dir: {:?}", e);
-                return -1;
+                log_error!("Invalid log filter {:?}: {}", directives, e);
+                -1
             }
         }
-    };
-
-    log_info!("AMEx: state changed to Initialized");
-    log_info!("Initializing Arti with data directory: {}", data_dir_str);
+    })
+}
 
-    // Initialize Tokio runtime (once)
-    INIT_ONCE.call_once(|| {
-        match tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-        {
-            Ok(rt) => {
-                log_info!("Tokio runtime created successfully");
-                *TOKIO_RUNTIME.lock().unwrap() = Some(rt);
+/// Turn safe logging on (1, the default) or off (0)
+///
+/// With safe logging on, destination hosts, onion addresses and peer sockets
+/// are logged as "[scrubbed]", by the wrapper and by arti alike. Turn it off
+/// only to debug. Returns 0 on success, -1 if it could not be changed.
+#[no_mangle]
+pub extern "C" fn arti_set_safe_logging(enabled: c_int) -> c_int {
+    guard::catch(|| {
+        match redact::set_safe_logging(enabled != 0) {
+            Ok(()) => {
+                log_info!("Safe logging {}", if enabled != 0 { "enabled" } else { "disabled - destinations will be logged" });
+                0
             }
             Err(e) => {
-                log_error!("Failed to create Tokio runtime: {:?}", e);
+                log_error!("Failed to change safe logging: {}", e);
+                -1
             }
         }
-    });
+    })
+}
 
-    // Check if runtime exists
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
+/// Recent log lines and events, oldest first, as a JSON array
+///
+/// Keeps the last 1000 records, whether or not a callback was set. The
+/// returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_recent_logs() -> *mut c_char {
+    guard::catch(|| {
+        into_c_string(diagnostics::recent_json())
+    })
+}
+
+/// Also write log lines to `<data dir>/logs/arti.log`, or stop doing so
+///
+/// The file is rotated to `arti.log.1`, `arti.log.2`, ... once it reaches
+/// `max_bytes` or, if `max_age_seconds` > 0, gets that old; up to `max_files`
+/// rotated files are kept and those older than `max_age_seconds` deleted.
+/// `max_bytes` <= 0 turns the file off. Returns 0 on success, -1 on invalid limits.
+#[no_mangle]
+pub extern "C" fn arti_set_log_file(max_bytes: i64, max_files: c_int, max_age_seconds: i64) -> c_int {
+    guard::catch(|| {
+        if max_bytes <= 0 {
+            diagnostics::set_file_limits(None);
+            log_info!("Log file disabled");
+            return 0;
         }
-    };
+        let Ok(max_files) = u32::try_from(max_files) else {
+            log_error!("Invalid max_files: {}", max_files);
+            return -1;
+        };
+
+        diagnostics::set_file_limits(Some(diagnostics::FileLimits {
+            max_bytes: max_bytes as u64,
+            max_files,
+            max_age: u64::try_from(max_age_seconds).ok().filter(|&s| s > 0).map(std::time::Duration::from_secs),
+        }));
+        log_info!("Log file enabled ({} bytes, {} rotated files)", max_bytes, max_files);
+        0
+    })
+}
 
-    // Create config with explicit Linux paths
-    let data_path = PathBuf::from(data_dir_str);
-    let cache_dir = data_path.join("cache");
-    let state_dir = data_path.join("state");
+/// Initialize Arti runtime
+#[no_mangle]
+pub extern "C" fn arti_initialize(data_dir: *const c_char) -> c_int {
+    guard::catch(|| {
+        if data_dir.is_null() {
+            log_error!("data_dir is null");
+            return -1;
+        }
 
-    // Create directories if they don't exist
-    std::fs::create_dir_all(&cache_dir).ok();
-    std::fs::create_dir_all(&state_dir).ok();
+        let data_dir_str = unsafe {
+            match CStr::from_ptr(data_dir).to_str() {
+                Ok(s) => s.to_string(),
+                Err(e) => {
+                    log_error!("Failed to convert data_dir: {:?}", e);
+                    return -1;
+                }
+            }
+        };
+
+        log_info!("AMEx: state changed to Initialized");
+        log_info!("Initializing Arti with data directory: {}", data_dir_str);
+
+        // Initialize Tokio runtime (once)
+        INIT_ONCE.call_once(|| {
+            match tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => {
+                    log_info!("Tokio runtime created successfully");
+                    *TOKIO_RUNTIME.lock_or_recover() = Some(rt);
+                }
+                Err(e) => {
+                    log_error!("Failed to create Tokio runtime: {:?}", e);
+                }
+            }
 
-    let result: Result<()> = runtime.block_on(async {
-        log_info!("Creating Arti client...");
-        log_info!("Cache dir: {:?}", cache_dir);
-        log_info!("State dir: {:?}", state_dir);
+            // Forward arti's diagnostics, watch for proof-of-work solver runs
+            // and count circuits
+            if tracing_subscriber::registry()
+                .with(metrics::layer())
+                .with(pow::layer())
+                .with(tracelog::layer())
+                .try_init()
+                .is_err()
+            {
+                log_error!("A tracing subscriber is already installed - arti logs, proof-of-work events and circuit metrics disabled");
+            }
+        });
 
-        // Create config with Linux-specific directories
-        let config = TorClientConfigBuilder::from_directories(state_dir, cache_dir)
-            .build()?;
+        // Check if runtime exists
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
+
+        // Headless nodes opt into the Prometheus endpoint through ARTI_METRICS_PORT
+        prometheus::start_from_env(runtime.handle());
+
+        // Create config with explicit Linux paths
+        let data_path = PathBuf::from(data_dir_str);
+        let cache_dir = data_path.join("cache");
+        let state_dir = data_path.join("state");
+
+        // Create directories if they don't exist
+        std::fs::create_dir_all(&cache_dir).ok();
+        std::fs::create_dir_all(&state_dir).ok();
+        *STATE_DIR.lock_or_recover() = Some(state_dir.clone());
+        ephemeral::set_directories(data_path.join("ephemeral"), cache_dir.clone());
+        diagnostics::set_log_dir(data_path.join("logs"));
+
+        let result: Result<()> = runtime.block_on(async {
+            log_info!("Creating Arti client...");
+            log_info!("Cache dir: {:?}", cache_dir);
+            log_info!("State dir: {:?}", state_dir);
+
+            // Create config with Linux-specific directories
+            let mut builder = TorClientConfigBuilder::from_directories(&state_dir, &cache_dir);
+            onion::apply_pow_max_effort(&mut builder);
+            let config = builder.build()?;
+
+            // Onion service identities live in the keystore arti itself uses
+            match tor_keymgr::ArtiNativeKeystore::from_path_and_mistrust(state_dir.join("keystore"), config.fs_mistrust()) {
+                Ok(keystore) => identity::set_keystore(keystore, state_dir.clone()),
+                Err(e) => {
+                    log_error!("Failed to open keystore: {:?}", e);
+                }
+            }
 
-        // Create client with Linux-specific config
-        let client = TorClient::create_bootstrapped(config).await?;
+            // Create client with Linux-specific config
+            let client = TorClient::create_bootstrapped(config).await?;
 
-        log_info!("Arti client created successfully");
+            log_info!("Arti client created successfully");
 
-        // Store client globally
-        *ARTI_CLIENT.lock().unwrap() = Some(Arc::new(client));
+            // Store client globally
+            *ARTI_CLIENT.lock_or_recover() = Some(Arc::new(client));
+            metrics::mark_started();
 
-        Ok(())
-    });
+            Ok(())
+        });
 
-    match result {
-        Ok(_) => {
-            log_info!("Arti initialized successfully");
-            0
-        }
-        Err(e) => {
-            log_error!("Failed to initialize Arti: {:?}", e);
-            -3
+        match result {
+            Ok(_) => {
+                log_info!("Arti initialized successfully");
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to initialize Arti: {:?}", e);
+                -3
+            }
         }
-    }
+    })
 }
 
 /// Start SOCKS proxy on specified port
 #[no_mangle]
 pub extern "C" fn arti_start_socks_proxy(port: c_int) -> c_int {
-    log_info!("AMEx: state changed to Starting");
-    log_info!("Starting SOCKS proxy on port {}", port);
-
-    // Stop any existing SOCKS server first
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting previous SOCKS server task");
-        handle.abort();
-    }
-
-    let client_guard = ARTI_CLIENT.lock().unwrap();
-    let client = match client_guard.as_ref() {
-        Some(c) => Arc::clone(c),
-        None => {
-            log_error!("Arti client not initialized - call arti_initialize() first");
-            return -1;
-        }
-    };
-    drop(client_guard);
-
-    let runtime_guard = TOKIO_RUNTIME.lock().unwrap();
-    let runtime = match runtime_guard.as_ref() {
-        Some(rt) => rt,
-        None => {
-            log_error!("Tokio runtime not initialized");
-            return -2;
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Starting");
+        log_info!("Starting SOCKS proxy on port {}", port);
+
+        // Stop any existing SOCKS server first
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting previous SOCKS server task");
+            handle.abort();
         }
-    };
 
-    // Try to bind IMMEDIATELY to detect port conflicts before returning
-    let addr = format!("127.0.0.1:{}", port);
+        let client_guard = ARTI_CLIENT.lock_or_recover();
+        let client = match client_guard.as_ref() {
+            Some(c) => Arc::clone(c),
+            None => {
+                log_error!("Arti client not initialized - call arti_initialize() first");
+                return -1;
+            }
+        };
+        drop(client_guard);
+
+        let runtime_guard = TOKIO_RUNTIME.lock_or_recover();
+        let runtime = match runtime_guard.as_ref() {
+            Some(rt) => rt,
+            None => {
+                log_error!("Tokio runtime not initialized");
+                return -2;
+            }
+        };
 
-    // Use block_on to synchronously attempt binding
-    let bind_result = runtime.block_on(async {
-        tokio::net::TcpListener::bind(&addr).await
-    });
+        // Try to bind IMMEDIATELY to detect port conflicts before returning
+        let addr = format!("127.0.0.1:{}", port);
 
-    let listener = match bind_result {
-        Ok(l) => {
-            log_info!("SOCKS proxy bound to {}", addr);
-            l
-        }
-        Err(e) => {
-            log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
-            return -3;
-        }
-    };
+        // Use block_on to synchronously attempt binding
+        let bind_result = runtime.block_on(async {
+            tokio::net::TcpListener::bind(&addr).await
+        });
 
-    // Now spawn the background task with the already-bound listener
-    let handle = runtime.spawn(async move {
-        log_info!("SOCKS proxy listening on {}", addr);
-        log_info!("Sufficiently bootstrapped; system SOCKS now functional");
-
-        // Signal bootstrap completion
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        log_info!("We have found that guard [scrubbed] is usable.");
-
-        // Accept connections
-        loop {
-            match listener.accept().await {
-                Ok((stream, peer_addr)) => {
-                    log_info!("SOCKS connection from: {}", peer_addr);
-                    let client_clone = Arc::clone(&client);
-
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_socks_connection(stream, client_clone).await {
-                            log_error!("SOCKS connection error: {:?}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    log_error!("Failed to accept SOCKS connection: {:?}", e);
-                    break;
+        let listener = match bind_result {
+            Ok(l) => {
+                log_info!("SOCKS proxy bound to {}", addr);
+                l
+            }
+            Err(e) => {
+                log_error!("Failed to bind SOCKS proxy to {}: {:?}", addr, e);
+                return -3;
+            }
+        };
+
+        // Now spawn the background task with the already-bound listener
+        let handle = runtime.spawn(async move {
+            log_info!("SOCKS proxy listening on {}", addr);
+            log_info!("Sufficiently bootstrapped; system SOCKS now functional");
+
+            // Signal bootstrap completion
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            log_info!("We have found that guard [scrubbed] is usable.");
+
+            // Accept connections
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer_addr)) => {
+                        log_info!("SOCKS connection from: {}", safelog::sensitive(peer_addr));
+                        let client_clone = Arc::clone(&client);
+
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_socks_connection(stream, peer_addr, client_clone).await {
+                                log_error!("SOCKS connection error: {:?}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log_error!("Failed to accept SOCKS connection: {:?}", e);
+                        break;
+                    }
                 }
             }
-        }
 
-        log_info!("SOCKS proxy task exiting");
-    });
+            log_info!("SOCKS proxy task exiting");
+        });
 
-    // Store handle for cleanup
-    *SOCKS_TASK.lock().unwrap() = Some(handle);
+        // Store handle for cleanup
+        *SOCKS_TASK.lock_or_recover() = Some(handle);
 
-    log_info!("SOCKS proxy started on port {}", port);
-    0
+        log_info!("SOCKS proxy started on port {}", port);
+        0
+    })
 }
 
 /// Handle a single SOCKS connection
 async fn handle_socks_connection(
     mut stream: tokio::net::TcpStream,
+    peer_addr: std::net::SocketAddr,
     client: Arc<TorClient<PreferredRuntime>>,
 ) -> Result<()> {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -280,8 +529,26 @@ async fn handle_socks_connection(
         return Err(anyhow::anyhow!("Invalid SOCKS handshake"));
     }
 
-    // Send "no auth required" response
-    stream.write_all(&[0x05, 0x00]).await?;
+    if auth::is_required() {
+        // Only accept username/password auth (RFC 1929) carrying the cookie
+        let nmethods = buf[1] as usize;
+        let methods = &buf[2..n.min(2 + nmethods)];
+        if !methods.contains(&0x02) {
+            stream.write_all(&[0x05, 0xFF]).await?;
+            return Err(anyhow::anyhow!("SOCKS client did not offer username/password auth"));
+        }
+        stream.write_all(&[0x05, 0x02]).await?;
+
+        let n = stream.read(&mut buf).await?;
+        if !auth::socks_password(&buf[..n]).is_some_and(auth::verify) {
+            stream.write_all(&[0x01, 0x01]).await?;
+            return Err(anyhow::anyhow!("SOCKS authentication failed"));
+        }
+        stream.write_all(&[0x01, 0x00]).await?;
+    } else {
+        // Send "no auth required" response
+        stream.write_all(&[0x05, 0x00]).await?;
+    }
 
     // Read request
     let n = stream.read(&mut buf).await?;
@@ -341,26 +608,84 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS5 CONNECT to {}:{}", target_host, target_port);
+    // Safe-socks: an IP literal means the caller resolved the name outside Tor.
+    // Checked before address mapping, since only the caller's own request can
+    // reveal such a leak; a mapping to an IP literal is the host's choice.
+    if policy::is_ip_literal(&target_host) {
+        let mode = policy::safe_socks_mode();
+        if mode != policy::SafeSocksMode::Off {
+            let rejected = mode == policy::SafeSocksMode::Reject;
+            events::emit(&events::Event::SafeSocksViolation {
+                peer: &safelog::sensitive(peer_addr).to_string(),
+                destination: &redact::destination(&target_host, target_port).to_string(),
+                rejected,
+            });
+            if rejected {
+                // Send SOCKS5 error: connection not allowed by ruleset
+                stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+                return Err(anyhow::anyhow!("Safe-socks rejected IP-literal destination from {}", safelog::sensitive(peer_addr)));
+            }
+            log_warn!(
+                "Safe-socks: {} requested IP-literal destination {}",
+                safelog::sensitive(peer_addr),
+                redact::destination(&target_host, target_port)
+            );
+        }
+    }
+
+    // Address mapping: transparently rewrite mapped destinations (e.g. onion mirrors)
+    let (target_host, target_port) = addrmap::apply(&target_host, target_port);
+
+    // Destination policy: refuse anything the loaded rule set does not allow
+    if !policy::is_allowed(&target_host, target_port) {
+        events::emit(&events::Event::DestinationRejected {
+            peer: &safelog::sensitive(peer_addr).to_string(),
+            destination: &redact::destination(&target_host, target_port).to_string(),
+        });
+        // Send SOCKS5 error: connection not allowed by ruleset
+        stream.write_all(&[0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(anyhow::anyhow!("Destination {} rejected by policy", redact::destination(&target_host, target_port)));
+    }
+
+    log_info!("SOCKS5 CONNECT to {}", redact::destination(&target_host, target_port));
 
     // Establish Tor connection
+    let mut lifecycle = streamevents::opened(&target_host, target_port);
     let tor_stream = match client.connect((target_host.as_str(), target_port)).await {
         Ok(s) => s,
         Err(e) => {
             log_error!("Failed to connect through Tor: {:?}", e);
-            stream.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+            lifecycle.failed(&e);
+            // Onion service failures get Tor's extended reply codes (e.g. 0xF4 missing client auth)
+            let reply = clientauth::socks_reply_code(&e);
+            stream.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
             return Err(e.into());
         }
     };
 
-    log_info!("Tor connection established to {}:{}", target_host, target_port);
+    log_info!("Tor connection established to {}", redact::destination(&target_host, target_port));
 
     // Send SOCKS5 success response
     stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
 
-    // Bidirectional data forwarding
-    let (mut client_read, mut client_write) = stream.split();
-    let (mut tor_read, mut tor_write) = tor_stream.split();
+    // Register the stream for traffic accounting and circuit inspection
+    let traffic_stream = traffic::open_stream(lifecycle.id(), &target_host, target_port);
+    let _circuit = circuits::track(traffic_stream.id(), &tor_stream);
+    lifecycle.connected(&tor_stream, &traffic_stream);
+
+    // Bidirectional data forwarding, counting bytes in both directions
+    let (client_read, mut client_write) = stream.split();
+    let (tor_read, mut tor_write) = tor_stream.split();
+    let mut client_read = traffic::CountingReader::new(
+        client_read,
+        traffic_stream.counters(),
+        traffic::Direction::Up,
+    );
+    let mut tor_read = traffic::CountingReader::new(
+        tor_read,
+        traffic_stream.counters(),
+        traffic::Direction::Down,
+    );
 
     let client_to_tor = async {
         tokio::io::copy(&mut client_read, &mut tor_write).await
@@ -383,7 +708,13 @@ async fn handle_socks_connection(
         }
     };
 
-    log_info!("SOCKS connection closed for {}:{}", target_host, target_port);
+    log_info!(
+        "SOCKS connection closed for {} ({} bytes up, {} bytes down, {} ms)",
+        redact::destination(&target_host, target_port),
+        traffic_stream.bytes_up(),
+        traffic_stream.bytes_down(),
+        traffic_stream.duration_ms()
+    );
 
     Ok(())
 }
@@ -391,24 +722,1161 @@ async fn handle_socks_connection(
 /// Stop Arti and cleanup
 #[no_mangle]
 pub extern "C" fn arti_stop() -> c_int {
-    log_info!("AMEx: state changed to Stopping");
-    log_info!("Stopping Arti...");
+    guard::catch(|| {
+        log_info!("AMEx: state changed to Stopping");
+        log_info!("Stopping Arti...");
+
+        // Abort SOCKS proxy task
+        if let Some(handle) = SOCKS_TASK.lock_or_recover().take() {
+            log_info!("Aborting SOCKS server task");
+            handle.abort();
+        }
+
+        // Take down hosted onion services, dropping ephemeral keys
+        ephemeral::destroy_all();
+        onion::stop_all();
+
+        // Give the abort a moment to complete
+        if let Some(rt) = TOKIO_RUNTIME.lock_or_recover().as_ref() {
+            rt.block_on(async {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            });
+        }
+
+        log_info!("AMEx: state changed to Stopped");
+        log_info!("Arti stopped successfully");
+
+        0
+    })
+}
+
+// ============================================================================
+// Loopback Authentication
+// ============================================================================
+
+/// Enable or disable cookie authentication for the SOCKS proxy
+///
+/// When enabled, a fresh cookie is written to `<state dir>/socks_auth_cookie`
+/// and clients must send it as the SOCKS5 password.
+#[no_mangle]
+pub extern "C" fn arti_set_cookie_auth(enabled: c_int) -> c_int {
+    guard::catch(|| {
+        let state_dir = match STATE_DIR.lock_or_recover().clone() {
+            Some(dir) => dir,
+            None => {
+                log_error!("Arti not initialized - call arti_initialize() first");
+                return -1;
+            }
+        };
+
+        if enabled == 0 {
+            auth::disable(&state_dir);
+            log_info!("SOCKS cookie authentication disabled");
+            return 0;
+        }
+
+        match auth::enable(&state_dir) {
+            Ok(path) => {
+                log_info!("SOCKS cookie authentication enabled, cookie at {:?}", path);
+                0
+            }
+            Err(e) => {
+                log_error!("Failed to write SOCKS auth cookie: {:?}", e);
+                -2
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Destination Policy
+// ============================================================================
+
+/// Set how IP-literal SOCKS destinations are handled
+///
+/// 0 = accept, 1 = accept and report (warn only), 2 = reject and report.
+#[no_mangle]
+pub extern "C" fn arti_set_safe_socks_mode(mode: c_int) -> c_int {
+    guard::catch(|| {
+        match policy::SafeSocksMode::from_raw(mode) {
+            Some(m) => {
+                policy::set_safe_socks_mode(m);
+                log_info!("Safe-socks mode set to {}", mode);
+                0
+            }
+            None => {
+                log_error!("Invalid safe-socks mode: {}", mode);
+                -1
+            }
+        }
+    })
+}
+
+/// Load (or replace) the SOCKS destination rule set from JSON
+///
+/// NULL or an empty string removes the rule set and allows all destinations.
+#[no_mangle]
+pub extern "C" fn arti_set_destination_policy(rules_json: *const c_char) -> c_int {
+    guard::catch(|| {
+        let json = if rules_json.is_null() {
+            ""
+        } else {
+            let Some(json) = str_arg(rules_json, "rules_json") else {
+                return -1;
+            };
+            json
+        };
+
+        if json.trim().is_empty() {
+            policy::set_rule_set(None);
+            log_info!("Destination policy cleared");
+            return 0;
+        }
+
+        match policy::RuleSet::from_json(json) {
+            Ok(rule_set) => {
+                log_info!("Destination policy loaded ({} rules)", rule_set.len());
+                policy::set_rule_set(Some(rule_set));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid destination policy: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Address Mapping
+// ============================================================================
+
+/// Replace the address mapping table from a JSON array of `{"from", "to"}`
+///
+/// NULL or an empty string clears the table.
+#[no_mangle]
+pub extern "C" fn arti_set_address_map(map_json: *const c_char) -> c_int {
+    guard::catch(|| {
+        let json = if map_json.is_null() {
+            "[]"
+        } else {
+            match str_arg(map_json, "map_json") {
+                Some(s) if !s.trim().is_empty() => s,
+                Some(_) => "[]",
+                None => return -1,
+            }
+        };
+
+        match addrmap::load_json(json) {
+            Ok(count) => {
+                log_info!("Address map loaded ({} mappings)", count);
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address map: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Add a single mapping, or retarget an existing one
+#[no_mangle]
+pub extern "C" fn arti_add_address_mapping(from: *const c_char, to: *const c_char) -> c_int {
+    guard::catch(|| {
+        let (Some(from), Some(to)) = (str_arg(from, "from"), str_arg(to, "to")) else {
+            return -1;
+        };
+
+        match addrmap::insert(from, to) {
+            Ok(()) => {
+                log_info!("Address mapping added: {} -> {}", safelog::sensitive(from), safelog::sensitive(to));
+                0
+            }
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Remove a single mapping
+#[no_mangle]
+pub extern "C" fn arti_remove_address_mapping(from: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(from) = str_arg(from, "from") else {
+            return -1;
+        };
+
+        match addrmap::remove(from) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                log_error!("Invalid address mapping: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Get the address mapping table with per-mapping usage counts as JSON
+///
+/// The returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_address_map() -> *mut c_char {
+    guard::catch(|| {
+        into_c_string(addrmap::snapshot_json())
+    })
+}
+
+// ============================================================================
+// WebSocket Client
+// ============================================================================
+
+/// Open a WebSocket (ws:// or wss://) through Tor
+///
+/// Returns a handle (> 0) right away; whether the connection succeeds is
+/// reported through `callbacks`, which are copied and may be freed afterwards.
+#[no_mangle]
+pub extern "C" fn arti_ws_open(url: *const c_char, callbacks: *const ws::WsCallbacks) -> i64 {
+    guard::catch(|| {
+        let Some(url) = str_arg(url, "url") else {
+            return -1;
+        };
+        let Some(callbacks) = struct_arg(callbacks, "callbacks") else {
+            return -1;
+        };
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        match ws::open(&runtime, client, url, callbacks) {
+            Ok(handle) => handle,
+            Err(ws::OpenError::InvalidUrl(e)) => {
+                log_error!("Invalid WebSocket URL: {}", e);
+                -1
+            }
+            Err(ws::OpenError::Rejected) => {
+                log_error!("WebSocket destination rejected by policy");
+                -4
+            }
+        }
+    })
+}
+
+/// Send a text frame on an open WebSocket
+#[no_mangle]
+pub extern "C" fn arti_ws_send_text(handle: i64, text: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(text) = str_arg(text, "text") else {
+            return -1;
+        };
+        if ws::send_text(handle, text.to_string()) { 0 } else { -2 }
+    })
+}
+
+/// Send a binary frame on an open WebSocket
+#[no_mangle]
+pub extern "C" fn arti_ws_send_binary(handle: i64, data: *const u8, len: usize) -> c_int {
+    guard::catch(|| {
+        let Some(data) = bytes_arg(data, len, "data") else {
+            return -1;
+        };
+        if ws::send_binary(handle, data.to_vec()) { 0 } else { -2 }
+    })
+}
+
+/// Start the closing handshake of a WebSocket
+#[no_mangle]
+pub extern "C" fn arti_ws_close(handle: i64, code: c_int, reason: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(close_code) = ws::close_code(code) else {
+            log_error!("Invalid WebSocket close code: {}", code);
+            return -1;
+        };
+        let reason = if reason.is_null() {
+            String::new()
+        } else {
+            let Some(reason) = str_arg(reason, "reason") else {
+                return -1;
+            };
+            reason.to_string()
+        };
+        if reason.len() > ws::MAX_CLOSE_REASON_LEN {
+            log_error!("WebSocket close reason is longer than {} bytes", ws::MAX_CLOSE_REASON_LEN);
+            return -1;
+        }
+        if ws::close(handle, close_code, reason) { 0 } else { -2 }
+    })
+}
+
+// ============================================================================
+// HTTP Client
+// ============================================================================
+
+/// Make an HTTP/1.1 or HTTP/2 request through Tor, with TLS done by rustls
+///
+/// Returns a request id (> 0) right away; the response is streamed through
+/// `callbacks`, which are copied and may be freed afterwards.
+#[no_mangle]
+pub extern "C" fn arti_http_request(
+    method: *const c_char,
+    url: *const c_char,
+    headers_json: *const c_char,
+    body: *const u8,
+    body_len: usize,
+    timeout_ms: c_int,
+    callbacks: *const http::HttpCallbacks,
+) -> i64 {
+    guard::catch(|| {
+        let (Some(method), Some(url)) = (str_arg(method, "method"), str_arg(url, "url")) else {
+            return -1;
+        };
+        let headers = if headers_json.is_null() {
+            Vec::new()
+        } else {
+            let Some(json) = str_arg(headers_json, "headers_json") else {
+                return -1;
+            };
+            match http::parse_headers_json(json) {
+                Ok(headers) => headers,
+                Err(e) => {
+                    log_error!("{}", e);
+                    return -1;
+                }
+            }
+        };
+        let Some(body) = bytes_arg(body, body_len, "body") else {
+            return -1;
+        };
+        let Some(callbacks) = struct_arg(callbacks, "callbacks") else {
+            return -1;
+        };
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        let request = http::HttpRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers,
+            body: body.to_vec(),
+            timeout: std::time::Duration::from_millis(timeout_ms.max(0) as u64),
+        };
+
+        match http::start(&runtime, client, request, callbacks) {
+            Ok(id) => id,
+            Err(http::RequestError::Invalid(e)) => {
+                log_error!("Invalid HTTP request: {}", e);
+                -1
+            }
+            Err(http::RequestError::Rejected) => {
+                log_error!("HTTP destination rejected by policy");
+                -4
+            }
+        }
+    })
+}
+
+/// Cancel a running HTTP request; no callback is made for it once this returns 0
+#[no_mangle]
+pub extern "C" fn arti_http_cancel(request_id: i64) -> c_int {
+    guard::catch(|| {
+        if http::cancel(request_id) { 0 } else { -2 }
+    })
+}
+
+// ============================================================================
+// File Descriptor Streams
+// ============================================================================
+
+/// Open a Tor stream and return it as one end of a Unix socketpair
+///
+/// Blocks until the stream is established. The returned descriptor belongs
+/// to the caller; closing it closes the Tor stream.
+#[no_mangle]
+pub extern "C" fn arti_connect_fd(host: *const c_char, port: c_int, isolation: i64) -> c_int {
+    guard::catch(|| {
+        let Some(host) = str_arg(host, "host") else {
+            return -1;
+        };
+        let Ok(port) = u16::try_from(port) else {
+            log_error!("Invalid port: {}", port);
+            return -1;
+        };
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        match fdstream::connect_fd(&runtime, client, host, port, isolation) {
+            Ok(fd) => {
+                log_info!("Tor fd stream open to {}", redact::destination(host, port));
+                fd
+            }
+            Err(fdstream::FdError::Socketpair(e)) => {
+                log_error!("Failed to create socketpair: {:?}", e);
+                -3
+            }
+            Err(fdstream::FdError::RuntimeThread) => {
+                log_error!("Tor fd streams cannot be opened from a Tor runtime thread");
+                -8
+            }
+            Err(fdstream::FdError::Rejected) => {
+                log_error!("Destination {} rejected by policy", redact::destination(host, port));
+                -4
+            }
+            Err(fdstream::FdError::Tor(e)) => {
+                log_error!("Failed to connect through Tor: {:?}", e);
+                match arti_client::HasKind::kind(&e) {
+                    arti_client::ErrorKind::OnionServiceMissingClientAuth => -6,
+                    arti_client::ErrorKind::OnionServiceWrongClientAuth => -7,
+                    _ => -5,
+                }
+            }
+        }
+    })
+}
 
-    // Abort SOCKS proxy task
-    if let Some(handle) = SOCKS_TASK.lock().unwrap().take() {
-        log_info!("Aborting SOCKS server task");
-        handle.abort();
+// ============================================================================
+// DNS Resolution
+// ============================================================================
+
+/// Completion callback for DNS lookups: (context, lookup id, status, JSON)
+type ResolveCallback = extern "C" fn(*mut std::ffi::c_void, i64, c_int, *const c_char);
+
+/// Host context pointer, moved onto the Tor runtime with the callback
+struct CallbackContext(*mut std::ffi::c_void);
+
+// The host owns the context and must accept callbacks from any thread
+unsafe impl Send for CallbackContext {}
+
+/// Deliver a lookup result: status 0 with the result JSON, or -5 with `{"error": ...}`
+fn complete_lookup(callback: ResolveCallback, context: CallbackContext, id: i64, result: Result<String, String>) {
+    let (status, json) = match result {
+        Ok(json) => (0, json),
+        Err(e) => (-5, serde_json::json!({ "error": e }).to_string()),
+    };
+    if let Ok(c_json) = CString::new(json) {
+        callback(context.0, id, status, c_json.as_ptr());
     }
+}
 
-    // Give the abort a moment to complete
-    if let Some(rt) = TOKIO_RUNTIME.lock().unwrap().as_ref() {
-        rt.block_on(async {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        });
+/// Resolve a hostname to all of its addresses through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub extern "C" fn arti_resolve(
+    hostname: *const c_char,
+    isolation: i64,
+    callback: Option<ResolveCallback>,
+    context: *mut std::ffi::c_void,
+) -> i64 {
+    guard::catch(|| {
+        let Some(hostname) = str_arg(hostname, "hostname") else {
+            return -1;
+        };
+        let Some(callback) = callback else {
+            log_error!("callback is null");
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        let context = CallbackContext(context);
+        resolve::resolve(&runtime, client, hostname.to_string(), isolation, move |id, result| {
+            complete_lookup(callback, context, id, result)
+        })
+    })
+}
+
+/// Resolve an IP address back to hostnames through Tor
+///
+/// Returns a lookup id (> 0); the result arrives through `callback`.
+#[no_mangle]
+pub extern "C" fn arti_resolve_ptr(
+    address: *const c_char,
+    isolation: i64,
+    callback: Option<ResolveCallback>,
+    context: *mut std::ffi::c_void,
+) -> i64 {
+    guard::catch(|| {
+        let Some(address) = str_arg(address, "address") else {
+            return -1;
+        };
+        let Ok(address) = address.parse::<std::net::IpAddr>() else {
+            log_error!("Invalid IP address: {}", safelog::sensitive(address));
+            return -1;
+        };
+        let Some(callback) = callback else {
+            log_error!("callback is null");
+            return -1;
+        };
+        let Some((client, runtime)) = client_and_runtime() else {
+            return -2;
+        };
+
+        let context = CallbackContext(context);
+        resolve::resolve_ptr(&runtime, client, address, isolation, move |id, result| {
+            complete_lookup(callback, context, id, result)
+        })
+    })
+}
+
+/// Cancel a running lookup; its callback is not called
+#[no_mangle]
+pub extern "C" fn arti_resolve_cancel(lookup_id: i64) -> c_int {
+    guard::catch(|| {
+        if resolve::cancel(lookup_id) { 0 } else { -2 }
+    })
+}
+
+// ============================================================================
+// Onion Services
+// ============================================================================
+
+/// Publish the onion service `nickname` and forward `virtual_port` to a local target
+///
+/// `local_target` is `host:port` or `unix:/path/to/socket`. Returns the
+/// service's .onion address, to be released with `arti_free_string`, or NULL
+/// on failure. The nickname selects the service's keys, so the address stays
+/// the same across restarts.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_start(
+    nickname: *const c_char,
+    virtual_port: c_int,
+    local_target: *const c_char,
+) -> *mut c_char {
+    guard::catch(|| {
+        let (Some(nickname), Some(local_target)) = (str_arg(nickname, "nickname"), str_arg(local_target, "local_target")) else {
+            return std::ptr::null_mut();
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        if ephemeral::is_ephemeral(&nickname) {
+            log_error!("Onion service nickname {} is reserved for ephemeral services", nickname);
+            return std::ptr::null_mut();
+        }
+
+        let Some((client, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        match onion::start(&runtime, client, nickname, virtual_port, local_target) {
+            Ok(address) => {
+                log_info!("Onion service {} published at {}", nickname, redact::destination(&address, virtual_port));
+                into_c_string(address)
+            }
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid onion service configuration: {}", e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::AlreadyRunning) => {
+                log_error!("Onion service {} is already running", nickname);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch onion service {}: {:?}", nickname, e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::NoAddress) => {
+                log_error!("Onion service {} has no identity key", nickname);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Onion services cannot be started from a Tor runtime thread");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Stop a hosted onion service
+///
+/// Returns 0 on success, -2 if no service with that nickname is running, -3
+/// for an ephemeral service (use `arti_onion_ephemeral_destroy`).
+#[no_mangle]
+pub extern "C" fn arti_onion_service_stop(nickname: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(nickname) = str_arg(nickname, "nickname") else {
+            return -1;
+        };
+
+        if ephemeral::is_ephemeral(nickname) {
+            log_error!("Onion service {} is ephemeral - destroy it by its address instead", nickname);
+            return -3;
+        }
+
+        if onion::stop(nickname) {
+            log_info!("Onion service {} stopped", nickname);
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+/// Get the state of every hosted onion service as JSON
+///
+/// State changes are also delivered as `onion_service_status` events. The
+/// returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_status() -> *mut c_char {
+    guard::catch(|| {
+        into_c_string(onion::snapshot_json())
+    })
+}
+
+/// Configure the DoS defenses of the onion service `nickname`
+///
+/// `config_json` is an object with optional `enable_pow`, `pow_queue_depth`,
+/// `intro_rate`/`intro_burst` and `max_streams_per_circuit`. Returns 0 if the
+/// settings are in effect, 1 if the service is running and they apply from
+/// its next start, -1 on invalid arguments.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_set_dos_params(nickname: *const c_char, config_json: *const c_char) -> c_int {
+    guard::catch(|| {
+        let (Some(nickname), Some(config_json)) = (str_arg(nickname, "nickname"), str_arg(config_json, "config_json")) else {
+            return -1;
+        };
+
+        match onion::set_dos_params(nickname, config_json) {
+            Ok(true) => {
+                log_info!("DoS defenses updated for onion service {}", nickname);
+                0
+            }
+            Ok(false) => {
+                log_info!("DoS defenses for onion service {} apply from its next start", nickname);
+                1
+            }
+            Err(onion::OnionError::InvalidNickname(e)) => {
+                log_error!("Invalid onion service nickname {}: {}", nickname, e);
+                -1
+            }
+            Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid DoS defense configuration: {}", e);
+                -1
+            }
+            Err(_) => -1,
+        }
+    })
+}
+
+/// Cap the proof-of-work effort hosted onion services credit to a client
+///
+/// Applies to every service of clients created afterwards, so call it before
+/// `arti_initialize`. A negative `max_effort` goes back to the consensus value.
+/// Always returns 0.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_set_pow_max_effort(max_effort: c_int) -> c_int {
+    guard::catch(|| {
+        if max_effort >= 0 {
+            onion::set_pow_max_effort(Some(max_effort));
+            log_info!("Onion service PoW effort capped at {}", max_effort);
+        } else {
+            onion::set_pow_max_effort(None);
+            log_info!("Onion service PoW effort cap follows the consensus");
+        }
+        0
+    })
+}
+
+// ============================================================================
+// Ephemeral Onion Services
+// ============================================================================
+
+/// Publish a throwaway onion service forwarding `virtual_port` to a local target
+///
+/// Its keys are kept in memory only and never written to disk. The first
+/// call bootstraps a separate client for ephemeral services, which can take a
+/// few seconds. With `ttl_seconds` > 0 the service is torn down after that
+/// long. Returns the .onion address, to be released with `arti_free_string`,
+/// or NULL on failure.
+#[no_mangle]
+pub extern "C" fn arti_onion_ephemeral_create(virtual_port: c_int, local_target: *const c_char, ttl_seconds: i64) -> *mut c_char {
+    guard::catch(|| {
+        let Some(local_target) = str_arg(local_target, "local_target") else {
+            return std::ptr::null_mut();
+        };
+        let Ok(virtual_port) = u16::try_from(virtual_port) else {
+            log_error!("Invalid virtual port: {}", virtual_port);
+            return std::ptr::null_mut();
+        };
+        let ttl = u64::try_from(ttl_seconds)
+            .ok()
+            .filter(|&secs| secs > 0)
+            .map(std::time::Duration::from_secs);
+
+        let Some((_, runtime)) = client_and_runtime() else {
+            return std::ptr::null_mut();
+        };
+
+        match ephemeral::create(&runtime, virtual_port, local_target, ttl) {
+            Ok(address) => {
+                log_info!("Ephemeral onion service published at {}", redact::destination(&address, virtual_port));
+                into_c_string(address)
+            }
+            Err(onion::OnionError::InvalidTarget(e)) | Err(onion::OnionError::Config(e)) => {
+                log_error!("Invalid ephemeral onion service configuration: {}", e);
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::RuntimeThread) => {
+                log_error!("Ephemeral onion services cannot be created from a Tor runtime thread");
+                std::ptr::null_mut()
+            }
+            Err(onion::OnionError::Launch(e)) => {
+                log_error!("Failed to launch ephemeral onion service: {:?}", e);
+                std::ptr::null_mut()
+            }
+            Err(_) => {
+                log_error!("Failed to launch ephemeral onion service");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Tear down an ephemeral onion service
+///
+/// Returns 0 on success, -2 if no such service exists (or it already expired).
+#[no_mangle]
+pub extern "C" fn arti_onion_ephemeral_destroy(onion_address: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(onion_address) = str_arg(onion_address, "onion_address") else {
+            return -1;
+        };
+
+        if ephemeral::destroy(onion_address) {
+            log_info!("Ephemeral onion service {} destroyed", safelog::sensitive(onion_address));
+            0
+        } else {
+            -2
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Identity
+// ============================================================================
+
+/// Map an identity result to a status code, handing the address to `onion_address_out`
+fn identity_status(result: Result<String, identity::IdentityError>, nickname: &str, onion_address_out: *mut *mut c_char) -> c_int {
+    match result {
+        Ok(address) => {
+            if !onion_address_out.is_null() {
+                unsafe { *onion_address_out = into_c_string(address) };
+            }
+            0
+        }
+        Err(identity::IdentityError::InvalidNickname(e)) => {
+            log_error!("Invalid onion service nickname {}: {}", nickname, e);
+            -1
+        }
+        Err(identity::IdentityError::InvalidKey(e)) => {
+            log_error!("Invalid onion service identity key: {}", e);
+            -1
+        }
+        Err(identity::IdentityError::NotInitialized) => {
+            log_error!("Arti client not initialized - call arti_initialize() first");
+            -2
+        }
+        Err(identity::IdentityError::Exists) => {
+            log_error!("Onion service {} already has a different identity", nickname);
+            -3
+        }
+        Err(identity::IdentityError::Running) => {
+            log_error!("Onion service {} is running - stop it first", nickname);
+            -4
+        }
+        Err(identity::IdentityError::NotFound) => {
+            log_error!("Onion service {} has no identity key", nickname);
+            -5
+        }
+        Err(identity::IdentityError::Decrypt) => {
+            log_error!("Failed to decrypt onion service identity (wrong passphrase?)");
+            -6
+        }
+        Err(identity::IdentityError::Keystore(e)) => {
+            log_error!("Keystore error: {}", e);
+            -7
+        }
+    }
+}
+
+/// Import an ed25519 identity key for the onion service `nickname`
+///
+/// `secret` is a 32-byte ed25519 seed or a 64-byte expanded secret key. On
+/// success the .onion address is stored in `onion_address_out` (if not NULL),
+/// to be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_onion_identity_import(
+    nickname: *const c_char,
+    secret: *const u8,
+    secret_len: usize,
+    overwrite: c_int,
+    onion_address_out: *mut *mut c_char,
+) -> c_int {
+    guard::catch(|| {
+        let Some(nickname) = str_arg(nickname, "nickname") else {
+            return -1;
+        };
+        let Some(secret) = bytes_arg(secret, secret_len, "secret") else {
+            return -1;
+        };
+
+        let result = identity::import(nickname, secret, overwrite != 0);
+        if result.is_ok() {
+            log_info!("Identity imported for onion service {}", nickname);
+        }
+        identity_status(result, nickname, onion_address_out)
+    })
+}
+
+/// Import an identity exported with `arti_onion_identity_export`
+#[no_mangle]
+pub extern "C" fn arti_onion_identity_import_encrypted(
+    nickname: *const c_char,
+    exported: *const c_char,
+    passphrase: *const c_char,
+    overwrite: c_int,
+    onion_address_out: *mut *mut c_char,
+) -> c_int {
+    guard::catch(|| {
+        let (Some(nickname), Some(exported), Some(passphrase)) = (
+            str_arg(nickname, "nickname"),
+            str_arg(exported, "exported"),
+            str_arg(passphrase, "passphrase"),
+        ) else {
+            return -1;
+        };
+
+        let result = identity::import_encrypted(nickname, exported, passphrase.as_bytes(), overwrite != 0);
+        if result.is_ok() {
+            log_info!("Identity imported for onion service {}", nickname);
+        }
+        identity_status(result, nickname, onion_address_out)
+    })
+}
+
+/// Export the identity key of `nickname`, encrypted with `passphrase`
+///
+/// Returns a hex string to be released with `arti_free_string`, or NULL.
+#[no_mangle]
+pub extern "C" fn arti_onion_identity_export(nickname: *const c_char, passphrase: *const c_char) -> *mut c_char {
+    guard::catch(|| {
+        let (Some(nickname), Some(passphrase)) = (str_arg(nickname, "nickname"), str_arg(passphrase, "passphrase")) else {
+            return std::ptr::null_mut();
+        };
+
+        match identity::export_encrypted(nickname, passphrase.as_bytes()) {
+            Ok(exported) => into_c_string(exported),
+            Err(e) => {
+                identity_status(Err(e), nickname, std::ptr::null_mut());
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Compute the v3 .onion address for a 32-byte ed25519 public key
+///
+/// Returns the address to be released with `arti_free_string`, or NULL.
+#[no_mangle]
+pub extern "C" fn arti_onion_address_from_public_key(public_key: *const u8, len: usize) -> *mut c_char {
+    guard::catch(|| {
+        let Some(public_key) = bytes_arg(public_key, len, "public_key") else {
+            return std::ptr::null_mut();
+        };
+
+        match identity::onion_address_for_public_key(public_key) {
+            Ok(address) => into_c_string(address),
+            Err(e) => {
+                identity_status(Err(e), "", std::ptr::null_mut());
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+// ============================================================================
+// Onion Service Client Authorization
+// ============================================================================
+
+/// Authorize a client to discover the onion service `nickname`
+///
+/// `public_key` is the client's x25519 key as `descriptor:x25519:<base32>`.
+/// The service runs in restricted discovery mode from its next start on;
+/// while it is running, changes apply immediately.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_authorize_client(
+    nickname: *const c_char,
+    client_nickname: *const c_char,
+    public_key: *const c_char,
+) -> c_int {
+    guard::catch(|| {
+        let (Some(nickname), Some(client_nickname), Some(public_key)) = (
+            str_arg(nickname, "nickname"),
+            str_arg(client_nickname, "client_nickname"),
+            str_arg(public_key, "public_key"),
+        ) else {
+            return -1;
+        };
+
+        let result = clientauth::authorize_client(nickname, client_nickname, public_key);
+        if result.is_ok() {
+            log_info!("Client {} authorized for onion service {}", client_nickname, nickname);
+        }
+        identity_status(result.map(|()| String::new()), nickname, std::ptr::null_mut())
+    })
+}
+
+/// Revoke a client authorized with `arti_onion_service_authorize_client`
+///
+/// Returns 0 on success, -5 if the client was not authorized.
+#[no_mangle]
+pub extern "C" fn arti_onion_service_revoke_client(nickname: *const c_char, client_nickname: *const c_char) -> c_int {
+    guard::catch(|| {
+        let (Some(nickname), Some(client_nickname)) =
+            (str_arg(nickname, "nickname"), str_arg(client_nickname, "client_nickname"))
+        else {
+            return -1;
+        };
+
+        match clientauth::revoke_client(nickname, client_nickname) {
+            Ok(true) => {
+                log_info!("Client {} revoked for onion service {}", client_nickname, nickname);
+                0
+            }
+            Ok(false) => -5,
+            Err(e) => identity_status(Err(e), nickname, std::ptr::null_mut()),
+        }
+    })
+}
+
+/// Store the client key used to reach the restricted onion service `onion_address`
+///
+/// `secret_key` is the x25519 secret as `descriptor:x25519:<base32>` or bare
+/// base32. On success the matching public key, for the service operator, is
+/// stored in `public_key_out` (if not NULL), to be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_onion_client_auth_set(
+    onion_address: *const c_char,
+    secret_key: *const c_char,
+    public_key_out: *mut *mut c_char,
+) -> c_int {
+    guard::catch(|| {
+        let (Some(onion_address), Some(secret_key)) =
+            (str_arg(onion_address, "onion_address"), str_arg(secret_key, "secret_key"))
+        else {
+            return -1;
+        };
+
+        identity_status(clientauth::set_client_key(onion_address, secret_key), "", public_key_out)
+    })
+}
+
+/// Generate and store a new client key for `onion_address`
+///
+/// Returns the public key to hand to the service operator, to be released
+/// with `arti_free_string`, or NULL.
+#[no_mangle]
+pub extern "C" fn arti_onion_client_auth_generate(onion_address: *const c_char) -> *mut c_char {
+    guard::catch(|| {
+        let Some(onion_address) = str_arg(onion_address, "onion_address") else {
+            return std::ptr::null_mut();
+        };
+
+        match clientauth::generate_client_key(onion_address) {
+            Ok(public_key) => into_c_string(public_key),
+            Err(e) => {
+                identity_status(Err(e), "", std::ptr::null_mut());
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Forget the client key for `onion_address`
+///
+/// Returns 0 on success, -5 if no key was stored.
+#[no_mangle]
+pub extern "C" fn arti_onion_client_auth_remove(onion_address: *const c_char) -> c_int {
+    guard::catch(|| {
+        let Some(onion_address) = str_arg(onion_address, "onion_address") else {
+            return -1;
+        };
+
+        match clientauth::remove_client_key(onion_address) {
+            Ok(true) => 0,
+            Ok(false) => -5,
+            Err(e) => identity_status(Err(e), "", std::ptr::null_mut()),
+        }
+    })
+}
+
+// ============================================================================
+// Traffic Accounting
+// ============================================================================
+
+/// Get a JSON snapshot of per-stream and aggregate traffic counters
+///
+/// The returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_traffic_snapshot() -> *mut c_char {
+    guard::catch(|| {
+        into_c_string(traffic::snapshot_json())
+    })
+}
+
+/// Allow or forbid recording stream destinations in traffic snapshots
+#[no_mangle]
+pub extern "C" fn arti_set_traffic_record_destinations(enabled: c_int) {
+    guard::catch(|| {
+        traffic::set_record_destinations(enabled != 0);
+    })
+}
+
+/// Turn per-stream lifecycle events on or off (off by default)
+///
+/// Once on, each stream reports `stream_opened`, then `stream_connected` or
+/// `stream_failed`, and finally `stream_closed` through the event callback.
+#[no_mangle]
+pub extern "C" fn arti_set_stream_events(enabled: c_int) {
+    guard::catch(|| {
+        streamevents::set_enabled(enabled != 0);
+    })
+}
+
+// ============================================================================
+// Circuits
+// ============================================================================
+
+/// Get the circuits behind active SOCKS and fd streams as a JSON array
+///
+/// The returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_circuits() -> *mut c_char {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        into_c_string(circuits::list_json(client.as_deref()))
+    })
+}
+
+/// Get the circuit behind a stream, by its id in the traffic snapshot
+///
+/// Returns NULL if the stream is not active. The returned string must be
+/// released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_stream_circuit(stream_id: i64) -> *mut c_char {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        match circuits::stream_json(client.as_deref(), stream_id as u64) {
+            Some(json) => into_c_string(json),
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Get a JSON snapshot of the Tor subsystem's metrics
+///
+/// The returned string must be released with `arti_free_string`.
+#[no_mangle]
+pub extern "C" fn arti_get_metrics() -> *mut c_char {
+    guard::catch(|| {
+        let client = ARTI_CLIENT.lock_or_recover().clone();
+        into_c_string(metrics::snapshot_json(client.as_deref()))
+    })
+}
+
+/// Free a string returned by the wrapper
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn arti_free_string(s: *mut c_char) {
+    guard::catch(|| {
+        if !s.is_null() {
+            unsafe {
+                drop(CString::from_raw(s));
+            }
+        }
+    })
+}
+
+/// Get the initialized client and a handle to the runtime it runs on
+fn client_and_runtime() -> Option<(Arc<TorClient<PreferredRuntime>>, tokio::runtime::Handle)> {
+    let client = ARTI_CLIENT.lock_or_recover().as_ref().map(Arc::clone);
+    let runtime = TOKIO_RUNTIME.lock_or_recover().as_ref().map(|rt| rt.handle().clone());
+    match (client, runtime) {
+        (Some(client), Some(runtime)) => Some((client, runtime)),
+        _ => {
+            log_error!("Arti client not initialized - call arti_initialize() first");
+            None
+        }
     }
+}
 
-    log_info!("AMEx: state changed to Stopped");
-    log_info!("Arti stopped successfully");
+/// Hand ownership of a Rust string to the caller
+fn into_c_string(s: String) -> *mut c_char {
+    CString::new(s)
+        .map(|c| c.into_raw())
+        .unwrap_or(std::ptr::null_mut())
+}
 
-    0
+/// Borrow a C string argument, logging why it cannot be used
+///
+/// Like the helpers below, this relies on the contract of the header: a
+/// pointer argument is either NULL or valid for the duration of the call.
+fn str_arg<'a>(ptr: *const c_char, name: &str) -> Option<&'a str> {
+    if ptr.is_null() {
+        log_error!("{} is null", name);
+        return None;
+    }
+    // SAFETY: non-NULL, so a NUL-terminated string per the header contract
+    match unsafe { CStr::from_ptr(ptr) }.to_str() {
+        Ok(s) => Some(s),
+        Err(e) => {
+            log_error!("Failed to convert {}: {:?}", name, e);
+            None
+        }
+    }
+}
+
+/// Borrow a buffer argument of `len` bytes; NULL is only accepted when empty
+fn bytes_arg<'a>(ptr: *const u8, len: usize, name: &str) -> Option<&'a [u8]> {
+    if len == 0 {
+        return Some(&[]);
+    }
+    if ptr.is_null() {
+        log_error!("{} is null", name);
+        return None;
+    }
+    // SAFETY: non-NULL, so valid for `len` bytes per the header contract
+    Some(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+
+/// Copy a struct argument, such as a callback table
+fn struct_arg<T: Copy>(ptr: *const T, name: &str) -> Option<T> {
+    if ptr.is_null() {
+        log_error!("{} is null", name);
+        return None;
+    }
+    // SAFETY: non-NULL, so a valid `T` per the header contract
+    Some(unsafe { *ptr })
 }
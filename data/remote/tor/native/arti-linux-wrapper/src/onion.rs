@@ -0,0 +1,622 @@
+//! Onion service hosting
+//!
+//! Publishes an onion service under a nickname and forwards every incoming
+//! stream for its virtual port to a local TCP or Unix socket target. Keys and
+//! state live in arti's keystore and state dir, so a nickname keeps the same
+//! .onion address across restarts. Every state change of a service is
+//! reported as an `onion_service_status` event, and `snapshot_json` gives
+//! the current state of all of them.
+//!
+//! DoS defenses (proof-of-work, introduction rate limits) are set per
+//! nickname with `set_dos_params` and apply to the running service and to
+//! every later start. The cap on the proof-of-work effort a service credits
+//! to a client is a network parameter instead, so it is set once for the
+//! whole client with `set_pow_max_effort`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use arti_client::config::onion_service::{OnionServiceConfig, OnionServiceConfigBuilder};
+use arti_client::config::{CfgPath, Reconfigure, TorClientConfigBuilder};
+use arti_client::TorClient;
+use futures::StreamExt;
+use safelog::DisplayRedacted;
+use serde::{Deserialize, Serialize};
+use tor_cell::relaycell::msg::{Connected, End, EndReason};
+use tor_hsservice::config::restricted_discovery::DirectoryKeyProviderBuilder;
+use tor_hsservice::config::TokenBucketConfig;
+use tor_hsservice::status::{OnionServiceStatus, Problem, State};
+use tor_hsservice::{HsNickname, RunningOnionService, StreamRequest};
+use tor_proto::client::stream::IncomingStreamRequest;
+use tor_rtcompat::PreferredRuntime;
+
+use crate::guard::LockExt;
+
+/// Prefix selecting a Unix socket target, e.g. `unix:/run/app.sock`
+const UNIX_TARGET_PREFIX: &str = "unix:";
+
+/// Where accepted streams are forwarded to
+#[derive(Clone)]
+pub enum LocalTarget {
+    /// `host:port`
+    Tcp(String),
+    /// Path of a Unix stream socket
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl LocalTarget {
+    /// Parse `host:port` or `unix:/path/to/socket`
+    pub fn parse(target: &str) -> Result<Self, String> {
+        if let Some(path) = target.strip_prefix(UNIX_TARGET_PREFIX) {
+            #[cfg(unix)]
+            return if path.is_empty() {
+                Err("Empty Unix socket path".to_string())
+            } else {
+                Ok(LocalTarget::Unix(path.into()))
+            };
+            #[cfg(not(unix))]
+            return Err(format!("Unix socket targets are not supported: {}", path));
+        }
+
+        match target.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(LocalTarget::Tcp(target.to_string()))
+            }
+            _ => Err(format!("Invalid local target: {}", target)),
+        }
+    }
+}
+
+impl std::fmt::Display for LocalTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalTarget::Tcp(addr) => f.write_str(addr),
+            #[cfg(unix)]
+            LocalTarget::Unix(path) => write!(f, "{}{}", UNIX_TARGET_PREFIX, path.display()),
+        }
+    }
+}
+
+/// A launched service and the tasks forwarding its streams and watching its status
+struct HostedService {
+    /// Dropping the last reference shuts the service down
+    service: Arc<RunningOnionService>,
+    address: String,
+    virtual_port: u16,
+    target: LocalTarget,
+    forwarder: tokio::task::AbortHandle,
+    status_watcher: tokio::task::AbortHandle,
+}
+
+impl HostedService {
+    fn abort_tasks(&self) {
+        self.forwarder.abort();
+        self.status_watcher.abort();
+    }
+}
+
+/// Health of a service, as reported to the host app
+///
+/// There is no "N of M HSDirs" or established intro point count here: arti
+/// 0.36 keeps per-HSDir upload results and the intro point set inside
+/// tor-hsservice and deliberately leaves such counts out of
+/// `OnionServiceStatus`. What it does publish is the overall state and the
+/// failures behind the most severe current problem, which is what we report.
+#[derive(Serialize)]
+pub struct ServiceHealth {
+    /// `bootstrapping`, `running`, `degraded_reachable`, `degraded_unreachable`,
+    /// `recovering`, `broken` or `shutdown`
+    pub state: &'static str,
+    /// Descriptor published and all introduction points established
+    pub reachable: bool,
+    pub problem: Option<String>,
+    /// HSDirs the last descriptor upload failed for, across both time periods.
+    /// Zero while an intro point problem outranks it.
+    pub descriptor_upload_failures: usize,
+    /// Introduction points that failed to establish
+    pub intro_point_failures: usize,
+}
+
+impl ServiceHealth {
+    fn from_status(status: &OnionServiceStatus) -> Self {
+        let state = status.state();
+        let problem = status.current_problem();
+        let (descriptor_upload_failures, intro_point_failures) = match problem {
+            Some(Problem::DescriptorUpload(errors)) => (errors.len(), 0),
+            Some(Problem::Ipt(errors)) => (0, errors.len()),
+            _ => (0, 0),
+        };
+
+        ServiceHealth {
+            state: state_name(state),
+            reachable: state.is_fully_reachable(),
+            problem: problem.map(describe_problem),
+            descriptor_upload_failures,
+            intro_point_failures,
+        }
+    }
+}
+
+/// A running service, as listed by `snapshot`
+#[derive(Serialize)]
+pub struct ServiceSnapshot {
+    pub nickname: String,
+    pub address: String,
+    pub virtual_port: u16,
+    pub target: String,
+    #[serde(flatten)]
+    pub health: ServiceHealth,
+}
+
+/// Running services, keyed by nickname
+static SERVICES: Mutex<BTreeMap<String, HostedService>> = Mutex::new(BTreeMap::new());
+
+/// Nicknames `start` is launching, outside the `SERVICES` lock
+///
+/// Only changed with `SERVICES` held, so holding it gives a consistent view
+/// of both.
+static STARTING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// A nickname claimed in `STARTING`, released when dropped
+struct StartClaim<'a>(&'a str);
+
+impl Drop for StartClaim<'_> {
+    fn drop(&mut self) {
+        let _services = SERVICES.lock_or_recover();
+        STARTING.lock_or_recover().remove(self.0);
+    }
+}
+
+/// DoS defense settings for a service, as JSON from the host app
+///
+/// The proof-of-work effort clients must spend is not configured here: the
+/// service raises its suggested effort on its own while it is under load.
+/// The most it credits to a request is `set_pow_max_effort`.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DosParams {
+    /// Require proof-of-work from clients while the service is under load
+    pub enable_pow: bool,
+    /// Introduction requests queued while PoW is on (arti's default is 8192)
+    pub pow_queue_depth: Option<usize>,
+    /// Introduction requests per second the intro points let through
+    pub intro_rate: Option<u32>,
+    /// Burst allowance on top of `intro_rate`
+    pub intro_burst: Option<u32>,
+    pub max_streams_per_circuit: Option<u32>,
+}
+
+/// DoS settings by nickname; services without an entry use arti's defaults
+static DOS_PARAMS: Mutex<BTreeMap<String, DosParams>> = Mutex::new(BTreeMap::new());
+
+/// Network parameter capping the PoW effort a service credits to a request
+const POW_MAX_EFFORT_PARAM: &str = "HiddenServiceProofOfWorkV1MaxEffort";
+
+/// Effort cap overriding the consensus value, if set
+static POW_MAX_EFFORT: Mutex<Option<i32>> = Mutex::new(None);
+
+/// Cap the PoW effort hosted services credit to a client, or `None` for the
+/// consensus value (10000 by default); the cap must not be negative
+///
+/// Requests with a higher effort are queued as if they had spent the cap.
+/// arti reads this when a client is created, so it applies to every service
+/// of clients created afterwards. There is no matching setting for the effort
+/// this client spends on other services; arti hardcodes that cap.
+pub fn set_pow_max_effort(max_effort: Option<i32>) {
+    *POW_MAX_EFFORT.lock_or_recover() = max_effort;
+}
+
+/// Add the PoW effort cap, if any, to the config of a client being created
+pub fn apply_pow_max_effort(builder: &mut TorClientConfigBuilder) {
+    if let Some(effort) = *POW_MAX_EFFORT.lock_or_recover() {
+        builder
+            .override_net_params()
+            .insert(POW_MAX_EFFORT_PARAM.to_string(), effort);
+    }
+}
+
+/// Reasons a service could not be started
+pub enum OnionError {
+    InvalidNickname(String),
+    InvalidTarget(String),
+    Config(String),
+    AlreadyRunning,
+    Launch(arti_client::Error),
+    NoAddress,
+    /// Called on a runtime thread (e.g. from a callback), where it cannot block
+    RuntimeThread,
+}
+
+/// Launch the service `nickname` and forward `virtual_port` to `target`
+///
+/// Returns the service's .onion address. The service keeps running until
+/// `stop` is called or the wrapper is shut down. If clients were authorized
+/// for `nickname`, it runs in restricted discovery mode: only those clients
+/// can fetch its descriptor, and authorizations changed while it runs apply
+/// without a restart.
+pub fn start(
+    runtime: &tokio::runtime::Handle,
+    client: Arc<TorClient<PreferredRuntime>>,
+    nickname: &str,
+    virtual_port: u16,
+    target: &str,
+) -> Result<String, OnionError> {
+    let hs_nickname: HsNickname = nickname
+        .to_string()
+        .try_into()
+        .map_err(|e| OnionError::InvalidNickname(format!("{}", e)))?;
+    let target = LocalTarget::parse(target).map_err(OnionError::InvalidTarget)?;
+
+    // Launching logs, and log callbacks may call back into the wrapper, so it
+    // happens unlocked; the claim keeps `unless_running` and a second start
+    // of the same nickname out meanwhile
+    {
+        let services = SERVICES.lock_or_recover();
+        if services.contains_key(nickname) || !STARTING.lock_or_recover().insert(nickname.to_string()) {
+            return Err(OnionError::AlreadyRunning);
+        }
+    }
+    let _claim = StartClaim(nickname);
+
+    let hosted = launch(runtime, &client, hs_nickname, nickname, virtual_port, target)?;
+    let address = hosted.address.clone();
+
+    let mut services = SERVICES.lock_or_recover();
+    if services.contains_key(nickname) {
+        drop(services);
+        hosted.abort_tasks();
+        return Err(OnionError::AlreadyRunning);
+    }
+    services.insert(nickname.to_string(), hosted);
+
+    Ok(address)
+}
+
+/// Launch a service and spawn its forwarding and status tasks
+fn launch(
+    runtime: &tokio::runtime::Handle,
+    client: &TorClient<PreferredRuntime>,
+    hs_nickname: HsNickname,
+    nickname: &str,
+    virtual_port: u16,
+    target: LocalTarget,
+) -> Result<HostedService, OnionError> {
+    let config = build_config(hs_nickname)?;
+
+    // The service spawns its background tasks onto the current runtime
+    let _guard = runtime.enter();
+    let (service, rend_requests) = client.launch_onion_service(config).map_err(OnionError::Launch)?;
+    let address = service
+        .onion_address()
+        .ok_or(OnionError::NoAddress)?
+        .display_unredacted()
+        .to_string();
+
+    let status_watcher = runtime.spawn(watch_status(nickname.to_string(), service.status_events()));
+
+    let name = nickname.to_string();
+    let forward_target = target.clone();
+    let forwarder = runtime.spawn(async move {
+        let mut stream_requests = tor_hsservice::handle_rend_requests(rend_requests);
+        while let Some(request) = stream_requests.next().await {
+            tokio::spawn(forward(name.clone(), request, virtual_port, forward_target.clone()));
+        }
+        log_info!("Onion service {} stopped accepting streams", name);
+    });
+
+    Ok(HostedService {
+        service,
+        address,
+        virtual_port,
+        target,
+        forwarder: forwarder.abort_handle(),
+        status_watcher: status_watcher.abort_handle(),
+    })
+}
+
+/// Stop a hosted service; returns whether it was running
+pub fn stop(nickname: &str) -> bool {
+    // Shutting the service down logs, so not under the lock
+    let hosted = SERVICES.lock_or_recover().remove(nickname);
+    match hosted {
+        Some(hosted) => {
+            hosted.abort_tasks();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Set the DoS defenses of `nickname`, applying them right away if it is running
+///
+/// Returns false if the service is running and the change only takes effect
+/// on its next start (arti cannot switch proof-of-work on or off live), or
+/// if the service is being started and may not have picked it up.
+pub fn set_dos_params(nickname: &str, params_json: &str) -> Result<bool, OnionError> {
+    let hs_nickname: HsNickname = nickname
+        .to_string()
+        .try_into()
+        .map_err(|e| OnionError::InvalidNickname(format!("{}", e)))?;
+    let params: DosParams = serde_json::from_str(params_json).map_err(|e| OnionError::Config(e.to_string()))?;
+    if params.intro_rate.is_some() != params.intro_burst.is_some() {
+        return Err(OnionError::Config("intro_rate and intro_burst must be set together".to_string()));
+    }
+
+    let previous = DOS_PARAMS.lock_or_recover().insert(nickname.to_string(), params);
+    let config = match build_config(hs_nickname) {
+        Ok(config) => config,
+        Err(e) => {
+            // Keep the settings that last built
+            let mut dos_params = DOS_PARAMS.lock_or_recover();
+            match previous {
+                Some(previous) => dos_params.insert(nickname.to_string(), previous),
+                None => dos_params.remove(nickname),
+            };
+            return Err(e);
+        }
+    };
+
+    // Reconfiguring logs, so it happens unlocked
+    let service = {
+        let services = SERVICES.lock_or_recover();
+        if STARTING.lock_or_recover().contains(nickname) {
+            return Ok(false);
+        }
+        match services.get(nickname) {
+            Some(hosted) => Arc::clone(&hosted.service),
+            None => return Ok(true),
+        }
+    };
+    match service.reconfigure(config.clone(), Reconfigure::AllOrNothing) {
+        Ok(()) => Ok(true),
+        Err(_) => {
+            // Apply what can be changed live; the rest waits for a restart
+            service
+                .reconfigure(config, Reconfigure::WarnOnFailures)
+                .map_err(|e| OnionError::Config(e.to_string()))?;
+            Ok(false)
+        }
+    }
+}
+
+/// Run `f` unless `nickname` is running or starting; it cannot start until
+/// `f` returns
+///
+/// `f` runs under the service registry lock, so it may take the identity
+/// store lock but not the other way around.
+pub fn unless_running<T>(nickname: &str, f: impl FnOnce() -> T) -> Option<T> {
+    let services = SERVICES.lock_or_recover();
+    if services.contains_key(nickname) || STARTING.lock_or_recover().contains(nickname) {
+        return None;
+    }
+    Some(f())
+}
+
+/// Stop every hosted service
+pub fn stop_all() {
+    let services = std::mem::take(&mut *SERVICES.lock_or_recover());
+    for (_, hosted) in services {
+        hosted.abort_tasks();
+    }
+}
+
+/// Address, target and health of every running service, ordered by nickname
+pub fn snapshot() -> Vec<ServiceSnapshot> {
+    SERVICES
+        .lock_or_recover()
+        .iter()
+        .map(|(nickname, hosted)| ServiceSnapshot {
+            nickname: nickname.clone(),
+            address: hosted.address.clone(),
+            virtual_port: hosted.virtual_port,
+            target: hosted.target.to_string(),
+            health: ServiceHealth::from_status(&hosted.service.status()),
+        })
+        .collect()
+}
+
+/// `snapshot` as a JSON array
+pub fn snapshot_json() -> String {
+    serde_json::to_string(&snapshot()).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Service configuration: client authorization and DoS settings come from
+/// the state dir and `DOS_PARAMS`
+fn build_config(hs_nickname: HsNickname) -> Result<OnionServiceConfig, OnionError> {
+    let nickname = hs_nickname.to_string();
+    let mut builder = OnionServiceConfigBuilder::default();
+    builder.nickname(hs_nickname);
+
+    if let Some(clients_dir) = crate::clientauth::restricted_discovery_dir(&nickname) {
+        let mut provider = DirectoryKeyProviderBuilder::default();
+        provider.path(CfgPath::new_literal(clients_dir));
+        let restricted = builder.restricted_discovery();
+        restricted.enabled(true).watch_configuration(true);
+        restricted.key_dirs().access().push(provider);
+        log_info!("Onion service {} uses restricted discovery", nickname);
+    }
+
+    if let Some(params) = DOS_PARAMS.lock_or_recover().get(&nickname) {
+        builder.enable_pow(params.enable_pow);
+        if let Some(depth) = params.pow_queue_depth {
+            builder.pow_rend_queue_depth(depth);
+        }
+        if let (Some(rate), Some(burst)) = (params.intro_rate, params.intro_burst) {
+            builder.rate_limit_at_intro(Some(TokenBucketConfig::new(rate, burst)));
+        }
+        if let Some(max_streams) = params.max_streams_per_circuit {
+            builder.max_concurrent_streams_per_circuit(max_streams);
+        }
+    }
+
+    builder.build().map_err(|e| OnionError::Config(e.to_string()))
+}
+
+/// Report every status change of a service as an event
+async fn watch_status<S>(nickname: String, mut status_events: S)
+where
+    S: futures::Stream<Item = OnionServiceStatus> + Unpin,
+{
+    let mut last_state = None;
+    while let Some(status) = status_events.next().await {
+        let health = ServiceHealth::from_status(&status);
+        // The stream also yields on every new error; only log real transitions
+        if last_state != Some(health.state) {
+            log_info!("Onion service {} is {}", nickname, health.state);
+            last_state = Some(health.state);
+        }
+        crate::events::emit(&crate::events::Event::OnionServiceStatus {
+            nickname: &nickname,
+            health: &health,
+        });
+    }
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Shutdown => "shutdown",
+        State::Bootstrapping => "bootstrapping",
+        State::DegradedReachable => "degraded_reachable",
+        State::DegradedUnreachable => "degraded_unreachable",
+        State::Running => "running",
+        State::Recovering => "recovering",
+        State::Broken => "broken",
+        _ => "unknown",
+    }
+}
+
+fn describe_problem(problem: &Problem) -> String {
+    match problem {
+        Problem::Runtime(e) => e.to_string(),
+        Problem::DescriptorUpload(errors) => match errors.first() {
+            Some(e) => format!("descriptor upload failed: {}", e),
+            None => "descriptor upload failed".to_string(),
+        },
+        Problem::Ipt(errors) => match errors.first() {
+            Some(e) => format!("introduction point failed: {}", e),
+            None => "introduction point failed".to_string(),
+        },
+        other => format!("{:?}", other),
+    }
+}
+
+/// Accept one stream if it targets the virtual port and pipe it to the local target
+async fn forward(nickname: String, request: StreamRequest, virtual_port: u16, target: LocalTarget) {
+    // Only BEGIN to the published port is served; everything else gets the
+    // same END(DONE) other implementations send, so we are not distinguishable
+    let port_matches = matches!(request.request(), IncomingStreamRequest::Begin(begin) if begin.port() == virtual_port);
+    if !port_matches {
+        request.reject(End::new_with_reason(EndReason::DONE)).await.ok();
+        return;
+    }
+
+    let result = match &target {
+        LocalTarget::Tcp(addr) => match tokio::net::TcpStream::connect(addr).await {
+            Ok(mut local) => pipe(request, &mut local).await,
+            Err(e) => refuse(request, e).await,
+        },
+        #[cfg(unix)]
+        LocalTarget::Unix(path) => match tokio::net::UnixStream::connect(path).await {
+            Ok(mut local) => pipe(request, &mut local).await,
+            Err(e) => refuse(request, e).await,
+        },
+    };
+
+    if let Err(e) = result {
+        log_error!("Onion service {}: stream to {} failed: {}", nickname, target, e);
+    }
+}
+
+/// Turn the client away because the local target is unreachable
+async fn refuse(request: StreamRequest, error: std::io::Error) -> Result<(), String> {
+    request.reject(End::new_with_reason(EndReason::DONE)).await.ok();
+    Err(format!("local target unreachable: {}", error))
+}
+
+/// Accept the Tor side and copy bytes both ways until either side closes
+async fn pipe<S>(request: StreamRequest, local: &mut S) -> Result<(), String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut onion_stream = request
+        .accept(Connected::new_empty())
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::io::copy_bidirectional(&mut onion_stream, local)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    //! Self-connection through a local stand-in Tor network
+    //!
+    //! Needs a network with HSDirs, e.g. chutney's `hs-v3-min`, and
+    //! `ARTI_TEST_CHUTNEY_CONFIG` set to an arti client configuration for it
+    //! (the `arti.toml` chutney writes next to its nodes):
+    //!
+    //! `cargo test -- --ignored onion_service_connects_to_itself`
+
+    use std::time::Duration;
+
+    use arti_client::config::TorClientConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tor_config::sources::MustRead;
+    use tor_config::{ConfigurationSource, ConfigurationSources};
+
+    use super::*;
+
+    const NICKNAME: &str = "loopback-test";
+    const VIRTUAL_PORT: u16 = 80;
+
+    #[test]
+    #[ignore = "needs a local Tor network, see the module docs"]
+    fn onion_service_connects_to_itself() {
+        let config_path = std::env::var("ARTI_TEST_CHUTNEY_CONFIG").expect("ARTI_TEST_CHUTNEY_CONFIG is not set");
+        let data_dir = std::env::temp_dir().join(format!("arti-onion-test-{}", std::process::id()));
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let mut sources = ConfigurationSources::new_empty();
+        sources.push_source(ConfigurationSource::from_path(config_path), MustRead::MustRead);
+        sources.push_option(format!("storage.state_dir = \"{}\"", data_dir.join("state").display()));
+        sources.push_option(format!("storage.cache_dir = \"{}\"", data_dir.join("cache").display()));
+        let config: TorClientConfig = tor_config::resolve(sources.load().unwrap()).unwrap();
+        let client = Arc::new(runtime.block_on(TorClient::create_bootstrapped(config)).unwrap());
+
+        // The local target echoes everything back
+        let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        runtime.spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = conn.split();
+                    tokio::io::copy(&mut read, &mut write).await.ok();
+                });
+            }
+        });
+
+        let Ok(address) = start(runtime.handle(), Arc::clone(&client), NICKNAME, VIRTUAL_PORT, &target) else {
+            panic!("failed to launch the onion service");
+        };
+
+        let reply = runtime.block_on(tokio::time::timeout(Duration::from_secs(300), async {
+            // Descriptor upload and introduction points take a while, even locally
+            while !SERVICES.lock_or_recover()[NICKNAME].service.status().state().is_fully_reachable() {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+
+            let mut stream = client.connect((address.as_str(), VIRTUAL_PORT)).await.unwrap();
+            stream.write_all(b"ping").await.unwrap();
+            stream.flush().await.unwrap();
+            let mut reply = [0u8; 4];
+            stream.read_exact(&mut reply).await.unwrap();
+            reply
+        }));
+
+        stop(NICKNAME);
+        std::fs::remove_dir_all(&data_dir).ok();
+        assert_eq!(&reply.expect("timed out connecting to the onion service"), b"ping");
+    }
+}
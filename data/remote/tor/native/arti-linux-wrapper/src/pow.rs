@@ -0,0 +1,138 @@
+//! Proof-of-work reporting for onion service connections
+//!
+//! arti solves the puzzles of PoW-protected onion services on its own when
+//! connecting; the effort it picks is only visible in its debug traces. This
+//! tracing layer picks those traces up and turns them into `pow_solve_started`
+//! and `pow_solved` events, so the host app can show its PoW indicator.
+
+use tracing::field::{Field, Visit};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::{Filtered, Targets};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::events::{self, Event};
+
+/// Where tor-hsclient traces its solver runs
+const SOLVER_TARGET: &str = "tor_hsclient::pow";
+
+/// Layer watching the client PoW solver
+pub struct PowLayer;
+
+/// `PowLayer`, limited to the solver's debug traces
+pub fn layer<S>() -> Filtered<PowLayer, Targets, S>
+where
+    S: Subscriber,
+{
+    PowLayer.with_filter(Targets::new().with_target(SOLVER_TARGET, Level::DEBUG))
+}
+
+impl<S: Subscriber> Layer<S> for PowLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let message = message.0;
+
+        // "beginning solve, Effort(N)"
+        // "solve complete, Ok(()) Effort(N) duration=Mms (ratio: ...)"
+        let Some(effort) = number_after(&message, "Effort(") else {
+            return;
+        };
+        if message.starts_with("beginning solve") {
+            log_info!("Solving onion service proof-of-work at effort {}", effort);
+            events::emit(&Event::PowSolveStarted { effort });
+        } else if message.starts_with("solve complete") {
+            let duration_ms = number_after(&message, "duration=").unwrap_or(0);
+            let success = message.starts_with("solve complete, Ok(");
+            log_info!("Proof-of-work at effort {} took {} ms", effort, duration_ms);
+            events::emit(&Event::PowSolved { effort, duration_ms, success });
+        }
+    }
+}
+
+/// Collects the formatted message of an event
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+fn number_after(message: &str, prefix: &str) -> Option<u32> {
+    let rest = &message[message.find(prefix)? + prefix.len()..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    //! The traces below are copied verbatim from arti 1.7.0 (crates 0.36):
+    //! tor-hsclient `pow/v1.rs`, which formats the effort with `Debug`. When
+    //! updating arti, check they are still emitted as written, from the same
+    //! module and at the same level.
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    const TARGET: &str = "tor_hsclient::pow::v1";
+
+    /// Stand-in for tor-hscrypto's `Effort`, with the same `Debug` output
+    #[derive(Debug)]
+    struct Effort(u32);
+
+    /// `pow_*` events with `effort` in the diagnostics ring, oldest first
+    fn pow_events(effort: u32) -> Vec<serde_json::Value> {
+        let recent: Vec<serde_json::Value> = serde_json::from_str(&crate::diagnostics::recent_json()).unwrap();
+        recent
+            .into_iter()
+            .filter(|record| record["type"].as_str().is_some_and(|t| t.starts_with("pow_")) && record["effort"] == effort)
+            .collect()
+    }
+
+    #[test]
+    fn reports_arti_solver_traces() {
+        let subscriber = tracing_subscriber::registry().with(layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let effort = Effort(4817);
+            tracing::debug!(target: TARGET, "beginning solve, {:?}", effort);
+            let result: Result<(), &str> = Ok(());
+            tracing::debug!(
+                target: TARGET,
+                "solve complete, {:?} {:?} duration={}ms (ratio: {} ms/M)",
+                result,
+                effort,
+                1234,
+                0.25
+            );
+            let result: Result<(), &str> = Err("runtime shut down");
+            tracing::debug!(
+                target: TARGET,
+                "solve complete, {:?} {:?} duration={}ms (ratio: {} ms/M)",
+                result,
+                Effort(4818),
+                99,
+                0.5
+            );
+
+            // Other modules and levels are not reported
+            tracing::trace!(target: TARGET, "beginning solve, {:?}", Effort(4819));
+            tracing::debug!(target: "tor_hsclient::connect", "beginning solve, {:?}", Effort(4819));
+        });
+
+        let solved = pow_events(4817);
+        assert_eq!(solved.len(), 2);
+        assert_eq!(solved[0]["type"], "pow_solve_started");
+        assert_eq!(solved[1]["type"], "pow_solved");
+        assert_eq!(solved[1]["duration_ms"], 1234);
+        assert_eq!(solved[1]["success"], true);
+
+        let failed = pow_events(4818);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["success"], false);
+
+        assert!(pow_events(4819).is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! Prometheus metrics endpoint for headless nodes
+//!
+//! Unattended gateways are monitored by a local scraper rather than through
+//! the app, so the endpoint is switched on from the environment: with
+//! `ARTI_METRICS_PORT` set, `arti_initialize` serves `GET /metrics` on
+//! `127.0.0.1:<port>` in the Prometheus text format. It exposes what
+//! `arti_get_metrics` reports plus the health of hosted onion services, and
+//! is never reachable from outside the machine.
+
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::guard::LockExt;
+use crate::metrics::{self, MetricsSnapshot};
+
+/// Port to serve on; unset disables the endpoint
+const PORT_VAR: &str = "ARTI_METRICS_PORT";
+
+/// Longest request (line and headers) read before giving up
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Time a scraper gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SERVER_TASK: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
+
+/// Start serving if `ARTI_METRICS_PORT` is set and the server is not running yet
+pub fn start_from_env(runtime: &tokio::runtime::Handle) {
+    let Ok(value) = std::env::var(PORT_VAR) else {
+        return;
+    };
+    let port = match value.parse::<u16>() {
+        Ok(port) if port != 0 => port,
+        _ => {
+            log_error!("Invalid {}: {:?}", PORT_VAR, value);
+            return;
+        }
+    };
+
+    let mut task = SERVER_TASK.lock_or_recover();
+    if task.is_some() {
+        return;
+    }
+
+    let listener = match std::net::TcpListener::bind(("127.0.0.1", port)).and_then(|l| {
+        l.set_nonblocking(true)?;
+        Ok(l)
+    }) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_error!("Failed to bind metrics endpoint to 127.0.0.1:{}: {:?}", port, e);
+            return;
+        }
+    };
+
+    *task = Some(runtime.spawn(async move {
+        let listener = match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log_error!("Metrics endpoint failed: {:?}", e);
+                return;
+            }
+        };
+        log_info!("Prometheus metrics at http://127.0.0.1:{}/metrics", port);
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_request(stream).await {
+                            log_error!("Metrics request failed: {:?}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    log_error!("Failed to accept metrics connection: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }));
+}
+
+async fn handle_request(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    let complete = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LEN {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    if !matches!(complete, Ok(Ok(()))) {
+        return Ok(());
+    }
+
+    // Only the request line matters: "GET /metrics HTTP/1.1"
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Current metrics in the Prometheus text exposition format
+fn render() -> String {
+    let client = crate::ARTI_CLIENT.lock_or_recover().clone();
+    let MetricsSnapshot {
+        uptime_seconds,
+        bootstrap,
+        consensus,
+        usable_relays,
+        circuits,
+        streams,
+        ..
+    } = metrics::snapshot(client.as_deref());
+
+    let mut out = String::new();
+    if let Some(uptime) = uptime_seconds {
+        gauge(&mut out, "arti_uptime_seconds", "Seconds since the Tor client was created.", uptime);
+    }
+    if let Some(bootstrap) = bootstrap {
+        gauge(&mut out, "arti_bootstrap_percent", "Bootstrap progress in percent.", bootstrap.percent);
+        gauge(
+            &mut out,
+            "arti_ready_for_traffic",
+            "Whether the client is bootstrapped enough to carry traffic.",
+            u8::from(bootstrap.ready_for_traffic),
+        );
+        gauge(
+            &mut out,
+            "arti_bootstrap_blocked",
+            "Whether bootstrapping appears to be blocked.",
+            u8::from(bootstrap.blocked.is_some()),
+        );
+    }
+    if let Some(consensus) = consensus {
+        gauge(
+            &mut out,
+            "arti_consensus_valid_until_seconds",
+            "Unix time at which the current consensus expires.",
+            consensus.valid_until_ms / 1000,
+        );
+    }
+    if let Some(relays) = usable_relays {
+        gauge(&mut out, "arti_usable_relays", "Relays in the consensus with a usable descriptor.", relays);
+    }
+
+    gauge(&mut out, "arti_circuits_open", "Circuits currently open.", circuits.open);
+    header(&mut out, "arti_circuit_builds_total", "Circuit builds by result.", "counter");
+    let _ = writeln!(out, "arti_circuit_builds_total{{result=\"success\"}} {}", circuits.build_successes);
+    let _ = writeln!(out, "arti_circuit_builds_total{{result=\"failure\"}} {}", circuits.build_failures);
+
+    gauge(&mut out, "arti_streams_active", "Proxied streams currently open.", streams.active);
+    header(&mut out, "arti_stream_bytes_total", "Bytes proxied over streams by direction.", "counter");
+    let _ = writeln!(out, "arti_stream_bytes_total{{direction=\"up\"}} {}", streams.bytes_up);
+    let _ = writeln!(out, "arti_stream_bytes_total{{direction=\"down\"}} {}", streams.bytes_down);
+
+    // Nicknames are restricted to letters, digits, '-' and '_', so they need
+    // no escaping as label values
+    let services = crate::onion::snapshot();
+    if !services.is_empty() {
+        header(&mut out, "arti_onion_service_reachable", "Whether the onion service is fully reachable.", "gauge");
+        for service in &services {
+            let _ = writeln!(
+                out,
+                "arti_onion_service_reachable{{nickname=\"{}\"}} {}",
+                service.nickname,
+                u8::from(service.health.reachable)
+            );
+        }
+        header(&mut out, "arti_onion_service_state", "Current state of the onion service.", "gauge");
+        for service in &services {
+            let _ = writeln!(
+                out,
+                "arti_onion_service_state{{nickname=\"{}\",state=\"{}\"}} 1",
+                service.nickname, service.health.state
+            );
+        }
+        header(
+            &mut out,
+            "arti_onion_service_problem_failures",
+            "Failures behind the onion service's current problem, by kind.",
+            "gauge",
+        );
+        for service in &services {
+            let _ = writeln!(
+                out,
+                "arti_onion_service_problem_failures{{nickname=\"{}\",kind=\"descriptor_upload\"}} {}",
+                service.nickname, service.health.descriptor_upload_failures
+            );
+            let _ = writeln!(
+                out,
+                "arti_onion_service_problem_failures{{nickname=\"{}\",kind=\"intro_point\"}} {}",
+                service.nickname, service.health.intro_point_failures
+            );
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{} {}", name, value);
+}
@@ -0,0 +1,102 @@
+//! Per-stream lifecycle events for a debug console
+//!
+//! Opt-in: once enabled, every SOCKS, fd, WebSocket and HTTP stream reports
+//! `stream_opened` when it is requested, then either `stream_failed` (with
+//! arti's error kind) or `stream_connected` (with the id of its circuit, as in
+//! `arti_get_circuits`) and finally `stream_closed` with its byte counts.
+//! Every opened stream ends with exactly one `stream_failed` or
+//! `stream_closed`. Whether a stream reports is decided when it is opened, so
+//! toggling events never splits a lifecycle. Stream ids are those of the
+//! traffic snapshot, and destinations and error messages are redacted as in
+//! log lines while safe logging is on.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use arti_client::{DataStream, HasKind};
+
+use crate::events::{self, Event};
+use crate::traffic::{self, StreamCounters, StreamHandle};
+
+/// Whether stream events are reported
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable stream events
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Lifecycle of one stream; reports how it ended when dropped
+pub struct StreamLifecycle {
+    id: u64,
+    /// Events were enabled when the stream was opened
+    enabled: bool,
+    started: Instant,
+    /// Set once connected, for the byte counts reported on close
+    counters: Option<Arc<StreamCounters>>,
+    failed: bool,
+}
+
+/// A stream to `host:port` was requested
+pub fn opened(host: &str, port: u16) -> StreamLifecycle {
+    let id = traffic::new_stream_id();
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    if enabled {
+        let destination = crate::redact::destination(host, port).to_string();
+        events::emit(&Event::StreamOpened { stream_id: id, destination: &destination });
+    }
+    StreamLifecycle { id, enabled, started: Instant::now(), counters: None, failed: false }
+}
+
+impl StreamLifecycle {
+    /// Stream id, shared with the traffic snapshot
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The stream is connected and registered for traffic accounting
+    pub fn connected(&mut self, stream: &DataStream, traffic_stream: &StreamHandle) {
+        self.counters = Some(traffic_stream.counters());
+        if self.enabled {
+            let circuit_id = crate::circuits::circuit_id(stream);
+            events::emit(&Event::StreamConnected { stream_id: self.id, circuit_id: circuit_id.as_deref() });
+        }
+    }
+
+    /// arti could not open the stream
+    pub fn failed(&mut self, error: &arti_client::Error) {
+        self.failed = true;
+        if self.enabled {
+            let error_kind = format!("{:?}", error.kind());
+            // arti's messages often name the target
+            self.emit_failed(&error_kind, &safelog::sensitive(error).to_string());
+        }
+    }
+
+    fn emit_failed(&self, error_kind: &str, error: &str) {
+        events::emit(&Event::StreamFailed { stream_id: self.id, error_kind, error });
+    }
+}
+
+impl Drop for StreamLifecycle {
+    fn drop(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        match &self.counters {
+            Some(counters) => {
+                let (bytes_up, bytes_down) = counters.bytes();
+                events::emit(&Event::StreamClosed {
+                    stream_id: self.id,
+                    bytes_up,
+                    bytes_down,
+                    duration_ms: self.started.elapsed().as_millis() as u64,
+                });
+            }
+            // Given up before connecting, e.g. the local client went away
+            None if !self.failed => self.emit_failed("Abandoned", "stream abandoned before it connected"),
+            None => {}
+        }
+    }
+}
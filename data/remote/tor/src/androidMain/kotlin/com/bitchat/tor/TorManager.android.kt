@@ -48,9 +48,9 @@ actual class TorManager actual constructor(
     init {
         if (libraryLoaded) {
             try {
-                nativeSetLogCallback(object : LogCallback {
-                    override fun onLogLine(message: String?) {
-                        message?.let { handleLogLine(it) }
+                nativeSetLogCallbackV2(object : LogCallbackV2 {
+                    override fun onLog(level: Int, timestampMs: Long, target: String?, message: String?) {
+                        message?.let { handleLogLine(level, it) }
                     }
                 })
             } catch (e: Throwable) {
@@ -162,7 +162,7 @@ actual class TorManager actual constructor(
 
     }
 
-    private fun handleLogLine(line: String) {
+    private fun handleLogLine(level: Int, line: String) {
         Log.d(TAG, "Arti: $line")
 
         _statusFlow.update { it.copy(lastLogLine = line) }
@@ -209,7 +209,7 @@ actual class TorManager actual constructor(
                 }
             }
 
-            line.contains("ERROR", ignoreCase = true) -> {
+            level >= LOG_LEVEL_ERROR -> {
                 _statusFlow.update {
                     it.copy(
                         state = TorState.ERROR,
@@ -258,10 +258,15 @@ actual class TorManager actual constructor(
         fun onLogLine(message: String?)
     }
 
+    interface LogCallbackV2 {
+        fun onLog(level: Int, timestampMs: Long, target: String?, message: String?)
+    }
+
     companion object {
         private const val TAG = "TorManager"
         private const val DEFAULT_SOCKS_PORT = 9050
         private const val MAX_RETRY_COUNT = 5
+        private const val LOG_LEVEL_ERROR = 4
 
         @Volatile
         private var libraryLoaded = false
@@ -284,6 +289,9 @@ actual class TorManager actual constructor(
         @JvmStatic
         private external fun nativeSetLogCallback(callback: LogCallback)
 
+        @JvmStatic
+        private external fun nativeSetLogCallbackV2(callback: LogCallbackV2?)
+
         @JvmStatic
         private external fun nativeInitialize(dataDir: String): Int
 
@@ -35,12 +35,15 @@ actual class TorManager actual constructor(
         File(dataDir).mkdirs()
 
         try {
-            nativeSetLogCallback(object : LogCallback {
-                override fun onLogLine(message: String?) {
-                    message?.let { handleLogLine(it) }
+            nativeSetLogCallbackV2(object : LogCallbackV2 {
+                override fun onLog(level: Int, timestampMs: Long, target: String?, message: String?) {
+                    message?.let { handleLogLine(level, it) }
                 }
             })
-        } catch (e: Exception) {
+        } catch (e: UnsatisfiedLinkError) {
+            // Native library without the leveled callback: fall back to flat lines
+            setFlatLogCallback()
+        } catch (e: Throwable) {
             System.err.println("$TAG: Failed to set log callback: ${e.message}")
         }
     }
@@ -66,7 +69,7 @@ actual class TorManager actual constructor(
             println("$TAG: Initializing Arti...")
             val result = try {
                 nativeInitialize(dataDir)
-            } catch (e: Exception) {
+            } catch (e: Throwable) {
                 System.err.println("$TAG: Failed to initialize: ${e.message}")
                 _statusFlow.update { it.copy(state = TorState.ERROR, errorMessage = e.message) }
                 return
@@ -86,7 +89,7 @@ actual class TorManager actual constructor(
 
         val result = try {
             nativeStartSocksProxy(currentPort)
-        } catch (e: Exception) {
+        } catch (e: Throwable) {
             System.err.println("$TAG: Failed to start proxy: ${e.message}")
             _statusFlow.update { it.copy(state = TorState.ERROR, errorMessage = e.message) }
             return
@@ -104,7 +107,7 @@ actual class TorManager actual constructor(
 
         try {
             nativeStop()
-        } catch (e: Exception) {
+        } catch (e: Throwable) {
             System.err.println("$TAG: Failed to stop: ${e.message}")
         }
 
@@ -122,7 +125,22 @@ actual class TorManager actual constructor(
 
     }
 
-    private fun handleLogLine(line: String) {
+    private fun setFlatLogCallback() {
+        try {
+            nativeSetLogCallback(object : LogCallback {
+                override fun onLogLine(message: String?) {
+                    message?.let {
+                        val level = if (it.startsWith("ERROR: ")) LOG_LEVEL_ERROR else LOG_LEVEL_INFO
+                        handleLogLine(level, it)
+                    }
+                }
+            })
+        } catch (e: Throwable) {
+            System.err.println("$TAG: Failed to set log callback: ${e.message}")
+        }
+    }
+
+    private fun handleLogLine(level: Int, line: String) {
         println("$TAG: Arti: $line")
 
         _statusFlow.update { it.copy(lastLogLine = line) }
@@ -166,7 +184,7 @@ actual class TorManager actual constructor(
                 }
             }
 
-            line.contains("ERROR", ignoreCase = true) -> {
+            level >= LOG_LEVEL_ERROR -> {
                 _statusFlow.update { it.copy(state = TorState.ERROR, errorMessage = line) }
             }
         }
@@ -176,9 +194,15 @@ actual class TorManager actual constructor(
         fun onLogLine(message: String?)
     }
 
+    interface LogCallbackV2 {
+        fun onLog(level: Int, timestampMs: Long, target: String?, message: String?)
+    }
+
     companion object {
         private const val TAG = "TorManager"
         private const val DEFAULT_SOCKS_PORT = 9050
+        private const val LOG_LEVEL_INFO = 2
+        private const val LOG_LEVEL_ERROR = 4
 
         init {
             try {
@@ -204,6 +228,9 @@ actual class TorManager actual constructor(
         @JvmStatic
         private external fun nativeSetLogCallback(callback: LogCallback)
 
+        @JvmStatic
+        private external fun nativeSetLogCallbackV2(callback: LogCallbackV2?)
+
         @JvmStatic
         private external fun nativeInitialize(dataDir: String): Int
 
@@ -4,11 +4,13 @@ import com.bitchat.domain.tor.model.TorMode
 import com.bitchat.domain.tor.model.TorState
 import com.bitchat.domain.tor.model.TorStatus
 import com.bitchat.tor.native.arti_initialize
-import com.bitchat.tor.native.arti_set_log_callback
+import com.bitchat.tor.native.ARTI_LOG_ERROR
+import com.bitchat.tor.native.arti_set_log_callback_v2
 import com.bitchat.tor.native.arti_start_socks_proxy
 import com.bitchat.tor.native.arti_stop
 import kotlinx.cinterop.ByteVar
 import kotlinx.cinterop.CFunction
+import kotlinx.cinterop.COpaquePointer
 import kotlinx.cinterop.CPointer
 import kotlinx.cinterop.ExperimentalForeignApi
 import kotlinx.cinterop.reinterpret
@@ -58,7 +60,7 @@ actual class TorManager actual constructor(
         currentInstance = this
 
         try {
-            arti_set_log_callback(logCallback)
+            arti_set_log_callback_v2(logCallback, null)
             println("$TAG: Log callback set")
         } catch (e: Exception) {
             println("$TAG: Failed to set log callback: ${e.message}")
@@ -126,7 +128,7 @@ actual class TorManager actual constructor(
         currentInstance = null
     }
 
-    private fun handleLogLine(line: String) {
+    private fun handleLogLine(level: Int, line: String) {
         println("$TAG: Arti: $line")
 
         _statusFlow.update { it.copy(lastLogLine = line) }
@@ -176,7 +178,7 @@ actual class TorManager actual constructor(
                 }
             }
 
-            line.contains("ERROR", ignoreCase = true) -> {
+            level >= ARTI_LOG_ERROR -> {
                 println("$TAG: Tor ERROR: $line")
                 _statusFlow.update { it.copy(state = TorState.ERROR, errorMessage = line) }
             }
@@ -187,11 +189,11 @@ actual class TorManager actual constructor(
         private const val TAG = "TorManager"
         private const val DEFAULT_SOCKS_PORT = 9050
 
-        private val logCallback: CPointer<CFunction<(CPointer<ByteVar>?) -> Unit>> =
-            staticCFunction { messagePtr: CPointer<ByteVar>? ->
+        private val logCallback: CPointer<CFunction<(Int, Long, CPointer<ByteVar>?, CPointer<ByteVar>?, COpaquePointer?) -> Unit>> =
+            staticCFunction { level: Int, _: Long, _: CPointer<ByteVar>?, messagePtr: CPointer<ByteVar>?, _: COpaquePointer? ->
                 if (messagePtr != null) {
                     val message = messagePtr.toKString()
-                    currentInstance?.handleLogLine(message)
+                    currentInstance?.handleLogLine(level, message)
                 }
             }.reinterpret()
 